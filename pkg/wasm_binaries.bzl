@@ -19,13 +19,43 @@ def _wasm_binaries_impl(ctx):
         inputs.append(src)
 
     outputs = []
+    binaries = []
     for file in inputs:
+        if ctx.attr.manifest and file.basename.endswith(".wasm"):
+            binaries.append(file)
+            continue
         output = ctx.actions.declare_file(
             "{}/{}".format(ctx.attr.name, file.basename),
         )
         ctx.actions.symlink(output = output, target_file = file)
         outputs.append(output)
 
+    if not ctx.attr.manifest:
+        return DefaultInfo(files = depset(outputs))
+
+    # Wasm binaries are content-addressed: each is named after its SHA-256 hash, which the
+    # manifest maps the program name to.
+    manifest = ctx.actions.declare_file("{}/manifest.txt".format(ctx.attr.name))
+    wasm_dir = ctx.actions.declare_directory(ctx.attr.wasm_dir)
+    ctx.actions.run_shell(
+        inputs = binaries,
+        outputs = [manifest, wasm_dir],
+        arguments = [manifest.path, wasm_dir.path] + [file.path for file in binaries],
+        command = """
+            manifest="$1" wasm_dir="$2"
+            shift 2
+            : > "$manifest"
+            for file in "$@"; do
+                cid="$(sha256sum "$file" | cut -d " " -f 1)"
+                cp "$file" "$wasm_dir/$cid.wasm"
+                echo "$cid  $(basename "$file" .wasm)" >> "$manifest"
+            done
+        """,
+        mnemonic = "WasmManifest",
+        progress_message = "Hashing Wasm binaries of %{label}",
+    )
+    outputs += [manifest, wasm_dir]
+
     return DefaultInfo(files = depset(outputs))
 
 wasm_binaries = rule(
@@ -35,6 +65,15 @@ wasm_binaries = rule(
             doc = "Sources that generate JS + Wasm binary outputs.",
             cfg = wasm_transition,
         ),
+        "manifest": attr.bool(
+            doc = "Whether to lay out the Wasm binaries by content ID along with a manifest, " +
+                  "rather than forward them, e.g. to another wasm_binaries rule. Only the rule " +
+                  "serving the binaries should set it.",
+        ),
+        "wasm_dir": attr.string(
+            doc = "Directory the Wasm binaries are laid out in, named after their content ID.",
+            default = "usr/wasm",
+        ),
         "_allowlist_function_transition": attr.label(
             default = "@bazel_tools//tools/allowlists/function_transition_allowlist",
         ),
@@ -1,41 +1,70 @@
 use std::{
     cell::RefCell,
-    collections::{
-        hash_map::Entry::{Occupied, Vacant},
-        HashMap, VecDeque,
-    },
+    collections::{BTreeMap, VecDeque},
     rc::Rc,
 };
 
 use js_sys::{Error, Promise, Uint8Array};
+use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
 
-use crate::js;
+use crate::{errno::Errno, js};
 
-pub const STDIN: u32 = 0;
-pub const STDOUT: u32 = 1;
-pub const STDERR: u32 = 2;
-pub const OPEN_FDS: [u32; 3] = [STDIN, STDOUT, STDERR];
+/// Writes of up to this many bytes are atomic, see pipe(7).
+pub const PIPE_BUF: usize = 4096;
 
-/// Asynchronous, Promise-backed I/O with an infinite buffer.
-///
-/// Supports one channel per file descriptor, with multiple producers and a single consumer. As
-/// soon as one producer closes the channel, no more data can be sent. Once the buffer is drained,
-/// the channel is removed and can be re-opened.
-pub struct AsyncIo {
-    // Internal I/O buffers, keyed by file descriptors.
-    buffers: RefCell<HashMap<u32, Rc<AsyncBuffer>>>,
+/// Default capacity of a channel, as with Linux pipes.
+pub const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Maximum capacity of a channel, see /proc/sys/fs/pipe-max-size.
+pub const PIPE_MAX_SIZE: usize = 1024 * 1024;
+
+/// Which end of a channel a file descriptor refers to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
 }
 
-struct AsyncBuffer {
+/// Asynchronous, Promise-backed I/O channel with a bounded buffer.
+///
+/// Supports multiple producers and a single consumer. Writers wait for space to become available,
+/// see writable(). Open file descriptions refer to one end (or both ends) of a channel, see
+/// acquire() and release(). A channel is closed once every reference to its write end is gone,
+/// after which no more data can be sent, and the reader gets EOF once the buffer is drained. A
+/// channel is broken once every reference to its read end is gone.
+///
+/// The consumer can also stop the channel to apply backpressure, in which case writers wait until
+/// it is started again.
+pub struct AsyncBuffer {
     // Internal I/O buffer.
     buffer: RefCell<VecDeque<Vec<u8>>>,
+    // Number of bytes in the buffer, and how many it can hold.
+    len: RefCell<usize>,
+    capacity: RefCell<usize>,
     // Target C-style buffer (pointer + length).
     target: RefCell<Option<HeapView>>,
     // Deferred object, waiting for data to become available.
     deferred: RefCell<Option<js::Deferred>>,
-    // Whether the file descriptor is open.
+    // Whether the write end is closed.
     closed: RefCell<bool>,
+    // Number of open file descriptions referring to each end.
+    readers: RefCell<usize>,
+    writers: RefCell<usize>,
+    // Whether writers have to wait before writing.
+    stopped: RefCell<bool>,
+    // Deferred objects of writers waiting for the channel to be started.
+    blocked: RefCell<Vec<js::Deferred>>,
+    // Deferred objects waiting for any change in readiness, by watch id, see changed().
+    watchers: RefCell<BTreeMap<u64, js::Deferred>>,
+    next_watch: RefCell<u64>,
+}
+
+/// Registration of a promise returned by AsyncBuffer::changed(), removed once dropped.
+pub struct Watch {
+    buf: Rc<AsyncBuffer>,
+    id: u64,
 }
 
 struct HeapView {
@@ -44,120 +73,54 @@ struct HeapView {
     length: u32,
 }
 
-impl AsyncIo {
-    pub fn new() -> Result<Self, Error> {
-        let io = Self {
-            buffers: RefCell::new(HashMap::new()),
-        };
-        for fd in OPEN_FDS {
-            io.open(fd)?;
-        }
-        Ok(io)
-    }
-
-    /// Opens a file descriptor.
-    ///
-    /// Returns an error if the file descriptor is already open.
-    pub fn open(&self, fd: u32) -> Result<(), Error> {
-        match self.buffers.borrow_mut().entry(fd) {
-            Occupied(_) => Err(Error::new(&format!("io: fd {}: open: already open", fd))),
-            Vacant(v) => {
-                v.insert(AsyncBuffer::new().into());
-                Ok(())
-            }
-        }
-    }
-
-    /// Read from the file descriptor.
-    ///
-    /// If there is data in the buffer, it will be returned immediately. Otherwise, if the producer
-    /// side is still connected, this function will block until either data becomes available or
-    /// the producer side disconnects. If the producer is disconnected and the queue is drained,
-    /// no data is returned (but the promise resolves immediately).
-    pub fn read_promise(
-        &self,
-        fd: u32,
-        module: &Rc<RefCell<Option<js::Module>>>,
-        offset: u32,
-        length: u32,
-    ) -> Result<Promise, Error> {
-        self.buffers
-            .borrow()
-            .get(&fd)
-            .ok_or(Error::new(&format!("io: fd {}: consume_all: not open", fd)))?
-            .read_promise(module, offset, length)
-    }
-
-    /// Consume all data from the file descriptor.
-    ///
-    /// This can be more efficient as the data is returned in chunks and no copy needs to be done.
-    pub async fn consume_all(&self, fd: u32) -> Result<Option<Vec<Vec<u8>>>, Error> {
-        {
-            let buf = self
-                .buffers
-                .borrow()
-                .get(&fd)
-                .ok_or(Error::new(&format!("io: fd {}: consume_all: not open", fd)))?
-                .clone();
-            // Releasing the temporary .borrow() here.
-            buf
-        }
-        .consume_all()
-        .await
-    }
-
-    /// Writes data to the file descriptor.
-    ///
-    /// Returns the number of bytes written, or an error.
-    ///
-    /// If there is a consumer connected, some data might be delivered immediately, otherwise, the
-    /// data is buffered.
-    pub fn write(&self, fd: u32, data: Vec<u8>) -> Result<usize, Error> {
-        self.buffers
-            .borrow()
-            .get(&fd)
-            .ok_or(Error::new(&format!("io: fd {}: write: not open", fd)))?
-            .write(data)
+impl Access {
+    pub fn reads(self) -> bool {
+        self != Access::Write
     }
 
-    /// Closes the file descriptor, indicating that no more data can be sent.
-    pub fn close(&self, fd: u32) -> Result<(), Error> {
-        match self.buffers.borrow_mut().remove(&fd) {
-            Some(buf) => Ok(buf.close()),
-            None => Err(Error::new(&format!("io: fd {}: close: not open", fd))),
-        }
-    }
-
-    /// Closes all file descriptors.
-    pub fn close_all(&self) -> Result<(), Error> {
-        let mut buffers = self.buffers.borrow_mut();
-        for buf in buffers.values() {
-            buf.close();
-        }
-        buffers.clear();
-
-        Ok(())
+    pub fn writes(self) -> bool {
+        self != Access::Read
     }
 }
 
 impl AsyncBuffer {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        Self::with_capacity(PIPE_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
             buffer: RefCell::new(VecDeque::new()),
+            len: RefCell::new(0),
+            capacity: RefCell::new(capacity),
             target: RefCell::new(None),
             deferred: RefCell::new(None),
             closed: RefCell::new(false),
+            readers: RefCell::new(0),
+            writers: RefCell::new(0),
+            stopped: RefCell::new(false),
+            blocked: RefCell::new(Vec::new()),
+            watchers: RefCell::new(BTreeMap::new()),
+            next_watch: RefCell::new(0),
         }
     }
 
-    fn read_promise(
+    /// Reads into a buffer on the heap of a module.
+    ///
+    /// If there is data in the buffer, it will be copied immediately. Otherwise, if the producer
+    /// side is still connected, the promise resolves once data becomes available or the producer
+    /// side disconnects. If the producer is disconnected and the buffer is drained, the promise
+    /// resolves to zero bytes right away.
+    ///
+    /// Fails with EBUSY if another read is already pending.
+    pub fn read_promise(
         &self,
         module: &Rc<RefCell<Option<js::Module>>>,
         offset: u32,
         length: u32,
-    ) -> Result<Promise, Error> {
+    ) -> Result<Promise, Errno> {
         if self.deferred.borrow().is_some() {
-            return Err(Error::new("io: read_promise: channel busy"));
+            return Err(Errno::EBUSY);
         }
 
         // Indicate where to copy the data.
@@ -169,7 +132,7 @@ impl AsyncBuffer {
 
         if !self.buffer.borrow().is_empty() {
             // Synchronous path: there is already data in the buffer.
-            let def = js::deferred()?;
+            let def = js::deferred().map_err(|_| Errno::EIO)?;
             let promise = def.promise();
             self.deferred.replace(Some(def));
             self.signal_write();
@@ -182,7 +145,7 @@ impl AsyncBuffer {
             return Ok(Promise::resolve(&0.into()));
         }
 
-        let def = js::deferred()?;
+        let def = js::deferred().map_err(|_| Errno::EIO)?;
         let promise = def.promise();
         self.deferred.replace(Some(def));
 
@@ -190,6 +153,14 @@ impl AsyncBuffer {
         Ok(promise)
     }
 
+    /// Abandons a pending read_promise(), e.g. when the read is interrupted by a signal.
+    ///
+    /// Any data that arrives later stays in the buffer for the next read.
+    pub fn cancel_read(&self) {
+        self.target.replace(None);
+        self.deferred.replace(None);
+    }
+
     /// Consume all data from the buffer.
     ///
     /// If there is data in the buffer, it will be returned immediately. Otherwise, if the producer
@@ -199,14 +170,16 @@ impl AsyncBuffer {
     ///
     /// This function may be more efficient than read() as the data is returned in chunks and no
     /// copy needs to be done.
-    async fn consume_all(&self) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    ///
+    /// Fails with EBUSY if a read is already pending.
+    pub async fn consume_all(&self) -> Result<Option<Vec<Vec<u8>>>, Errno> {
         if self.deferred.borrow().is_some() {
-            return Err(Error::new("io: consume_all: channel busy"));
+            return Err(Errno::EBUSY);
         }
 
         if !self.buffer.borrow().is_empty() {
             // Fast path: there is already data in the buffer.
-            return Ok(Some(self.buffer.borrow_mut().drain(..).collect()));
+            return Ok(Some(self.drain()));
         }
 
         if *self.closed.borrow() {
@@ -215,38 +188,123 @@ impl AsyncBuffer {
             return Ok(None);
         }
 
-        self.deferred.replace(Some(js::deferred()?));
+        self.deferred
+            .replace(Some(js::deferred().map_err(|_| Errno::EIO)?));
 
         // Wait for data. We have to make sure nothing is currently borrowed.
-        self.wait_read().await?;
+        self.wait_read().await.map_err(|_| Errno::EIO)?;
 
         Ok(if !self.buffer.borrow().is_empty() {
-            Some(self.buffer.borrow_mut().drain(..).collect())
+            Some(self.drain())
         } else {
             // The promise resolved but the buffer is empty: channel must be closed.
             None
         })
     }
 
-    /// Writes data to the buffer.
+    /// Writes as much data to the buffer as it has space for.
     ///
-    /// Returns the number of bytes written, or an error.
+    /// Returns the number of bytes written, or EPIPE if either end is closed.
     ///
     /// If there is a consumer connected, some data might be delivered immediately, otherwise, the
     /// data is buffered.
-    fn write(&self, data: Vec<u8>) -> Result<usize, Error> {
-        if *self.closed.borrow() {
-            return Err(Error::new("io: write: stream closed"));
+    pub fn write(&self, mut data: Vec<u8>) -> Result<usize, Errno> {
+        if *self.closed.borrow() || self.is_broken() {
+            return Err(Errno::EPIPE);
         }
 
+        data.truncate(self.space());
         let count = data.len();
-        // TODO: This borrow fails, someone is holding on to it?
-        self.buffer.borrow_mut().push_back(data);
-        self.signal_write();
+        if count > 0 {
+            self.len.replace_with(|len| *len + count);
+            // TODO: This borrow fails, someone is holding on to it?
+            self.buffer.borrow_mut().push_back(data);
+            self.signal_write();
+        }
 
         Ok(count)
     }
 
+    /// Number of bytes that can be written without blocking.
+    pub fn space(&self) -> usize {
+        self.capacity().saturating_sub(*self.len.borrow())
+    }
+
+    pub fn capacity(&self) -> usize {
+        *self.capacity.borrow()
+    }
+
+    /// Changes the capacity, unless there is more data in the buffer than it would hold.
+    pub fn set_capacity(&self, capacity: usize) -> bool {
+        if *self.len.borrow() > capacity {
+            return false;
+        }
+        self.capacity.replace(capacity);
+        self.unblock_writers();
+        true
+    }
+
+    /// Returns a promise that resolves once count bytes may be written, or None if they may be
+    /// written right away.
+    ///
+    /// Up to PIPE_BUF bytes are written atomically, so this waits until there is space for all of
+    /// them. Larger writes may be split, so this only waits until there is space for some.
+    ///
+    /// Writers should check again once the promise resolves, as the channel may have filled up,
+    /// been stopped again, or broken in the meantime.
+    pub fn writable(&self, count: usize) -> Result<Option<Promise>, Error> {
+        if !self.would_block(count) {
+            return Ok(None);
+        }
+        let def = js::deferred()?;
+        let promise = def.promise();
+        self.blocked.borrow_mut().push(def);
+        Ok(Some(promise))
+    }
+
+    /// Whether a write of count bytes would have to wait, see writable().
+    pub fn would_block(&self, count: usize) -> bool {
+        let needed = if count <= PIPE_BUF { count } else { 1 };
+        (*self.stopped.borrow() || self.space() < needed) && !self.is_broken()
+    }
+
+    /// Whether a read would return right away, with data or EOF.
+    pub fn readable(&self) -> bool {
+        self.has_data() || self.is_closed()
+    }
+
+    pub fn has_data(&self) -> bool {
+        !self.buffer.borrow().is_empty()
+    }
+
+    /// Whether the write end is closed.
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Returns a promise that resolves the next time data is written or consumed, or either end
+    /// is closed, for poll() and friends to check readiness again.
+    ///
+    /// The promise is only resolved as long as the watch returned along with it is kept.
+    pub fn changed(self: Rc<Self>) -> Result<(Promise, Watch), Error> {
+        let def = js::deferred()?;
+        let promise = def.promise();
+        let id = self.next_watch.replace_with(|id| *id + 1);
+        self.watchers.borrow_mut().insert(id, def);
+        Ok((promise, Watch { buf: self, id }))
+    }
+
+    /// Makes writers wait, see writable().
+    pub fn stop(&self) {
+        self.stopped.replace(true);
+    }
+
+    /// Lets writers continue.
+    pub fn start(&self) {
+        self.stopped.replace(false);
+        self.unblock_writers();
+    }
+
     /// Blocks until someone calls signal_write().
     async fn wait_read(&self) -> Result<(), Error> {
         if let Some(promise) = {
@@ -266,6 +324,15 @@ impl AsyncBuffer {
         if let Some(def) = self.deferred.replace(None) {
             def.resolve(&self.copy_to_target().into());
         }
+        self.notify();
+    }
+
+    /// Takes all data out of the buffer.
+    fn drain(&self) -> Vec<Vec<u8>> {
+        let chunks = self.buffer.borrow_mut().drain(..).collect();
+        self.len.replace(0);
+        self.unblock_writers();
+        chunks
     }
 
     /// Copies bytes to the target buffer if there is one.
@@ -302,13 +369,66 @@ impl AsyncBuffer {
             }
         }
 
+        if copied > 0 {
+            self.len.replace_with(|len| len.saturating_sub(copied));
+            self.unblock_writers();
+        }
         copied
     }
 
+    /// Whether writing would fail because there are no readers left.
+    pub fn is_broken(&self) -> bool {
+        *self.readers.borrow() == 0
+    }
+
+    /// Adds a reference to one or both ends of the channel.
+    pub fn acquire(&self, access: Access) {
+        if access.reads() {
+            self.readers.replace_with(|n| *n + 1);
+        }
+        if access.writes() {
+            self.writers.replace_with(|n| *n + 1);
+        }
+    }
+
+    /// Drops a reference to one or both ends of the channel, closing it when the last writer is
+    /// gone.
+    pub fn release(&self, access: Access) {
+        if access.reads() && self.readers.replace_with(|n| n.saturating_sub(1)) <= 1 {
+            // Let blocked writers find out that the channel is broken.
+            self.unblock_writers();
+        }
+        if access.writes() && self.writers.replace_with(|n| n.saturating_sub(1)) <= 1 {
+            self.close();
+        }
+    }
+
     fn close(&self) {
         self.closed.replace(true);
         self.signal_write();
     }
+
+    fn unblock_writers(&self) {
+        let blocked = self.blocked.take();
+        for def in blocked {
+            def.resolve(&JsValue::undefined());
+        }
+        self.notify();
+    }
+
+    /// Resolves the promises returned by changed().
+    fn notify(&self) {
+        let watchers = self.watchers.take();
+        for def in watchers.into_values() {
+            def.resolve(&JsValue::undefined());
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.buf.watchers.borrow_mut().remove(&self.id);
+    }
 }
 
 // TODO: Move to js::Module!
@@ -323,3 +443,37 @@ impl HeapView {
             .subarray(self.offset, self.offset + self.length)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        let buf = AsyncBuffer::with_capacity(PIPE_BUF);
+        buf.acquire(Access::ReadWrite);
+
+        assert_eq!(buf.write(vec![0; 3000]).unwrap(), 3000);
+        assert_eq!(buf.space(), PIPE_BUF - 3000);
+        assert!(buf.readable());
+        assert!(!buf.would_block(PIPE_BUF - 3000));
+        assert!(buf.would_block(PIPE_BUF)); // atomic
+        assert!(!buf.would_block(PIPE_BUF + 1)); // may be split
+
+        // Only as much as fits is written.
+        assert_eq!(buf.write(vec![0; 3000]).unwrap(), PIPE_BUF - 3000);
+        assert_eq!(buf.space(), 0);
+        assert!(buf.would_block(PIPE_BUF + 1));
+
+        assert!(!buf.set_capacity(PIPE_BUF / 2));
+        assert!(buf.set_capacity(PIPE_CAPACITY));
+        assert_eq!(buf.space(), PIPE_CAPACITY - PIPE_BUF);
+    }
+
+    #[test]
+    fn test_broken() {
+        let buf = AsyncBuffer::new();
+        buf.acquire(Access::Write);
+        assert_eq!(buf.write(vec![0; 8]), Err(Errno::EPIPE));
+    }
+}
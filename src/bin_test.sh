@@ -0,0 +1,41 @@
+#!/bin/bash
+#
+# Checks that the programs served under /bin are listed in the manifest, each
+# with its Wasm binary named after its content ID.
+#
+# Usage: bin_test.sh <files of //src:bin>...
+
+set -euo pipefail
+
+manifest="" wasm_dir=""
+for file in "$@"; do
+  case "$file" in
+    */manifest.txt) manifest="$file" ;;
+    */usr/wasm) wasm_dir="$file" ;;
+  esac
+done
+if [[ -z "$manifest" || -z "$wasm_dir" ]]; then
+  echo "missing manifest or Wasm directory in: $*" >&2
+  exit 1
+fi
+
+for program in busybox; do
+  cid="$(awk -v name="$program" '$2 == name { print $1 }' "$manifest")"
+  if [[ ! "$cid" =~ ^[0-9a-f]{64}$ ]]; then
+    echo "$program is not listed in $manifest:" >&2
+    cat "$manifest" >&2
+    exit 1
+  fi
+  if [[ ! -f "$wasm_dir/$cid.wasm" ]]; then
+    echo "missing $wasm_dir/$cid.wasm" >&2
+    exit 1
+  fi
+  if [[ "$(sha256sum "$wasm_dir/$cid.wasm" | cut -d " " -f 1)" != "$cid" ]]; then
+    echo "$wasm_dir/$cid.wasm does not match its content ID" >&2
+    exit 1
+  fi
+  if [[ ! -e "$(dirname "$manifest")/$program.js" ]]; then
+    echo "missing $program.js next to $manifest" >&2
+    exit 1
+  fi
+done
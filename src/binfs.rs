@@ -1,55 +1,152 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{
+    errno::Errno::{self, EINVAL, ENOENT},
+    vfs::{DirEntry, FileSystem, Node, Program, StatFs, Synthetic, S_IFREG},
+};
+
+/// Magic number reported by statfs(), "bin" in ASCII.
+const BINFS_MAGIC: u32 = 0x62696e;
+
+/// Name of the manifest listing the programs, next to their JS modules.
+pub const MANIFEST: &str = "manifest.txt";
 
 /// Binary FS.
 ///
-/// This filesystem is "mounted" read-only under /bin/. All files are owned by the root user/group.
+/// This filesystem is mounted read-only, normally at /bin. Each file is a program listed in the
+/// manifest, implemented by the JS module at the same path under url_base, which instantiates
+/// the Wasm binary named after its content ID under wasm_url. All files are owned by the root
+/// user/group.
 pub struct BinFs {
-    mount_point: PathBuf,
+    url_base: String,
+    wasm_url: String,
+    // Content IDs by program name.
+    programs: BTreeMap<String, String>,
 }
 
 impl BinFs {
-    pub fn new(mount_point: &str) -> Self {
-        Self {
-            mount_point: PathBuf::from(mount_point),
+    /// Creates the filesystem from a manifest, which has a line per program: the content ID of
+    /// its Wasm binary, i.e. its SHA-256 hash in hex, then its name, as output by sha256sum(1).
+    ///
+    /// Fails with EINVAL if a line is malformed.
+    pub fn new(url_base: &str, wasm_url: &str, manifest: &str) -> Result<Self, Errno> {
+        let mut programs = BTreeMap::new();
+        for line in manifest.lines().filter(|line| !line.trim().is_empty()) {
+            let (cid, name) = line.split_once(' ').ok_or(EINVAL)?;
+            let name = name.trim_start_matches([' ', '*']);
+            let hex = |c: char| c.is_ascii_digit() || ('a'..='f').contains(&c);
+            if cid.len() != 64 || !cid.chars().all(hex) || name.is_empty() || name.contains('/') {
+                return Err(EINVAL);
+            }
+            programs.insert(name.to_string(), cid.to_string());
         }
+        Ok(Self {
+            url_base: url_base.trim_end_matches('/').to_string(),
+            wasm_url: wasm_url.trim_end_matches('/').to_string(),
+            programs,
+        })
     }
 
-    /// Resolve a file path to a location backing the file.
-    pub fn resolve(&self, file_path: &str) -> Option<PathBuf> {
-        let path_buf = PathBuf::from(file_path);
+    /// Resolve a path relative to the mount point to the program backing the file.
+    pub fn resolve(&self, path: &str) -> Option<Program> {
+        let name = path.strip_prefix('/')?;
+        let cid = self.programs.get(name)?;
+        Some(Program {
+            module_url: format!("{}/{}.js", self.url_base, name),
+            wasm_url: format!("{}/{}.wasm", self.wasm_url, cid),
+            cid: cid.clone(),
+        })
+    }
+}
 
-        if !path_buf.starts_with(&self.mount_point) {
-            // Not under our mount point, don't try to resolve.
-            return None;
+impl FileSystem for BinFs {
+    fn lookup(&self, path: &str) -> Result<Rc<dyn Node>, Errno> {
+        // Programs are numbered in name order, after the root.
+        let mut inos = (2..).zip(self.programs.keys());
+        if path == "/" {
+            let entries = inos
+                .map(|(ino, name)| DirEntry {
+                    name: name.clone(),
+                    ino,
+                    kind: (S_IFREG >> 12) as u8,
+                })
+                .collect();
+            return Ok(Rc::new(Synthetic::dir(1, 0o555, entries)));
         }
+        let name = path.strip_prefix('/').unwrap_or(path);
+        let (ino, _) = inos.find(|(_, program)| *program == name).ok_or(ENOENT)?;
+        Ok(Rc::new(Synthetic::file(ino, 0o555, Vec::new())))
+    }
 
-        if let Some(file_name) = path_buf.file_name().and_then(|name| name.to_str()) {
-            Some(path_buf.with_file_name(format!("{}{}", file_name, ".js")))
-        } else {
-            None
+    fn statfs(&self) -> StatFs {
+        StatFs {
+            magic: BINFS_MAGIC,
+            namelen: 255,
+            ..Default::default()
         }
     }
+
+    fn program(&self, path: &str) -> Option<Program> {
+        self.resolve(path)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const BUSYBOX: &str = "5ab0c9e3a8bcd2d2f4b1e8a4d0c3b9f6e7a1d2c3b4a5f6e7d8c9b0a1f2e3d4c5";
+    const GOLD: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
     #[test]
     fn test_resolve() {
-        let fs = BinFs::new("/bin");
-
-        let file_paths = vec![
-            ("/bin/ls", Some(PathBuf::from("/bin/ls.js"))),
-            ("/bin/perl/cpan", Some("/bin/perl/cpan.js".into())),
-            ("/bin/ld.gold", Some("/bin/ld.gold.js".into())),
-            ("/bin/node.js", Some("/bin/node.js.js".into())),
-            ("/usr/bin/go", None),
-            ("/sbin/sudo", None),
-        ];
-
-        for (input_path, expected_output) in file_paths {
-            assert_eq!(fs.resolve(input_path), expected_output);
+        let manifest = format!("{}  busybox\n{} *ld.gold\n", BUSYBOX, GOLD);
+        let fs = BinFs::new("/bin/", "/usr/wasm", &manifest).unwrap();
+
+        let program = fs.resolve("/busybox").unwrap();
+        assert_eq!(program.module_url, "/bin/busybox.js");
+        assert_eq!(program.wasm_url, format!("/usr/wasm/{}.wasm", BUSYBOX));
+        assert_eq!(program.cid, BUSYBOX);
+        let program = fs.resolve("/ld.gold").unwrap();
+        assert_eq!(program.module_url, "/bin/ld.gold.js");
+        assert_eq!(program.cid, GOLD);
+        for path in ["/ls", "/", "/busybox/", "busybox"] {
+            assert!(fs.resolve(path).is_none(), "{}", path);
+        }
+
+        let names: Vec<String> = fs
+            .lookup("/")
+            .unwrap()
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["busybox", "ld.gold"]);
+        assert_eq!(fs.lookup("/ld.gold").unwrap().stat().ino, 3);
+        assert_eq!(fs.lookup("/ls").err(), Some(ENOENT));
+    }
+
+    #[test]
+    fn test_manifest() {
+        for manifest in [
+            "busybox",
+            "abc  busybox",
+            &format!("{}  ", GOLD),
+            &format!("{}  a/b", GOLD),
+        ] {
+            assert_eq!(
+                BinFs::new("/bin", "/usr/wasm", manifest).err(),
+                Some(EINVAL),
+                "{}",
+                manifest
+            );
         }
+        let upper = format!("{}  busybox", BUSYBOX.to_uppercase());
+        assert_eq!(BinFs::new("/bin", "/usr/wasm", &upper).err(), Some(EINVAL));
+        assert!(BinFs::new("/bin", "/usr/wasm", "\n")
+            .unwrap()
+            .programs
+            .is_empty());
     }
 }
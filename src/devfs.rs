@@ -0,0 +1,214 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    errno::Errno::{self, EIO, ENOENT, ENOSPC},
+    js,
+    memfs::TMPFS_MAGIC,
+    vfs::{self, DirEntry, FileSystem, Ino, Node, Stat, StatFs, Synthetic, S_IFCHR},
+};
+
+/// Device numbers of the terminal, opened as a channel rather than through its node, see
+/// ProcessManager::open().
+pub const TTY_DEV: u32 = makedev(5, 0);
+pub const CONSOLE_DEV: u32 = makedev(5, 1);
+
+/// Character devices: name, kind, device number and permissions.
+const DEVICES: [(&str, Device, u32, u32); 7] = [
+    ("console", Device::Terminal, CONSOLE_DEV, 0o600),
+    ("full", Device::Full, makedev(1, 7), 0o666),
+    ("null", Device::Null, makedev(1, 3), 0o666),
+    ("random", Device::Random, makedev(1, 8), 0o666),
+    ("tty", Device::Terminal, TTY_DEV, 0o666),
+    ("urandom", Device::Random, makedev(1, 9), 0o666),
+    ("zero", Device::Zero, makedev(1, 5), 0o666),
+];
+
+/// Device FS, normally mounted at /dev, see devtmpfs.
+///
+/// The devices are fixed: files cannot be created or removed.
+pub struct DevFs {
+    root: Rc<Synthetic>,
+    devices: Vec<(&'static str, Rc<DeviceNode>)>,
+}
+
+/// Source of the bytes read from /dev/random and /dev/urandom.
+///
+/// Bytes come from crypto.getRandomValues(), unless a seed is given, in which case they are the
+/// same sequence every time, e.g. for tests.
+pub struct Random {
+    // State of the SplitMix64 generator, if seeded.
+    state: RefCell<Option<u64>>,
+}
+
+#[derive(Clone, Copy)]
+enum Device {
+    Null,
+    Zero,
+    Full,
+    Random,
+    Terminal,
+}
+
+struct DeviceNode {
+    device: Device,
+    stat: Stat,
+    random: Rc<Random>,
+}
+
+impl DevFs {
+    pub fn new(random: Rc<Random>) -> Self {
+        let now = vfs::now();
+        let devices: Vec<(&str, Rc<DeviceNode>)> = DEVICES
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, device, rdev, mode))| {
+                let stat = Stat {
+                    rdev,
+                    ino: i as Ino + 2,
+                    mode: S_IFCHR | mode,
+                    nlink: 1,
+                    atime: now,
+                    mtime: now,
+                    ctime: now,
+                    ..Default::default()
+                };
+                let random = random.clone();
+                (
+                    name,
+                    Rc::new(DeviceNode {
+                        device,
+                        stat,
+                        random,
+                    }),
+                )
+            })
+            .collect();
+        let entries = devices
+            .iter()
+            .map(|(name, node)| DirEntry::new(name, &node.stat))
+            .collect();
+        Self {
+            root: Rc::new(Synthetic::dir(1, 0o755, entries)),
+            devices,
+        }
+    }
+}
+
+impl FileSystem for DevFs {
+    fn lookup(&self, path: &str) -> Result<Rc<dyn Node>, Errno> {
+        if path == "/" {
+            return Ok(self.root.clone());
+        }
+        let name = path.strip_prefix('/').unwrap_or(path);
+        self.devices
+            .iter()
+            .find(|(device, _)| *device == name)
+            .map(|(_, node)| node.clone() as Rc<dyn Node>)
+            .ok_or(ENOENT)
+    }
+
+    /// Reports the magic number of tmpfs, which backs devtmpfs on Linux.
+    fn statfs(&self) -> StatFs {
+        StatFs {
+            magic: TMPFS_MAGIC,
+            files: DEVICES.len() as u64 + 1,
+            namelen: 255,
+            ..Default::default()
+        }
+    }
+}
+
+impl Random {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            state: RefCell::new(seed),
+        }
+    }
+
+    pub fn bytes(&self, count: usize) -> Vec<u8> {
+        let mut buf = vec![0; count];
+        match &mut *self.state.borrow_mut() {
+            Some(state) => {
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = splitmix64(state).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+            None => js::random_bytes(&mut buf),
+        }
+        buf
+    }
+}
+
+impl Node for DeviceNode {
+    fn stat(&self) -> Stat {
+        self.stat
+    }
+
+    fn read_at(&self, _offset: u64, count: usize) -> Result<Vec<u8>, Errno> {
+        match self.device {
+            Device::Null => Ok(Vec::new()),
+            Device::Zero | Device::Full => Ok(vec![0; count]),
+            Device::Random => Ok(self.random.bytes(count)),
+            Device::Terminal => Err(EIO),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        match self.device {
+            Device::Full => Err(ENOSPC),
+            Device::Terminal => Err(EIO),
+            // Writes to /dev/random are accepted, but do not affect what is read.
+            _ => Ok(buf.len()),
+        }
+    }
+
+    /// Devices have no size, so O_TRUNC is ignored.
+    fn truncate(&self, _len: u64) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Devices have no times to keep.
+    fn set_times(&self, _atime: Option<f64>, _mtime: Option<f64>) -> Result<(), Errno> {
+        Ok(())
+    }
+}
+
+/// Combines major and minor device numbers, see makedev(3).
+const fn makedev(major: u32, minor: u32) -> u32 {
+    (major << 8) | minor
+}
+
+/// Returns the next output of a SplitMix64 generator.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices() {
+        let random = Rc::new(Random::new(Some(42)));
+        let fs = DevFs::new(random.clone());
+        let dev = |name| fs.lookup(name).unwrap();
+
+        assert!(dev("/null").read_at(0, 16).unwrap().is_empty());
+        assert_eq!(dev("/null").write_at(0, b"gone").unwrap(), 4);
+        assert_eq!(dev("/zero").read_at(0, 3).unwrap(), vec![0; 3]);
+        assert_eq!(dev("/full").write_at(0, b"x"), Err(ENOSPC));
+        assert_eq!(dev("/full").stat().rdev, makedev(1, 7));
+        assert_eq!(fs.lookup("/sda").err(), Some(ENOENT));
+
+        let bytes = dev("/urandom").read_at(0, 13).unwrap();
+        assert_eq!(bytes.len(), 13);
+        assert_eq!(Random::new(Some(42)).bytes(13), bytes);
+        assert_ne!(random.bytes(13), bytes);
+        assert_eq!(fs.root.entries().unwrap().len(), DEVICES.len());
+    }
+}
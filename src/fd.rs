@@ -0,0 +1,404 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use crate::{
+    async_io::{Access, AsyncBuffer, PIPE_BUF},
+    poll::{Epoll, POLLERR, POLLHUP, POLLIN, POLLOUT},
+    vfs::File,
+};
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+pub const OPEN_FDS: [u32; 3] = [STDIN, STDOUT, STDERR];
+
+/// Maximum number of open file descriptors per process, see RLIMIT_NOFILE.
+pub const MAX_FDS: u32 = 1024;
+
+// Access modes and status flags, see open(2).
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 1;
+pub const O_RDWR: i32 = 2;
+pub const O_ACCMODE: i32 = 3;
+pub const O_CREAT: i32 = 0o100;
+pub const O_EXCL: i32 = 0o200;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+pub const O_NONBLOCK: i32 = 0o4000;
+pub const O_DIRECTORY: i32 = 0o200000;
+pub const O_CLOEXEC: i32 = 0o2000000;
+
+/// Per-process file descriptor table.
+///
+/// File descriptors refer to open file descriptions, which are shared by duplicated descriptors
+/// and inherited across fork(). Only the close-on-exec flag belongs to the descriptor itself.
+pub struct FdTable {
+    fds: RefCell<BTreeMap<u32, Fd>>,
+}
+
+#[derive(Clone)]
+struct Fd {
+    file: Rc<OpenFile>,
+    cloexec: bool,
+}
+
+/// An open file description.
+pub struct OpenFile {
+    pub target: Target,
+    // Access mode and file status flags, see fcntl(F_GETFL).
+    flags: RefCell<i32>,
+}
+
+/// What an open file description refers to.
+pub enum Target {
+    /// A channel between processes, or between a process and the terminal.
+    Channel {
+        buf: Rc<AsyncBuffer>,
+        access: Access,
+        tty: bool,
+        // Channel written to instead of buf, for the terminal opened through /dev/tty, whose input
+        // and output are distinct channels.
+        output: Option<Rc<AsyncBuffer>>,
+    },
+    /// A file, directory or device in the filesystem shared by all processes.
+    File(File),
+    /// An epoll instance.
+    Epoll(Epoll),
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self {
+            fds: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Creates a table with the standard file descriptors connected to the terminal.
+    pub fn stdio() -> Self {
+        let table = Self::new();
+        for fd in OPEN_FDS {
+            let file = OpenFile::channel(AsyncBuffer::new().into(), Access::ReadWrite, true);
+            table.fds.borrow_mut().insert(
+                fd,
+                Fd {
+                    file: file.into(),
+                    cloexec: false,
+                },
+            );
+        }
+        table
+    }
+
+    /// Creates a copy referring to the same open file descriptions, as inherited on fork().
+    pub fn fork(&self) -> Self {
+        Self {
+            fds: RefCell::new(self.fds.borrow().clone()),
+        }
+    }
+
+    /// Closes the file descriptors that do not survive execve().
+    pub fn exec(&self) {
+        self.fds.borrow_mut().retain(|_, fd| !fd.cloexec);
+    }
+
+    pub fn get(&self, fd: u32) -> Option<Rc<OpenFile>> {
+        self.fds.borrow().get(&fd).map(|fd| fd.file.clone())
+    }
+
+    /// Adds an open file description using the lowest free file descriptor not less than min.
+    pub fn insert(&self, file: Rc<OpenFile>, min: u32, cloexec: bool) -> Option<u32> {
+        let mut fds = self.fds.borrow_mut();
+        let fd = (min..MAX_FDS).find(|fd| !fds.contains_key(fd))?;
+        fds.insert(fd, Fd { file, cloexec });
+        Some(fd)
+    }
+
+    /// Makes new refer to the same open file description as old, closing new first if needed.
+    ///
+    /// Returns the open file description previously referred to by new, if any.
+    pub fn dup2(&self, old: u32, new: u32, cloexec: bool) -> Option<Option<Rc<OpenFile>>> {
+        let file = self.get(old)?;
+        let prev = self.fds.borrow_mut().insert(new, Fd { file, cloexec });
+        Some(prev.map(|fd| fd.file))
+    }
+
+    /// Removes a file descriptor, returning the open file description it referred to.
+    pub fn close(&self, fd: u32) -> Option<Rc<OpenFile>> {
+        self.fds.borrow_mut().remove(&fd).map(|fd| fd.file)
+    }
+
+    /// Returns the open file descriptors, in order.
+    pub fn list(&self) -> Vec<(u32, Rc<OpenFile>)> {
+        self.fds
+            .borrow()
+            .iter()
+            .map(|(fd, entry)| (*fd, entry.file.clone()))
+            .collect()
+    }
+
+    pub fn close_all(&self) {
+        self.fds.borrow_mut().clear();
+    }
+
+    pub fn cloexec(&self, fd: u32) -> Option<bool> {
+        self.fds.borrow().get(&fd).map(|fd| fd.cloexec)
+    }
+
+    pub fn set_cloexec(&self, fd: u32, cloexec: bool) -> Option<()> {
+        self.fds
+            .borrow_mut()
+            .get_mut(&fd)
+            .map(|fd| fd.cloexec = cloexec)
+    }
+}
+
+impl OpenFile {
+    pub fn channel(buf: Rc<AsyncBuffer>, access: Access, tty: bool) -> Self {
+        buf.acquire(access);
+        Self {
+            target: Target::Channel {
+                buf,
+                access,
+                tty,
+                output: None,
+            },
+            flags: RefCell::new(access_mode(access)),
+        }
+    }
+
+    /// Opens the terminal, reading from its input channel and writing to its output channel.
+    pub fn terminal(input: Rc<AsyncBuffer>, output: Rc<AsyncBuffer>, access: Access) -> Self {
+        if access.reads() {
+            input.acquire(Access::Read);
+        }
+        if access.writes() {
+            output.acquire(Access::Write);
+        }
+        Self {
+            target: Target::Channel {
+                buf: input,
+                access,
+                tty: true,
+                output: Some(output),
+            },
+            flags: RefCell::new(access_mode(access)),
+        }
+    }
+
+    pub fn file(file: File, flags: i32) -> Self {
+        Self {
+            target: Target::File(file),
+            flags: RefCell::new(flags & (O_ACCMODE | O_APPEND | O_NONBLOCK)),
+        }
+    }
+
+    pub fn epoll(epoll: Epoll) -> Self {
+        Self {
+            target: Target::Epoll(epoll),
+            flags: RefCell::new(O_RDWR),
+        }
+    }
+
+    pub fn flags(&self) -> i32 {
+        *self.flags.borrow()
+    }
+
+    /// Updates the file status flags; the access mode cannot be changed.
+    pub fn set_flags(&self, flags: i32) {
+        let mode = self.flags() & O_ACCMODE;
+        self.flags.replace(mode | flags & (O_APPEND | O_NONBLOCK));
+    }
+
+    /// Returns the file, if the open file description refers to one in the filesystem.
+    pub fn fs_file(&self) -> Option<&File> {
+        match &self.target {
+            Target::File(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Returns the channel, if the open file description refers to one.
+    pub fn channel_end(&self) -> Option<(&Rc<AsyncBuffer>, Access)> {
+        match &self.target {
+            Target::Channel { buf, access, .. } => Some((buf, *access)),
+            _ => None,
+        }
+    }
+
+    /// Returns the channel to read from or write to, if the open file description refers to one
+    /// open for the given access.
+    pub fn channel_for(&self, access: Access) -> Option<&Rc<AsyncBuffer>> {
+        let Target::Channel {
+            buf,
+            access: end,
+            output,
+            ..
+        } = &self.target
+        else {
+            return None;
+        };
+        if (access.reads() && !end.reads()) || (access.writes() && !end.writes()) {
+            return None;
+        }
+        match output {
+            Some(output) if access.writes() => Some(output),
+            _ => Some(buf),
+        }
+    }
+
+    /// Returns the events that are ready, see poll(2).
+    ///
+    /// Files are always ready, as is the case for regular files. Whether an epoll instance is
+    /// depends on the file descriptors it watches, which the process manager checks: on its own,
+    /// it is never ready.
+    pub fn poll(&self) -> i16 {
+        let (buf, access) = match &self.target {
+            Target::Channel { buf, access, .. } => (buf, access),
+            Target::File(_) => return POLLIN | POLLOUT,
+            Target::Epoll(_) => return 0,
+        };
+        let mut events = 0;
+        if access.reads() {
+            if buf.has_data() {
+                events |= POLLIN;
+            }
+            if buf.is_closed() {
+                events |= POLLHUP;
+            }
+        }
+        if let Some(buf) = self.channel_for(Access::Write) {
+            if buf.is_broken() {
+                events |= POLLERR;
+            } else if !buf.would_block(PIPE_BUF) {
+                events |= POLLOUT;
+            }
+        }
+        events
+    }
+
+    pub fn is_tty(&self) -> bool {
+        matches!(self.target, Target::Channel { tty: true, .. })
+    }
+
+    /// Describes what the open file description refers to, like the links in /proc/<pid>/fd.
+    ///
+    /// Anything outside the filesystem is named after its kind and a unique id.
+    pub fn link(&self) -> String {
+        match &self.target {
+            Target::Channel { tty: true, .. } => "/dev/tty".to_string(),
+            Target::Channel { buf, .. } => format!("pipe:[{}]", Rc::as_ptr(buf) as usize),
+            Target::File(file) => file.location.path.clone(),
+            Target::Epoll(_) => "anon_inode:[eventpoll]".to_string(),
+        }
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        match &self.target {
+            Target::Channel {
+                buf,
+                access,
+                output: Some(output),
+                ..
+            } => {
+                if access.reads() {
+                    buf.release(Access::Read);
+                }
+                if access.writes() {
+                    output.release(Access::Write);
+                }
+            }
+            Target::Channel { buf, access, .. } => buf.release(*access),
+            _ => {}
+        }
+    }
+}
+
+fn access_mode(access: Access) -> i32 {
+    match access {
+        Access::Read => O_RDONLY,
+        Access::Write => O_WRONLY,
+        Access::ReadWrite => O_RDWR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipe(table: &FdTable) -> (u32, u32, Rc<AsyncBuffer>) {
+        let buf = Rc::new(AsyncBuffer::new());
+        let read = OpenFile::channel(buf.clone(), Access::Read, false);
+        let write = OpenFile::channel(buf.clone(), Access::Write, false);
+        let read = table.insert(read.into(), 0, false).unwrap();
+        let write = table.insert(write.into(), 0, true).unwrap();
+        (read, write, buf)
+    }
+
+    #[test]
+    fn test_lowest_free() {
+        let table = FdTable::stdio();
+        assert_eq!(pipe(&table).0, 3);
+        table.close(STDIN);
+        assert_eq!(pipe(&table).0, STDIN);
+        assert_eq!(
+            table.insert(table.get(STDOUT).unwrap(), 10, false),
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn test_dup2() {
+        let table = FdTable::new();
+        let (read, write, buf) = pipe(&table);
+
+        let prev = table.dup2(write, read, false).unwrap();
+        assert!(prev.is_some());
+        drop(prev);
+        assert!(buf.is_broken()); // no readers left
+        assert_eq!(table.cloexec(read), Some(false));
+        assert!(table.dup2(42, read, false).is_none());
+    }
+
+    #[test]
+    fn test_fork_exec() {
+        let table = FdTable::new();
+        let (read, write, buf) = pipe(&table);
+
+        let child = table.fork();
+        child.exec();
+        assert!(child.get(read).is_some());
+        assert!(child.get(write).is_none()); // close-on-exec
+
+        child.close_all();
+        table.close(read);
+        assert!(buf.is_broken());
+    }
+
+    #[test]
+    fn test_poll() {
+        let table = FdTable::new();
+        let (read, write, buf) = pipe(&table);
+        let file = table.get(read).unwrap();
+        assert_eq!(file.poll(), 0);
+        assert_eq!(table.get(write).unwrap().poll(), POLLOUT);
+
+        buf.write(vec![0]).unwrap();
+        assert_eq!(file.poll(), POLLIN);
+        table.close(write);
+        assert_eq!(file.poll(), POLLIN | POLLHUP);
+
+        let (read, write, _) = pipe(&table);
+        table.close(read);
+        assert_eq!(table.get(write).unwrap().poll(), POLLERR);
+        // Readiness of epoll instances is left to the process manager.
+        assert_eq!(OpenFile::epoll(Epoll::new()).poll(), 0);
+    }
+
+    #[test]
+    fn test_flags() {
+        let file = OpenFile::channel(AsyncBuffer::new().into(), Access::Write, false);
+        file.set_flags(O_RDWR | O_NONBLOCK);
+        assert_eq!(file.flags(), O_WRONLY | O_NONBLOCK);
+    }
+}
@@ -3,13 +3,14 @@ use std::cell::RefCell;
 use js_sys::{
     eval, Array, Error, Function, JsString, Object, Promise, Reflect, Uint8Array, JSON::stringify,
 };
-use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsValue};
+use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::HtmlElement;
 
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen]
+    #[derive(Clone)]
     pub type Module;
 
     #[wasm_bindgen(method, getter, js_name=HEAPU8)]
@@ -36,6 +37,27 @@ extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     pub fn error(s: &str);
 
+    #[wasm_bindgen(js_namespace = crypto, js_name = getRandomValues)]
+    fn get_random_values(array: &Uint8Array);
+
+    #[wasm_bindgen(js_namespace = ["crypto", "subtle"])]
+    fn digest(algorithm: &str, data: &Uint8Array) -> Promise;
+
+    #[wasm_bindgen(js_name = fetch)]
+    fn fetch_url(url: &str, options: &JsValue) -> Promise;
+
+    #[wasm_bindgen]
+    type Response;
+
+    #[wasm_bindgen(method, getter)]
+    fn ok(this: &Response) -> bool;
+
+    #[wasm_bindgen(method, getter)]
+    fn status(this: &Response) -> u16;
+
+    #[wasm_bindgen(method, js_name = arrayBuffer)]
+    fn array_buffer(this: &Response) -> Promise;
+
     #[wasm_bindgen]
     pub type Terminal;
 
@@ -45,8 +67,9 @@ extern "C" {
     #[wasm_bindgen(method, catch, js_name = loadAddon)]
     pub fn load_fit_addon(this: &Terminal, addon: &FitAddon) -> Result<(), JsValue>;
 
+    /// Writes data, calling callback once xterm.js has processed it.
     #[wasm_bindgen(method, catch)]
-    pub fn write(this: &Terminal, data: &Uint8Array) -> Result<(), JsValue>;
+    pub fn write(this: &Terminal, data: &Uint8Array, callback: &Function) -> Result<(), JsValue>;
 
     #[wasm_bindgen(method, js_name = write)]
     pub fn write_string(this: &Terminal, data: &str);
@@ -68,6 +91,16 @@ extern "C" {
 
     #[wasm_bindgen(method, catch)]
     pub fn dispose(this: &Disposable) -> Result<(), JsValue>;
+
+    /// Storage option of boot(), see storage::Storage.
+    #[wasm_bindgen]
+    pub type HostStorage;
+
+    #[wasm_bindgen(method, catch)]
+    pub fn load(this: &HostStorage) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    pub fn save(this: &HostStorage, snapshot: &Uint8Array) -> Result<(), JsValue>;
 }
 
 thread_local! {
@@ -78,13 +111,72 @@ pub fn p_defer_init(p_defer: Function) {
     P_DEFER.with(|rc| *rc.borrow_mut() = Some(p_defer));
 }
 
+/// Returns a new deferred object, or a null one in native unit tests, which must not be settled.
 pub fn deferred() -> Result<Deferred, Error> {
+    if !cfg!(target_arch = "wasm32") {
+        return Ok(JsValue::NULL.unchecked_into());
+    }
     Ok(P_DEFER
         .with(|rc| rc.borrow().as_ref().map(|f| f.call0(&JsValue::null())))
         .expect("deferred: not ready")?
         .into())
 }
 
+/// Returns a promise that resolves after the given number of milliseconds.
+pub fn sleep(ms: f64) -> Promise {
+    Promise::new(&mut |resolve, _| {
+        let window = web_sys::window().expect("sleep: no window");
+        if window
+            .set_timeout_with_callback_and_timeout_and_arguments_1(
+                &resolve,
+                ms.min(i32::MAX as f64) as i32,
+                &JsValue::TRUE,
+            )
+            .is_err()
+        {
+            error("sleep: setTimeout failed");
+        }
+    })
+}
+
+/// Fills buf with cryptographically strong random bytes, see Crypto.getRandomValues().
+pub fn random_bytes(buf: &mut [u8]) {
+    // At most 64 KiB are filled per call.
+    for chunk in buf.chunks_mut(65536) {
+        let array = Uint8Array::new_with_length(chunk.len() as u32);
+        get_random_values(&array);
+        array.copy_to(chunk);
+    }
+}
+
+/// Returns the SHA-256 hash of data in hex, see SubtleCrypto.digest().
+pub async fn sha256(data: &Uint8Array) -> Result<String, Error> {
+    let hash = Uint8Array::new(&JsFuture::from(digest("SHA-256", data)).await?);
+    Ok(hash
+        .to_vec()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Returns the body of the response to a GET request, see fetch().
+///
+/// The cache mode tells how the HTTP cache is used, e.g. "no-cache" to revalidate a cached
+/// response first.
+pub async fn fetch(url: &str, cache: &str) -> Result<Uint8Array, Error> {
+    let options: JsValue = Builder::new().set("cache", cache)?.into();
+    let response: Response = JsFuture::from(fetch_url(url, &options)).await?.into();
+    if !response.ok() {
+        return Err(Error::new(&format!(
+            "fetch {}: HTTP status {}",
+            url,
+            response.status()
+        )));
+    }
+    let body = JsFuture::from(response.array_buffer()).await?;
+    Ok(Uint8Array::new(&body))
+}
+
 pub async fn load_module(path: &str) -> Result<Function, Error> {
     // Currently wasm-bindgen doesn't seem to support dynamic imports.
     // As a fallback, we eval(…) the import statement. Not very elegant, but it works.
@@ -97,6 +189,70 @@ pub async fn load_module(path: &str) -> Result<Function, Error> {
     Ok(Reflect::get(&JsFuture::from(promise).await?, &"default".into())?.into())
 }
 
+impl Module {
+    /// Reads a NUL-terminated C string from the heap.
+    pub fn read_cstr(&self, ptr: u32) -> String {
+        let heap = self.heap();
+        let mut end = ptr;
+        while end < heap.length() && heap.get_index(end) != 0 {
+            end += 1;
+        }
+        String::from_utf8_lossy(&heap.subarray(ptr, end).to_vec()).into_owned()
+    }
+
+    /// Reads a NULL-terminated array of C strings (e.g. argv or envp) from the heap.
+    pub fn read_cstr_array(&self, ptr: u32) -> Vec<String> {
+        let mut items = Vec::new();
+        if ptr == 0 {
+            return items;
+        }
+        let mut offset = ptr;
+        loop {
+            let item = self.read_u32(offset);
+            if item == 0 {
+                return items;
+            }
+            items.push(self.read_cstr(item));
+            offset += 4;
+        }
+    }
+
+    /// Reads a little-endian 32-bit word from the heap.
+    pub fn read_u32(&self, ptr: u32) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.heap().subarray(ptr, ptr + 4).copy_to(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Writes a little-endian 32-bit word to the heap.
+    pub fn write_u32(&self, ptr: u32, value: u32) {
+        self.write_bytes(ptr, &value.to_le_bytes());
+    }
+
+    /// Copies bytes from the heap.
+    pub fn read_bytes(&self, ptr: u32, len: u32) -> Vec<u8> {
+        self.heap().subarray(ptr, ptr + len).to_vec()
+    }
+
+    /// Gathers the buffers of an array of struct iovec, as passed to writev().
+    pub fn read_iovec(&self, iov: u32, iovcnt: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..iovcnt {
+            let base = self.read_u32(iov + i * 8);
+            let len = self.read_u32(iov + i * 8 + 4);
+            data.extend(self.read_bytes(base, len));
+        }
+        data
+    }
+
+    /// Copies bytes to the heap.
+    pub fn write_bytes(&self, ptr: u32, data: &[u8]) {
+        self.heap()
+            .subarray(ptr, ptr + data.len() as u32)
+            .copy_from(data);
+    }
+}
+
 pub fn str_array(items: &[&str]) -> Array {
     let array = Array::new_with_length(items.len() as u32);
     for (i, arg) in items.iter().enumerate() {
@@ -25,6 +25,42 @@ import { Terminal } from "xterm";
 import { FitAddon } from "xterm-addon-fit";
 import pDefer from "p-defer"
 
+// Boot options.
+// Standard error output is shown in red, see SGR parameters in console_codes(4).
+// A numeric randomSeed makes /dev/random and /dev/urandom deterministic, e.g.
+// for tests.
+// A rootfs tar archive, as a byte array, or a rootfsUrl to fetch it from, is
+// unpacked into the root filesystem before the shell starts.
+// A storage object keeps the home directory across boots: load() returns the
+// last snapshot saved, as a byte array, or null, and save(snapshot) replaces
+// it. Both are called synchronously, on exit and by snapshot().
+const options = { stderrStyle: "31" };
+
+// The home directory is kept in localStorage across page loads.
+const HOME_SNAPSHOT_KEY = "snailos.homeSnapshot";
+options.storage = {
+  load() {
+    const saved = localStorage.getItem(HOME_SNAPSHOT_KEY);
+    return saved === null
+      ? null
+      : Uint8Array.from(atob(saved), (c) => c.charCodeAt(0));
+  },
+  save(snapshot) {
+    let binary = "";
+    for (let i = 0; i < snapshot.length; i += 0x8000) {
+      binary += String.fromCharCode(...snapshot.subarray(i, i + 0x8000));
+    }
+    localStorage.setItem(HOME_SNAPSHOT_KEY, btoa(binary));
+  },
+};
+window.addEventListener("pagehide", () => {
+  try {
+    os.snapshot();
+  } catch (err) {
+    console.warn("failed to save the home directory:", err);
+  }
+});
+
 const deps = { Terminal, FitAddon, pDefer };
 
 // Dynamic import.
@@ -35,4 +71,4 @@ const deps = { Terminal, FitAddon, pDefer };
 
 // Don't block the page load.
 // This will still execute on the main thread, but in the next event loop.
-setTimeout(os.boot.bind(os, deps), 0);
+setTimeout(os.boot.bind(os, { ...deps, ...options }), 0);
@@ -1,4 +1,6 @@
-use js_sys::Error;
+use std::{cell::RefCell, rc::Rc};
+
+use js_sys::{Error, Uint8Array};
 use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
 
 use crate::os::OS;
@@ -6,12 +8,50 @@ use crate::os::OS;
 mod async_io;
 mod binfs;
 mod compilation_mode;
+mod devfs;
+mod errno;
+mod fd;
 mod js;
+mod memfs;
+mod mount;
 mod os;
+mod poll;
 mod proc;
+mod procfs;
+mod signal;
+mod snapshot;
+mod storage;
+mod tar;
 mod term;
+mod vfs;
+
+thread_local! {
+    // The OS once booted, see snapshot().
+    static BOOTED: RefCell<Option<Rc<OS>>> = const { RefCell::new(None) };
+}
 
 #[wasm_bindgen]
 pub async fn boot(config: JsValue) -> Result<(), Error> {
-    OS::new(config)?.boot().await
+    let os = Rc::new(OS::new(config)?);
+    BOOTED.with(|booted| booted.replace(Some(os.clone())));
+    os.boot().await
+}
+
+/// Saves the home directory to the storage option of boot(), and returns its snapshot.
+#[wasm_bindgen]
+pub fn snapshot() -> Result<Uint8Array, Error> {
+    Ok(booted()?.save()?.as_slice().into())
+}
+
+/// Packs the file or directory at path into a tar archive, e.g. for the host page to offer as a
+/// download.
+#[wasm_bindgen(js_name = exportTar)]
+pub fn export_tar(path: &str) -> Result<Uint8Array, Error> {
+    Ok(booted()?.pack(path)?.as_slice().into())
+}
+
+fn booted() -> Result<Rc<OS>, Error> {
+    BOOTED
+        .with(|booted| booted.borrow().clone())
+        .ok_or_else(|| Error::new("not booted"))
 }
@@ -0,0 +1,618 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+};
+
+use crate::{
+    errno::Errno::{
+        self, EBUSY, EEXIST, EFBIG, EINVAL, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR,
+        ENOTEMPTY,
+    },
+    procfs::MEM_TOTAL,
+    vfs::{self, DirEntry, FileSystem, Ino, Node, Stat, StatFs, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG},
+};
+
+/// Magic numbers reported by statfs(), see statfs(2).
+const RAMFS_MAGIC: u32 = 0x858458f6;
+pub const TMPFS_MAGIC: u32 = 0x01021994;
+
+const S_IALLUGO: u32 = 0o7777;
+
+/// Mask applied to the mode of new files and directories, as there is no umask() yet.
+const UMASK: u32 = 0o022;
+
+/// Longest file name, see NAME_MAX.
+const NAME_MAX: usize = 255;
+
+/// Largest file that can be written, as everything is kept in memory.
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// Size of the pages holding file contents, and the unit of the size limit of a tmpfs.
+const PAGE_SIZE: u64 = vfs::BLKSIZE as u64;
+
+/// Default size and number of inodes of a tmpfs: half the memory, as on Linux.
+const DEFAULT_BLOCKS: u64 = MEM_TOTAL / 2 / PAGE_SIZE;
+const DEFAULT_INODES: u64 = MEM_TOTAL / 2 / PAGE_SIZE;
+
+/// In-memory filesystem, like ramfs, or like tmpfs when created with limits.
+pub struct MemFs {
+    root: Rc<Inode>,
+    next_ino: RefCell<Ino>,
+    usage: Rc<Usage>,
+}
+
+/// Pages and inodes in use by a filesystem, shared by all its inodes.
+///
+/// An inode is only freed once it is neither linked nor open, as on Linux.
+struct Usage {
+    limits: Option<Limits>,
+    blocks: Cell<u64>,
+    inodes: Cell<u64>,
+}
+
+/// Maximum numbers of pages and inodes of a tmpfs, where 0 means no limit.
+#[derive(Clone, Copy)]
+struct Limits {
+    blocks: u64,
+    inodes: u64,
+}
+
+/// A file, directory or symbolic link.
+pub struct Inode {
+    ino: Ino,
+    // File type and permissions.
+    mode: RefCell<u32>,
+    data: RefCell<Data>,
+    times: RefCell<Times>,
+    usage: Rc<Usage>,
+}
+
+enum Data {
+    File(Pages),
+    Dir(BTreeMap<String, Rc<Inode>>),
+    Link(String),
+}
+
+/// Contents of a regular file, split in pages so that holes left by sparse writes and truncation
+/// take no space.
+#[derive(Default)]
+struct Pages {
+    size: u64,
+    // By index, missing pages read as zeros.
+    pages: BTreeMap<u64, Box<[u8]>>,
+}
+
+/// Access, modification and status change times, in milliseconds since the epoch.
+#[derive(Clone, Copy)]
+struct Times {
+    atime: f64,
+    mtime: f64,
+    ctime: f64,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::with_limits(None, 0o755)
+    }
+
+    /// Creates a tmpfs from its mount options, see tmpfs(5).
+    ///
+    /// The options are size, in bytes with an optional k, m or g suffix or in percent of the
+    /// memory, nr_blocks, nr_inodes and the mode of the root directory. Fails with EINVAL on
+    /// anything else.
+    pub fn tmpfs(options: &str) -> Result<Self, Errno> {
+        let mut limits = Limits {
+            blocks: DEFAULT_BLOCKS,
+            inodes: DEFAULT_INODES,
+        };
+        let mut mode = 0o1777;
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (name, value) = option.split_once('=').ok_or(EINVAL)?;
+            match name {
+                "size" => {
+                    let size = match value.strip_suffix('%') {
+                        Some(percent) => {
+                            MEM_TOTAL / 100 * percent.parse::<u64>().map_err(|_| EINVAL)?
+                        }
+                        None => parse_size(value)?,
+                    };
+                    limits.blocks = size.div_ceil(PAGE_SIZE);
+                }
+                "nr_blocks" => limits.blocks = parse_size(value)?,
+                "nr_inodes" => limits.inodes = parse_size(value)?,
+                "mode" => {
+                    mode = u32::from_str_radix(value, 8).map_err(|_| EINVAL)? & S_IALLUGO;
+                }
+                _ => return Err(EINVAL),
+            }
+        }
+        Ok(Self::with_limits(Some(limits), mode))
+    }
+
+    fn with_limits(limits: Option<Limits>, mode: u32) -> Self {
+        let usage = Rc::new(Usage {
+            limits,
+            blocks: Cell::new(0),
+            inodes: Cell::new(0),
+        });
+        // There is always room for one inode.
+        let root = Inode::new(1, S_IFDIR | mode, Data::Dir(BTreeMap::new()), &usage)
+            .expect("no inode left for the root");
+        Self {
+            root: Rc::new(root),
+            next_ino: RefCell::new(2),
+            usage,
+        }
+    }
+
+    fn inode(&self, path: &str) -> Result<Rc<Inode>, Errno> {
+        let mut inode = self.root.clone();
+        for name in vfs::components(path) {
+            inode = inode.child(name)?;
+        }
+        Ok(inode)
+    }
+
+    /// Returns the directory containing path, and the last component of path.
+    fn parent<'a>(&self, path: &'a str) -> Result<(Rc<Inode>, &'a str), Errno> {
+        let (dir, name) = path.rsplit_once('/').ok_or(EINVAL)?;
+        if name.is_empty() {
+            return Err(EBUSY);
+        }
+        let dir = self.inode(dir)?;
+        if !dir.is_dir() {
+            return Err(ENOTDIR);
+        }
+        Ok((dir, name))
+    }
+
+    /// Adds a new inode to the directory containing path.
+    fn create_inode(&self, path: &str, mode: u32, data: Data) -> Result<Rc<Inode>, Errno> {
+        let (dir, name) = self.parent(path)?;
+        if name.len() > NAME_MAX {
+            return Err(ENAMETOOLONG);
+        }
+        if dir.child(name).is_ok() {
+            return Err(EEXIST);
+        }
+        let ino = *self.next_ino.borrow();
+        let inode = Rc::new(Inode::new(ino, mode, data, &self.usage)?);
+        self.next_ino.replace(ino + 1);
+        dir.insert(name, inode.clone());
+        Ok(inode)
+    }
+}
+
+impl FileSystem for MemFs {
+    fn lookup(&self, path: &str) -> Result<Rc<dyn Node>, Errno> {
+        Ok(self.inode(path)?)
+    }
+
+    fn create(&self, path: &str, mode: u32) -> Result<Rc<dyn Node>, Errno> {
+        let data = Data::File(Pages::default());
+        Ok(self.create_inode(path, S_IFREG | mode & !UMASK, data)?)
+    }
+
+    fn mkdir(&self, path: &str, mode: u32) -> Result<(), Errno> {
+        if path == "/" {
+            return Err(EEXIST);
+        }
+        let data = Data::Dir(BTreeMap::new());
+        self.create_inode(path, S_IFDIR | mode & !UMASK, data)
+            .map(|_| ())
+    }
+
+    /// Links are stored whole, so their targets are limited to a page like on Linux.
+    fn symlink(&self, path: &str, target: &str) -> Result<(), Errno> {
+        if target.len() >= PAGE_SIZE as usize {
+            return Err(ENAMETOOLONG);
+        }
+        let data = Data::Link(target.to_string());
+        self.create_inode(path, S_IFLNK | 0o777, data).map(|_| ())
+    }
+
+    fn unlink(&self, path: &str) -> Result<(), Errno> {
+        let (dir, name) = self.parent(path)?;
+        if dir.child(name)?.is_dir() {
+            return Err(EISDIR);
+        }
+        dir.remove(name);
+        Ok(())
+    }
+
+    fn rmdir(&self, path: &str) -> Result<(), Errno> {
+        let inode = self.inode(path)?;
+        if !inode.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let (dir, name) = self.parent(path)?;
+        if !inode.is_empty() {
+            return Err(ENOTEMPTY);
+        }
+        dir.remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, old: &str, new: &str) -> Result<(), Errno> {
+        let (old_dir, old_name) = self.parent(old)?;
+        let inode = old_dir.child(old_name)?;
+        let (new_dir, new_name) = self.parent(new)?;
+        if old == new {
+            return Ok(());
+        }
+        if inode.is_dir() && new.starts_with(&format!("{}/", old)) {
+            // A directory cannot be moved into itself.
+            return Err(EINVAL);
+        }
+        if let Ok(existing) = new_dir.child(new_name) {
+            match (inode.is_dir(), existing.is_dir()) {
+                (true, false) => return Err(ENOTDIR),
+                (false, true) => return Err(EISDIR),
+                (true, true) if !existing.is_empty() => return Err(ENOTEMPTY),
+                _ => {}
+            }
+        }
+
+        old_dir.remove(old_name);
+        new_dir.insert(new_name, inode.clone());
+        inode.touch(false);
+        Ok(())
+    }
+
+    fn statfs(&self) -> StatFs {
+        let blocks = self.usage.blocks.get();
+        let files = self.usage.inodes.get();
+        match self.usage.limits {
+            Some(limits) => StatFs {
+                magic: TMPFS_MAGIC,
+                blocks: limits.blocks,
+                bfree: limits.blocks.saturating_sub(blocks),
+                files: limits.inodes,
+                ffree: limits.inodes.saturating_sub(files),
+                namelen: NAME_MAX as u32,
+                ..Default::default()
+            },
+            // Nothing is reserved beyond what is in use.
+            None => StatFs {
+                magic: RAMFS_MAGIC,
+                blocks,
+                bfree: 0,
+                files,
+                ffree: 0,
+                namelen: NAME_MAX as u32,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Usage {
+    /// Takes pages and inodes, failing with ENOSPC beyond the limits.
+    fn reserve(&self, blocks: u64, inodes: u64) -> Result<(), Errno> {
+        let blocks = self.blocks.get() + blocks;
+        let inodes = self.inodes.get() + inodes;
+        if let Some(limits) = self.limits {
+            let exceeds = |used, limit| limit != 0 && used > limit;
+            if exceeds(blocks, limits.blocks) || exceeds(inodes, limits.inodes) {
+                return Err(ENOSPC);
+            }
+        }
+        self.blocks.set(blocks);
+        self.inodes.set(inodes);
+        Ok(())
+    }
+
+    fn release(&self, blocks: u64, inodes: u64) {
+        self.blocks.set(self.blocks.get() - blocks);
+        self.inodes.set(self.inodes.get() - inodes);
+    }
+}
+
+impl Inode {
+    fn new(ino: Ino, mode: u32, data: Data, usage: &Rc<Usage>) -> Result<Self, Errno> {
+        usage.reserve(0, 1)?;
+        let now = vfs::now();
+        Ok(Self {
+            ino,
+            mode: RefCell::new(mode),
+            data: RefCell::new(data),
+            times: RefCell::new(Times {
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+            usage: usage.clone(),
+        })
+    }
+
+    fn mode(&self) -> u32 {
+        *self.mode.borrow()
+    }
+
+    fn size(&self) -> u64 {
+        match &*self.data.borrow() {
+            Data::File(pages) => pages.size,
+            Data::Dir(entries) => entries.len() as u64,
+            Data::Link(target) => target.len() as u64,
+        }
+    }
+
+    fn child(&self, name: &str) -> Result<Rc<Inode>, Errno> {
+        match &*self.data.borrow() {
+            Data::Dir(entries) => entries.get(name).cloned().ok_or(ENOENT),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    fn insert(&self, name: &str, inode: Rc<Inode>) {
+        if let Data::Dir(entries) = &mut *self.data.borrow_mut() {
+            entries.insert(name.to_string(), inode);
+        }
+        self.touch(true);
+    }
+
+    fn remove(&self, name: &str) {
+        if let Data::Dir(entries) = &mut *self.data.borrow_mut() {
+            entries.remove(name);
+        }
+        self.touch(true);
+    }
+
+    /// Updates the status change time, and the modification time if the contents changed.
+    fn touch(&self, modified: bool) {
+        let now = vfs::now();
+        let mut times = self.times.borrow_mut();
+        times.ctime = now;
+        if modified {
+            times.mtime = now;
+        }
+    }
+}
+
+impl Node for Inode {
+    fn stat(&self) -> Stat {
+        let nlink = match &*self.data.borrow() {
+            // Each subdirectory links back with "..".
+            Data::Dir(entries) => 2 + entries.values().filter(|inode| inode.is_dir()).count(),
+            _ => 1,
+        };
+        let times = *self.times.borrow();
+        Stat {
+            ino: self.ino,
+            mode: self.mode(),
+            nlink: nlink as u32,
+            size: self.size(),
+            atime: times.atime,
+            mtime: times.mtime,
+            ctime: times.ctime,
+            ..Default::default()
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode() & S_IFMT == S_IFDIR
+    }
+
+    fn read_at(&self, offset: u64, count: usize) -> Result<Vec<u8>, Errno> {
+        match &*self.data.borrow() {
+            Data::File(pages) => Ok(pages.read(offset, count)),
+            Data::Dir(_) => Err(EISDIR),
+            Data::Link(_) => Err(EINVAL),
+        }
+    }
+
+    /// Writes data at offset, leaving a hole in any gap.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        if offset + buf.len() as u64 > MAX_FILE_SIZE {
+            return Err(EFBIG);
+        }
+        match &mut *self.data.borrow_mut() {
+            Data::File(pages) => pages.write(offset, buf, &self.usage)?,
+            Data::Dir(_) => return Err(EISDIR),
+            Data::Link(_) => return Err(EINVAL),
+        }
+        self.touch(true);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, len: u64) -> Result<(), Errno> {
+        if len > MAX_FILE_SIZE {
+            return Err(EFBIG);
+        }
+        match &mut *self.data.borrow_mut() {
+            Data::File(pages) => pages.truncate(len, &self.usage),
+            Data::Dir(_) => return Err(EISDIR),
+            Data::Link(_) => return Err(EINVAL),
+        }
+        self.touch(true);
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, Errno> {
+        match &*self.data.borrow() {
+            Data::Dir(entries) => Ok(entries
+                .iter()
+                .map(|(name, inode)| DirEntry::new(name, &inode.stat()))
+                .collect()),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    fn chmod(&self, mode: u32) -> Result<(), Errno> {
+        self.mode
+            .replace_with(|old| *old & S_IFMT | mode & S_IALLUGO);
+        self.touch(false);
+        Ok(())
+    }
+
+    fn set_times(&self, atime: Option<f64>, mtime: Option<f64>) -> Result<(), Errno> {
+        let mut times = self.times.borrow_mut();
+        times.atime = atime.unwrap_or(times.atime);
+        times.mtime = mtime.unwrap_or(times.mtime);
+        times.ctime = vfs::now();
+        Ok(())
+    }
+
+    fn readlink(&self) -> Result<String, Errno> {
+        match &*self.data.borrow() {
+            Data::Link(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+}
+
+/// Returns what the inode held to its filesystem.
+impl Drop for Inode {
+    fn drop(&mut self) {
+        let blocks = match self.data.get_mut() {
+            Data::File(pages) => pages.pages.len() as u64,
+            _ => 0,
+        };
+        self.usage.release(blocks, 1);
+    }
+}
+
+impl Pages {
+    fn read(&self, offset: u64, count: usize) -> Vec<u8> {
+        let start = offset.min(self.size);
+        let end = self.size.min(start.saturating_add(count as u64));
+        let mut buf = vec![0; (end - start) as usize];
+        for (index, page) in self.pages.range(start / PAGE_SIZE..end.div_ceil(PAGE_SIZE)) {
+            let page_start = index * PAGE_SIZE;
+            let from = start.max(page_start);
+            let to = end.min(page_start + PAGE_SIZE);
+            buf[(from - start) as usize..(to - start) as usize]
+                .copy_from_slice(&page[(from - page_start) as usize..(to - page_start) as usize]);
+        }
+        buf
+    }
+
+    /// Writes data at offset, allocating the pages it covers first.
+    fn write(&mut self, offset: u64, buf: &[u8], usage: &Usage) -> Result<(), Errno> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let end = offset + buf.len() as u64;
+        let indexes = offset / PAGE_SIZE..end.div_ceil(PAGE_SIZE);
+        let missing = indexes
+            .clone()
+            .filter(|index| !self.pages.contains_key(index))
+            .count();
+        usage.reserve(missing as u64, 0)?;
+        for index in indexes {
+            let page = self
+                .pages
+                .entry(index)
+                .or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+            let page_start = index * PAGE_SIZE;
+            let from = offset.max(page_start);
+            let to = end.min(page_start + PAGE_SIZE);
+            page[(from - page_start) as usize..(to - page_start) as usize]
+                .copy_from_slice(&buf[(from - offset) as usize..(to - offset) as usize]);
+        }
+        self.size = self.size.max(end);
+        Ok(())
+    }
+
+    /// Changes the size, freeing the pages past the end.
+    fn truncate(&mut self, len: u64, usage: &Usage) {
+        if len < self.size {
+            let freed = self.pages.split_off(&len.div_ceil(PAGE_SIZE));
+            usage.release(freed.len() as u64, 0);
+            // The rest of the last page must read as zeros if the file grows again.
+            let tail = (len % PAGE_SIZE) as usize;
+            if let Some(page) = self.pages.get_mut(&(len / PAGE_SIZE)) {
+                page[tail..].fill(0);
+            }
+        }
+        self.size = len;
+    }
+}
+
+/// Parses a number with an optional k, m or g suffix, as in the options of tmpfs(5).
+fn parse_size(value: &str) -> Result<u64, Errno> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    let number: u64 = digits.parse().map_err(|_| EINVAL)?;
+    number.checked_mul(unit).ok_or(EINVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let fs = MemFs::new();
+        let node = fs.create("/f", 0o666).unwrap();
+        assert_eq!(node.stat().mode, S_IFREG | 0o644);
+        assert_eq!(fs.create("/f", 0o666).err(), Some(EEXIST));
+        assert_eq!(fs.create("/f/g", 0o666).err(), Some(ENOTDIR));
+
+        node.write_at(2, b"ab").unwrap();
+        assert_eq!(node.read_at(0, 16).unwrap(), b"\0\0ab");
+        node.truncate(1).unwrap();
+        assert_eq!(fs.lookup("/f").unwrap().stat().size, 1);
+    }
+
+    #[test]
+    fn test_dirs() {
+        let fs = MemFs::new();
+        fs.mkdir("/a", 0o777).unwrap();
+        fs.create("/a/f", 0o666).unwrap();
+        assert_eq!(fs.mkdir("/a", 0o777), Err(EEXIST));
+        assert_eq!(fs.rmdir("/a"), Err(ENOTEMPTY));
+        assert_eq!(fs.unlink("/a"), Err(EISDIR));
+        assert_eq!(fs.rmdir("/"), Err(EBUSY));
+        assert_eq!(fs.lookup("/").unwrap().stat().nlink, 3);
+
+        assert_eq!(fs.rename("/a", "/a/b"), Err(EINVAL));
+        fs.mkdir("/b", 0o777).unwrap();
+        assert_eq!(fs.rename("/b", "/a"), Err(ENOTEMPTY));
+        fs.rename("/a", "/b").unwrap();
+        fs.unlink("/b/f").unwrap();
+        fs.rmdir("/b").unwrap();
+        assert_eq!(fs.lookup("/b").err(), Some(ENOENT));
+        assert_eq!(fs.statfs().files, 1);
+    }
+
+    #[test]
+    fn test_tmpfs() {
+        let fs = MemFs::tmpfs("size=8k,nr_inodes=3,mode=755").unwrap();
+        assert_eq!(fs.lookup("/").unwrap().stat().mode, S_IFDIR | 0o755);
+        assert_eq!(MemFs::tmpfs("uid=0").err(), Some(EINVAL));
+
+        // Holes take no space.
+        let node = fs.create("/f", 0o666).unwrap();
+        node.write_at(1 << 20, b"x").unwrap();
+        assert_eq!(node.stat().size, (1 << 20) + 1);
+        assert_eq!(node.read_at(4095, 2).unwrap(), b"\0\0");
+        assert_eq!(node.write_at(0, &[1; 4097]), Err(ENOSPC));
+        node.truncate(1).unwrap();
+        node.write_at(0, &[1; 4097]).unwrap();
+        assert_eq!(fs.statfs().bfree, 0);
+        node.truncate(2).unwrap();
+        node.truncate(4096).unwrap();
+        assert_eq!(node.read_at(0, 4).unwrap(), b"\x01\x01\0\0");
+        assert_eq!(fs.statfs().bfree, 1);
+
+        fs.symlink("/l", "f").unwrap();
+        assert_eq!(fs.lookup("/l").unwrap().readlink().unwrap(), "f");
+        assert_eq!(fs.mkdir("/d", 0o777), Err(ENOSPC));
+        // Open files keep their inode.
+        fs.unlink("/f").unwrap();
+        assert_eq!(fs.statfs().ffree, 0);
+        drop(node);
+        assert_eq!(fs.statfs().ffree, 1);
+        fs.mkdir("/d", 0o777).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    errno::Errno::{self, EBUSY, EINVAL},
+    vfs::{self, FileSystem, StatFs},
+};
+
+// Flags of mount(), see mount(2).
+pub const MS_RDONLY: u32 = 1;
+pub const MS_REMOUNT: u32 = 32;
+
+/// Flag of statfs() for read-only mounts, see statvfs(3).
+const ST_RDONLY: u32 = 1;
+
+/// Filesystems mounted in the tree, see mount(8).
+pub struct MountTable {
+    // In mount order.
+    mounts: RefCell<Vec<Rc<Mount>>>,
+    next_dev: RefCell<u32>,
+}
+
+/// A filesystem mounted on a directory.
+pub struct Mount {
+    // Device number reported by stat() for everything in the mount.
+    pub dev: u32,
+    pub source: String,
+    // Absolute path of the mount point.
+    pub target: String,
+    pub fstype: String,
+    pub fs: Rc<dyn FileSystem>,
+    flags: RefCell<u32>,
+}
+
+impl MountTable {
+    pub fn new() -> Self {
+        Self {
+            mounts: RefCell::new(Vec::new()),
+            next_dev: RefCell::new(1),
+        }
+    }
+
+    /// Returns the mount containing path, and path relative to its root.
+    ///
+    /// The mount point closest to path wins, comparing whole components.
+    pub fn resolve(&self, path: &str) -> (Rc<Mount>, String) {
+        let mount = self
+            .mounts
+            .borrow()
+            .iter()
+            .filter(|mount| contains(&mount.target, path))
+            .max_by_key(|mount| mount.target.len())
+            .cloned()
+            .expect("no root filesystem");
+        let rel = mount.relative(path);
+        (mount, rel)
+    }
+
+    /// Adds a mount, failing with EBUSY if something is already mounted on target.
+    pub fn add(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        fs: Rc<dyn FileSystem>,
+        flags: u32,
+    ) -> Result<(), Errno> {
+        if self.get(target).is_some() {
+            return Err(EBUSY);
+        }
+        let dev = self.next_dev.replace_with(|dev| *dev + 1);
+        self.mounts.borrow_mut().push(Rc::new(Mount {
+            dev,
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: fstype.to_string(),
+            fs,
+            flags: RefCell::new(flags & MS_RDONLY),
+        }));
+        Ok(())
+    }
+
+    /// Removes the mount on target, see umount(2).
+    ///
+    /// Fails with EBUSY if files are open in it or other filesystems are mounted in it, unless
+    /// force is set. The root filesystem cannot be unmounted.
+    pub fn remove(&self, target: &str, force: bool) -> Result<(), Errno> {
+        let mount = self.get(target).ok_or(EINVAL)?;
+        if target == "/" {
+            return Err(EBUSY);
+        }
+        let mut mounts = self.mounts.borrow_mut();
+        let nested = mounts
+            .iter()
+            .any(|other| other.target != target && contains(target, &other.target));
+        // One reference is held by the table and one by mount above.
+        if nested || (!force && Rc::strong_count(&mount) > 2) {
+            return Err(EBUSY);
+        }
+        mounts.retain(|other| !Rc::ptr_eq(other, &mount));
+        Ok(())
+    }
+
+    /// Returns the mount on target.
+    pub fn get(&self, target: &str) -> Option<Rc<Mount>> {
+        self.mounts
+            .borrow()
+            .iter()
+            .find(|mount| mount.target == target)
+            .cloned()
+    }
+}
+
+/// Formats the table like /proc/mounts, see fstab(5).
+impl fmt::Display for MountTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for mount in self.mounts.borrow().iter() {
+            let options = if mount.read_only() { "ro" } else { "rw" };
+            writeln!(
+                f,
+                "{} {} {} {} 0 0",
+                mount.source, mount.target, mount.fstype, options
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Mount {
+    pub fn read_only(&self) -> bool {
+        *self.flags.borrow() & MS_RDONLY != 0
+    }
+
+    /// Changes the flags of an existing mount, see MS_REMOUNT.
+    pub fn set_flags(&self, flags: u32) {
+        self.flags.replace(flags & MS_RDONLY);
+    }
+
+    /// Returns an absolute path inside the mount relative to its root.
+    pub fn relative(&self, path: &str) -> String {
+        let rel = path.strip_prefix(&self.target).unwrap_or(path);
+        vfs::join("/", rel)
+    }
+
+    pub fn statfs(&self) -> StatFs {
+        let mut statfs = self.fs.statfs();
+        if self.read_only() {
+            statfs.flags |= ST_RDONLY;
+        }
+        statfs
+    }
+}
+
+/// Whether path is dir or inside it.
+fn contains(dir: &str, path: &str) -> bool {
+    match path.strip_prefix(dir) {
+        Some(rest) => dir == "/" || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+
+    fn table() -> MountTable {
+        let table = MountTable::new();
+        for target in ["/", "/bin", "/tmp", "/tmp/a"] {
+            table
+                .add("none", target, "ramfs", Rc::new(MemFs::new()), 0)
+                .unwrap();
+        }
+        table
+    }
+
+    #[test]
+    fn test_resolve() {
+        let table = table();
+        let resolve = |path| {
+            let (mount, rel) = table.resolve(path);
+            (mount.target.clone(), rel)
+        };
+        assert_eq!(resolve("/"), ("/".into(), "/".into()));
+        assert_eq!(resolve("/binx/y"), ("/".into(), "/binx/y".into()));
+        assert_eq!(resolve("/bin/ls"), ("/bin".into(), "/ls".into()));
+        assert_eq!(resolve("/tmp/a"), ("/tmp/a".into(), "/".into()));
+        assert_eq!(resolve("/tmp/ab"), ("/tmp".into(), "/ab".into()));
+        assert_eq!(
+            table.to_string().lines().nth(1),
+            Some("none /bin ramfs rw 0 0")
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let table = table();
+        assert_eq!(table.remove("/", false), Err(EBUSY));
+        assert_eq!(table.remove("/home", false), Err(EINVAL));
+        assert_eq!(table.remove("/tmp", true), Err(EBUSY));
+
+        let (mount, _) = table.resolve("/bin/ls");
+        assert_eq!(table.remove("/bin", false), Err(EBUSY));
+        drop(mount);
+        table.remove("/bin", false).unwrap();
+        assert_eq!(table.resolve("/bin/ls").1, "/bin/ls");
+        table.remove("/tmp/a", false).unwrap();
+        table.remove("/tmp", false).unwrap();
+    }
+}
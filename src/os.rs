@@ -1,64 +1,190 @@
 use std::rc::Rc;
 
-use js_sys::{Error, Reflect};
-use wasm_bindgen::JsValue;
+use js_sys::{Array, Error, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
 use crate::{
-    async_io::STDOUT, compilation_mode::COMPILATION_MODE, js, proc::ProcessManager, term::Terminal,
+    async_io::AsyncBuffer,
+    binfs::{self, BinFs},
+    compilation_mode::COMPILATION_MODE,
+    fd::{STDERR, STDOUT},
+    js,
+    proc::ProcessManager,
+    snapshot,
+    storage::{MemoryStorage, Storage},
+    tar,
+    term::{Output, Terminal},
+    vfs,
 };
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Scratch filesystems mounted at boot, and their options, see tmpfs(5).
+const TMPFS: [(&str, &str); 2] = [("/run", "mode=755,size=10%"), ("/tmp", "mode=1777")];
+
+/// Directory kept in storage across boots.
+const HOME: &str = "/home/snail";
+
+/// Where programs are served from: JS modules along with the manifest under BIN_URL, e.g.
+/// /bin/busybox.js, and the Wasm binaries they load under WASM_URL, named after their content ID.
+const BIN_URL: &str = "/bin";
+const WASM_URL: &str = "/usr/wasm";
 
 // TODO:
 //
 // - users: simple user/group management
-// - mnt: simple mount point management
-// - binfs: read-only fs mounted at /bin
-// - other signals
-//
-// TODO: Structure the virtual filesystem like so:
-// /bin/busybox is the JS binary without any extension
-// /usr/wasm/cid.wasm is the WASM binary that it loads, where "cid" is the content ID.
 
 pub struct OS {
     proc: Rc<ProcessManager>,
-    term: Terminal,
+    term: Rc<Terminal>,
+    // SGR parameters applied to standard error output, e.g. "31" for red.
+    stderr_style: Option<String>,
+    storage: Box<dyn Storage>,
+    rootfs: Option<Rootfs>,
+}
+
+/// Tar archive unpacked into the root filesystem at boot.
+enum Rootfs {
+    Archive(Vec<u8>),
+    // Fetched at boot.
+    Url(String),
 }
 
 impl OS {
     pub fn new(config: JsValue) -> Result<Self, Error> {
         js::p_defer_init(Reflect::get(&config, &"pDefer".into())?.into());
 
-        let proc = Rc::new(ProcessManager::new());
+        let random_seed = Reflect::get(&config, &"randomSeed".into())?
+            .as_f64()
+            .map(|seed| seed as u64);
+        let proc = ProcessManager::new(random_seed);
         let term = Terminal::new(
             Reflect::get(&config, &"Terminal".into())?.into(),
             Reflect::get(&config, &"FitAddon".into())?.into(),
             &proc,
         )?;
+        let stderr_style = Reflect::get(&config, &"stderrStyle".into())?.as_string();
+        let storage: Box<dyn Storage> = match Reflect::get(&config, &"storage".into())? {
+            storage if storage.is_object() => Box::new(js::HostStorage::from(storage)),
+            _ => Box::<MemoryStorage>::default(),
+        };
+        let rootfs = match Reflect::get(&config, &"rootfs".into())?.dyn_into::<Uint8Array>() {
+            Ok(archive) => Some(Rootfs::Archive(archive.to_vec())),
+            Err(_) => Reflect::get(&config, &"rootfsUrl".into())?
+                .as_string()
+                .map(Rootfs::Url),
+        };
 
-        Ok(Self { proc, term })
+        Ok(Self {
+            proc,
+            term: Rc::new(term),
+            stderr_style,
+            storage,
+            rootfs,
+        })
     }
 
-    pub async fn boot(&mut self) -> Result<(), Error> {
+    pub async fn boot(&self) -> Result<(), Error> {
         self.term.open()?;
 
         self.term
             .writeln(&format!("_@/\" OS {}-{}, booting…", VERSION, COMPILATION_MODE).as_bytes())?;
         self.term.writeln(b"")?;
 
-        let pid = self.proc.exec("/bin/busybox", &["hush"]).await?;
-        self.term.attach_to(pid);
-
-        // TODO: Merge stdout and stderr!
-        // For now, let's just display the output of stdout.
-        while let Some(chunks) = self.proc.wait_data(pid, STDOUT).await? {
-            for chunk in chunks.into_iter() {
-                self.term.writeln(chunk.as_slice())?;
+        // The manifest is revalidated on every boot, so that a deployment replaces all programs at
+        // once, while unchanged binaries keep being served from the HTTP cache.
+        let manifest = js::fetch(&format!("{}/{}", BIN_URL, binfs::MANIFEST), "no-cache").await?;
+        let bin = BinFs::new(
+            BIN_URL,
+            WASM_URL,
+            &String::from_utf8_lossy(&manifest.to_vec()),
+        )?;
+        self.proc
+            .vfs()
+            .mount(BIN_URL, "/bin", "binfs", Rc::new(bin), 0)?;
+        for (target, options) in TMPFS {
+            self.proc.mount_fs("tmpfs", target, "tmpfs", 0, options)?;
+        }
+        if let Some(rootfs) = &self.rootfs {
+            let unpack = |archive: &[u8]| Ok(tar::unpack(self.proc.vfs(), "/", archive)?);
+            let result = match rootfs {
+                Rootfs::Archive(archive) => unpack(archive),
+                Rootfs::Url(url) => js::fetch(url, "default")
+                    .await
+                    .and_then(|archive| unpack(&archive.to_vec())),
+            };
+            if let Err(err) = result {
+                let message = format!("rootfs: cannot unpack: {}", String::from(err.message()));
+                self.term.writeln(message.as_bytes())?;
             }
         }
+        // Changes made to the home directory take precedence over the root filesystem.
+        match self.storage.load() {
+            Ok(Some(snapshot)) => {
+                if let Err(errno) = snapshot::restore(self.proc.vfs(), HOME, &snapshot) {
+                    let message = format!("snapshot: cannot restore {}: {}", HOME, errno);
+                    self.term.writeln(message.as_bytes())?;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let message = format!("snapshot: cannot load: {}", String::from(err.message()));
+                self.term.writeln(message.as_bytes())?;
+            }
+        }
+
+        let pid = self.proc.exec("/bin/busybox", &["hush"]).await?;
+        self.proc.tty_attach(pid)?;
+
+        // Both streams are forwarded as soon as data arrives, so they end up on the terminal in
+        // arrival order.
+        let stdout = Self::forward(self.term.clone(), self.proc.channel(pid, STDOUT)?, None);
+        let stderr = Self::forward(
+            self.term.clone(),
+            self.proc.channel(pid, STDERR)?,
+            self.stderr_style.clone(),
+        );
+        JsFuture::from(Promise::all(&Array::of2(&stdout, &stderr))).await?;
 
         let exit_code = self.proc.wait_quit(pid).await?;
+        self.save()?;
         self.term.writeln(b"")?;
         self.term.writeln(&format!("EXIT {}", exit_code).as_bytes())
     }
+
+    /// Packs the file or directory at path into a tar archive, see tar::pack().
+    pub fn pack(&self, path: &str) -> Result<Vec<u8>, Error> {
+        Ok(tar::pack(self.proc.vfs(), &vfs::join("/", path))?)
+    }
+
+    /// Saves the home directory to storage, and returns the snapshot.
+    pub fn save(&self) -> Result<Vec<u8>, Error> {
+        let snapshot = snapshot::export(self.proc.vfs(), HOME)?;
+        self.storage.save(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Copies the output written to a channel to the terminal, until the channel is closed.
+    fn forward(term: Rc<Terminal>, channel: Rc<AsyncBuffer>, style: Option<String>) -> Promise {
+        future_to_promise(async move {
+            let mut output = Output::new();
+            let write = |data: Vec<u8>| match style.as_deref() {
+                Some(_) if data.is_empty() => Ok(()),
+                Some(style) => term.write_styled(&data, style),
+                None => term.write(&data),
+            };
+            while let Some(chunks) = channel.consume_all().await.map_err(Error::from)? {
+                // Writers are blocked while the terminal is catching up or output is stopped.
+                while let Some(ready) = term.ready()? {
+                    channel.stop();
+                    JsFuture::from(ready).await?;
+                }
+                channel.start();
+                write(output.process(&chunks.concat()))?;
+            }
+            write(output.flush())?;
+            Ok(JsValue::undefined())
+        })
+    }
 }
@@ -0,0 +1,130 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+// Events for poll(), see poll(2).
+pub const POLLIN: i16 = 0x001;
+pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+
+// Events for epoll, the same bits as for poll(), see epoll_ctl(2).
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+// Values of the op argument of epoll_ctl().
+pub const EPOLL_CTL_ADD: i32 = 1;
+pub const EPOLL_CTL_DEL: i32 = 2;
+pub const EPOLL_CTL_MOD: i32 = 3;
+
+/// How deep epoll instances may watch each other, as on Linux.
+pub const EPOLL_MAX_NESTS: usize = 4;
+
+/// Size of a struct epoll_event: the events, then the data aligned to 8 bytes.
+pub const EPOLL_EVENT_SIZE: u32 = 16;
+
+/// Interest list of an epoll instance.
+///
+/// Entries are keyed by file descriptor, and are dropped once the file descriptor is closed.
+/// EPOLLET is accepted, but readiness is always level-triggered.
+pub struct Epoll {
+    interest: RefCell<BTreeMap<u32, Interest>>,
+}
+
+/// Events a file descriptor is watched for, and the data reported along with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interest {
+    pub events: u32,
+    pub data: u64,
+}
+
+impl Epoll {
+    pub fn new() -> Self {
+        Self {
+            interest: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Adds a file descriptor, returning false if it is already there.
+    pub fn add(&self, fd: u32, interest: Interest) -> bool {
+        let mut map = self.interest.borrow_mut();
+        if map.contains_key(&fd) {
+            return false;
+        }
+        map.insert(fd, interest);
+        true
+    }
+
+    /// Changes the events of a file descriptor, returning false if it is not there.
+    pub fn modify(&self, fd: u32, interest: Interest) -> bool {
+        match self.interest.borrow_mut().get_mut(&fd) {
+            Some(entry) => {
+                *entry = interest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes a file descriptor, returning false if it is not there.
+    pub fn remove(&self, fd: u32) -> bool {
+        self.interest.borrow_mut().remove(&fd).is_some()
+    }
+
+    /// Returns the entries that are watched for any events.
+    pub fn armed(&self) -> Vec<(u32, Interest)> {
+        self.interest
+            .borrow()
+            .iter()
+            .filter(|(_, interest)| interest.events & !(EPOLLONESHOT | EPOLLET) != 0)
+            .map(|(fd, interest)| (*fd, *interest))
+            .collect()
+    }
+
+    /// Stops watching a file descriptor after an event with EPOLLONESHOT was reported.
+    pub fn reported(&self, fd: u32) {
+        if let Some(entry) = self.interest.borrow_mut().get_mut(&fd) {
+            if entry.events & EPOLLONESHOT != 0 {
+                entry.events = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IN: Interest = Interest {
+        events: POLLIN as u32,
+        data: 42,
+    };
+
+    #[test]
+    fn test_ctl() {
+        let epoll = Epoll::new();
+        assert!(epoll.add(3, IN));
+        assert!(!epoll.add(3, IN));
+        assert!(!epoll.modify(4, IN));
+        assert!(epoll.remove(3));
+        assert!(!epoll.remove(3));
+    }
+
+    #[test]
+    fn test_oneshot() {
+        let epoll = Epoll::new();
+        let oneshot = Interest {
+            events: IN.events | EPOLLONESHOT,
+            ..IN
+        };
+        epoll.add(3, oneshot);
+        epoll.add(4, IN);
+
+        epoll.reported(3);
+        epoll.reported(4);
+        assert_eq!(epoll.armed(), vec![(4, IN)]);
+
+        // Rearmed with EPOLL_CTL_MOD.
+        epoll.modify(3, oneshot);
+        assert_eq!(epoll.armed().len(), 2);
+    }
+}
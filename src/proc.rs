@@ -1,329 +1,3428 @@
-use std::{cell::RefCell, collections::HashMap, ops::Deref, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    rc::{Rc, Weak},
+};
 
-use js_sys::{Error, Function, JsString, Object, Promise, Reflect};
+use js_sys::{Array, Date, Error, Function, JsString, Object, Promise, Reflect, Uint8Array};
 use wasm_bindgen::{closure::Closure, JsValue};
-use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
 use crate::{
-    async_io::{AsyncIo, STDERR, STDIN, STDOUT},
-    binfs::BinFs,
+    async_io::{Access, AsyncBuffer, PIPE_BUF, PIPE_MAX_SIZE},
+    devfs::{DevFs, Random, CONSOLE_DEV, TTY_DEV},
+    errno::Errno::{
+        self, EAGAIN, EBADF, EBUSY, ECHILD, EEXIST, EINTR, EINVAL, EIO, EMFILE, ENODEV, ENOENT,
+        ENOEXEC, ENOMEM, ENOTDIR, ENOTTY, ENXIO, EPERM, EPIPE, ERANGE, ESPIPE, ESRCH,
+    },
+    fd::{
+        FdTable, OpenFile, Target, MAX_FDS, O_ACCMODE, O_APPEND, O_CLOEXEC, O_NONBLOCK, O_RDONLY,
+        O_WRONLY, STDIN, STDOUT,
+    },
     js,
+    memfs::MemFs,
+    mount::MS_REMOUNT,
+    poll::{
+        Epoll, Interest, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, EPOLL_EVENT_SIZE,
+        EPOLL_MAX_NESTS, POLLERR, POLLHUP, POLLIN, POLLNVAL,
+    },
+    procfs::{self, ProcFs},
+    signal::{
+        self, Action, DefaultAction, Disposition, SigSet, Signal, Signals, SA_NOCLDSTOP, SIGCHLD,
+        SIGCONT, SIGHUP, SIGKILL, SIGPIPE, SIGTTIN, SIGTTOU, SIG_DFL,
+    },
+    vfs::{
+        self, FileSystem, Location, Program, Stat, StatFs, Vfs, BLKSIZE, S_IFCHR, S_IFIFO, S_IFMT,
+    },
 };
 
 pub type Pid = u32;
 
+/// The first process started, which inherits all orphans.
+const INIT_PID: Pid = 1;
+
+// Commands accepted by fcntl().
+const F_DUPFD: i32 = 0;
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+const F_SETPIPE_SZ: i32 = 1031;
+const F_GETPIPE_SZ: i32 = 1032;
+const FD_CLOEXEC: i32 = 1;
+
+// Special dirfd and flags of the *at() syscalls.
+const AT_FDCWD: i32 = -100;
+const AT_SYMLINK_NOFOLLOW: i32 = 0x100;
+const AT_REMOVEDIR: i32 = 0x200;
+
+// Flags of umount2().
+const MNT_FORCE: i32 = 1;
+const MNT_DETACH: i32 = 2;
+const UMOUNT_NOFOLLOW: i32 = 8;
+
+// Size of struct os_stat, see write_stat().
+const STAT_SIZE: usize = 64;
+
+// Size of struct os_statfs, see write_statfs().
+const STATFS_SIZE: usize = 48;
+
+/// Filesystems mounted at boot: source, target and type.
+///
+/// /bin is mounted by the OS, once it has fetched the manifest of the programs.
+const MOUNTS: [(&str, &str, &str); 2] = [("dev", "/dev", "devtmpfs"), ("proc", "/proc", "proc")];
+
+// Options accepted by wait4().
+const WNOHANG: i32 = 1;
+const WUNTRACED: i32 = 2;
+const WCONTINUED: i32 = 8;
+
+// Wait status of a continued process.
+const WSTATUS_CONTINUED: i32 = 0xffff;
+
+// Size of struct rusage: two 64-bit timevals followed by 14 + 16 reserved longs.
+const RUSAGE_SIZE: usize = 2 * 16 + 30 * 4;
+
 pub struct ProcessManager {
+    this: Weak<Self>,
     map: RefCell<HashMap<Pid, Rc<Process>>>,
     next_pid: RefCell<Pid>,
-    binfs: BinFs,
+    // The terminal, if it is the controlling terminal of a session.
+    tty: RefCell<Option<Tty>>,
+    vfs: Vfs,
+    // Time of boot, in milliseconds since the epoch.
+    booted: f64,
+    // Source of /dev/random and /dev/urandom.
+    random: Rc<Random>,
+    // Wasm binaries by content ID, once verified against it.
+    binaries: RefCell<HashMap<String, Uint8Array>>,
+}
+
+/// Job control state of the terminal.
+#[derive(Clone)]
+struct Tty {
+    // Session leader.
+    session: Pid,
+    // Foreground process group, whose members may read from the terminal and receive the signals
+    // generated by control characters.
+    pgrp: Pid,
+    // Channel receiving the terminal input.
+    input: Rc<AsyncBuffer>,
+    // Input the channel had no space for, written as it is read.
+    backlog: Rc<RefCell<Vec<u8>>>,
+    // Channel forwarded to the terminal output, written to through /dev/tty.
+    output: Rc<AsyncBuffer>,
 }
 
 struct Process {
     id: Pid,
-    state: Rc<RefCell<State>>,
-    module: Rc<RefCell<Option<js::Module>>>,
-    io: Rc<AsyncIo>,
+    // Parent pid; 0 means the process was started by the kernel, see wait_quit().
+    ppid: RefCell<Pid>,
+    pgid: RefCell<Pid>,
+    // Session id, the pid of the session leader.
+    sid: RefCell<Pid>,
+    // Children that have not been reaped yet.
+    children: RefCell<BTreeSet<Pid>>,
+    state: RefCell<State>,
+    // Stop or continue status not yet reported by wait4().
+    report: RefCell<Option<i32>>,
+    // Deferred object, waiting for a child to change state.
+    child_event: RefCell<Option<js::Deferred>>,
+    signals: RefCell<Signals>,
+    // Deferred object, interrupting a blocking syscall when a signal arrives, and the token it
+    // resolves with.
+    interrupt: RefCell<Option<(js::Deferred, JsValue)>>,
+    // Deferred object, waiting for a stopped process to be continued.
+    resume: RefCell<Option<js::Deferred>>,
+    usage: RefCell<Usage>,
+    fds: FdTable,
+    // Path of the executable loaded by the last execve(), and the arguments and environment it
+    // was given, see /proc/<pid>/exe.
+    exe: RefCell<Option<String>>,
+    cmdline: RefCell<Vec<String>>,
+    environ: RefCell<Vec<String>>,
+    // Absolute path of the working directory.
+    cwd: RefCell<String>,
+    // The program image running in this process.
+    // A vforked child has no image of its own until it calls execve().
+    image: RefCell<Option<Image>>,
+    // Images replaced by execve(). Their callbacks must outlive the module, so they are only
+    // dropped when the process is reaped.
+    retired: RefCell<Vec<Image>>,
+}
+
+/// A program image: an Emscripten module instance and the callbacks wired into it.
+struct Image {
     promise: Promise,
+    module: Rc<RefCell<Option<js::Module>>>,
 
     #[allow(dead_code)]
     callbacks: Callbacks,
 }
 
+/// Pids on whose behalf an image is currently running.
+///
+/// The bottom entry is the process owning the image. A vforked child runs inside its parent's
+/// image, so vfork() pushes the child's pid on top, and execve() or _exit() pops it. Once the
+/// image is replaced by execve(), the context is cleared and any further calls are ignored.
+type Context = Rc<RefCell<Vec<Pid>>>;
+
 struct Callbacks {
-    print: Closure<dyn Fn(String)>,
-    print_err: Closure<dyn Fn(String)>,
     exit: Closure<dyn FnMut(i32)>,
 
     // OS init:
     set_module: Closure<dyn Fn(js::Module)>,
-    init_module: Closure<dyn Fn(Object, Object)>,
+    init_module: Closure<dyn Fn(Object)>,
     init_runtime: Closure<dyn Fn()>,
 
     // Mocked syscalls & library functions:
+    getpid: Closure<dyn Fn() -> Pid>,
+    getppid: Closure<dyn Fn() -> Pid>,
     vfork: Closure<dyn Fn() -> Promise>, // -> pid_t = u32
-    waitpid: Closure<dyn Fn(u32, u32, i32) -> Promise>, // -> pid_t = u32
-    wait4: Closure<dyn Fn(u32, u32, i32, u32) -> Promise>, // -> pid_t = u32
-    read: Closure<dyn Fn(i32, u32, u32) -> Promise>, // -> ssize_t = usize
-    write: Closure<dyn Fn(i32, u32, u32) -> u32>,
+    vfork_exit: Closure<dyn Fn(i32)>,
+    execve: Closure<dyn Fn(u32, u32, u32) -> Promise>, // -> int = i32
+    waitpid: Closure<dyn Fn(i32, u32, i32) -> Promise>, // -> pid_t = i32
+    wait4: Closure<dyn Fn(i32, u32, i32, u32) -> Promise>, // -> pid_t = i32
+    read: Closure<dyn Fn(i32, u32, u32) -> Promise>,   // -> ssize_t = usize
+    write: Closure<dyn Fn(i32, u32, u32) -> Promise>,  // -> ssize_t
+    pipe: Closure<dyn Fn(u32, i32) -> i32>,
+    poll: Closure<dyn Fn(u32, u32, f64) -> Promise>, // -> int
+    epoll_create: Closure<dyn Fn(i32) -> i32>,
+    epoll_ctl: Closure<dyn Fn(i32, i32, i32, u32) -> i32>,
+    epoll_wait: Closure<dyn Fn(i32, u32, i32, f64) -> Promise>, // -> int
+
+    // File descriptors:
+    close: Closure<dyn Fn(i32) -> i32>,
+    dup3: Closure<dyn Fn(i32, i32, i32) -> i32>,
+    fcntl: Closure<dyn Fn(i32, i32, i32) -> i32>,
+    isatty: Closure<dyn Fn(i32) -> i32>,
+    nanosleep: Closure<dyn Fn(f64, u32) -> Promise>, // -> int = i32
+
+    // Files:
+    open: Closure<dyn Fn(i32, u32, i32, u32) -> i32>,
+    lseek: Closure<dyn Fn(i32, i64, i32, u32) -> i32>,
+    fstat: Closure<dyn Fn(i32, u32) -> i32>,
+    stat: Closure<dyn Fn(i32, u32, u32, i32) -> i32>,
+    readlink: Closure<dyn Fn(i32, u32, u32, u32) -> i32>,
+    getdents: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    ftruncate: Closure<dyn Fn(i32, i64) -> i32>,
+    unlink: Closure<dyn Fn(i32, u32, i32) -> i32>,
+    mkdir: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    symlink: Closure<dyn Fn(u32, i32, u32) -> i32>,
+    rename: Closure<dyn Fn(i32, u32, i32, u32) -> i32>,
+    chdir: Closure<dyn Fn(i32, u32) -> i32>,
+    getcwd: Closure<dyn Fn(u32, u32) -> i32>,
+    access: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    chmod: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    utimens: Closure<dyn Fn(i32, u32, f64, f64) -> i32>,
+    statfs: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    mount: Closure<dyn Fn(u32, u32, u32, u32, u32) -> i32>,
+    umount: Closure<dyn Fn(u32, i32) -> i32>,
+
+    // Signals:
+    kill: Closure<dyn Fn(i32, i32) -> i32>,
+    sigaction: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    sigprocmask: Closure<dyn Fn(i32, u32, u32) -> i32>,
+    sigpending: Closure<dyn Fn(u32) -> i32>,
+    sigsuspend: Closure<dyn Fn(u32, u32) -> Promise>, // -> int = i32
+    signal_deliver: Closure<dyn Fn(u32) -> Promise>,  // -> int = i32
+    sigreturn: Closure<dyn Fn()>,
+
+    // Process groups, sessions & job control:
+    getpgid: Closure<dyn Fn(Pid) -> i32>,
+    setpgid: Closure<dyn Fn(Pid, Pid) -> i32>,
+    getsid: Closure<dyn Fn(Pid) -> i32>,
+    setsid: Closure<dyn Fn() -> i32>,
+    tcgetpgrp: Closure<dyn Fn() -> i32>,
+    tcsetpgrp: Closure<dyn Fn(Pid) -> i32>,
 }
 
+/// Process state.
+///
+/// The deferred object resolves once the process terminates.
 pub enum State {
     Running(js::Deferred),
+    Stopped(js::Deferred),
     Exited(i32),
+    Signaled(Signal),
+}
+
+/// Outcome of delivering pending signals at a syscall boundary.
+pub enum Delivery {
+    /// Nothing left to deliver.
+    Done,
+    /// The handler has to be run by the module.
+    Handle(Signal, Action),
+    /// The process has been terminated.
+    Terminated(Signal),
+}
+
+/// Wall clock accounting, in milliseconds since the epoch.
+///
+/// There is no way to measure CPU time in the browser, so any time the process is not blocked in
+/// a syscall is counted as user time.
+struct Usage {
+    started: f64,
+    ended: Option<f64>,
+    // Time spent blocked in syscalls.
+    blocked: f64,
+    // User time of waited-for children, including their own waited-for children.
+    children: f64,
+}
+
+/// A child whose state change has been collected by wait4().
+pub struct Waited {
+    pub pid: Pid,
+    pub status: i32,
+    // User time in milliseconds, including that of waited-for children.
+    pub utime: f64,
+}
+
+/// A snapshot of a process, as shown in /proc/<pid>.
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub ppid: Pid,
+    pub pgid: Pid,
+    pub sid: Pid,
+    // Foreground process group of the terminal, if it is the controlling terminal of the session.
+    pub tty: Option<Pid>,
+    // One of R (running), S (blocked in a syscall), T (stopped) or Z (zombie), see ps(1).
+    pub state: char,
+    pub cmdline: Vec<String>,
+    pub environ: Vec<String>,
+    pub cwd: String,
+    pub exe: Option<String>,
+    // Open file descriptors and what they refer to, see OpenFile::link().
+    pub fds: Vec<(u32, String)>,
+    // Times in milliseconds: start since the epoch, user time of the process and of its
+    // waited-for children.
+    pub started: f64,
+    pub utime: f64,
+    pub cutime: f64,
+    // Size of the program image's memory in bytes.
+    pub memory: u64,
+    pub pending: SigSet,
+    pub blocked: SigSet,
+    pub ignored: SigSet,
+    pub caught: SigSet,
 }
 
 impl ProcessManager {
-    pub fn new() -> Self {
-        Self {
+    /// Creates the process manager and its filesystem tree.
+    ///
+    /// With a random seed, /dev/random and /dev/urandom return the same bytes on every boot.
+    pub fn new(random_seed: Option<u64>) -> Rc<Self> {
+        let processes = Rc::new_cyclic(|this| Self {
+            this: this.clone(),
             map: RefCell::new(HashMap::new()),
             next_pid: RefCell::new(1),
-            binfs: BinFs::new("/bin"),
+            tty: RefCell::new(None),
+            vfs: Vfs::new(Rc::new(MemFs::new())),
+            booted: vfs::now(),
+            random: Rc::new(Random::new(random_seed)),
+            binaries: RefCell::new(HashMap::new()),
+        });
+        for (source, target, fstype) in MOUNTS {
+            processes
+                .mount_fs(source, target, fstype, 0, "")
+                .expect("mount failed");
         }
+        processes
     }
 
-    /// Executes the given binary file.
+    /// Executes the given binary file in a new process, leading a new session.
     /// Once the process has started, returns its pid.
     pub async fn exec(&self, file_path: &str, args: &[&str]) -> Result<Pid, Error> {
-        let resolved_path = self
-            .binfs
-            .resolve(file_path)
-            .ok_or(Error::new(&format!("failed to resolve: {}", file_path)))?;
+        let name = PathBuf::from(file_path)
+            .file_stem()
+            .ok_or(Error::new(&format!("failed to resolve: {}", file_path)))?
+            .to_string_lossy()
+            .to_string();
+        let argv: Vec<String> = std::iter::once(name)
+            .chain(args.iter().map(|arg| arg.to_string()))
+            .collect();
+
+        let pid = self.next_pid();
+        self.map.borrow_mut().insert(
+            pid,
+            Rc::new(Process::new(
+                pid,
+                0,
+                pid,
+                pid,
+                FdTable::stdio(),
+                Signals::new(),
+            )?),
+        );
+
+        if let Err(errno) = self.execve(pid, file_path, &argv, &[]).await {
+            self.map.borrow_mut().remove(&pid);
+            return Err(Error::new(&format!("exec: {}: {}", file_path, errno)));
+        }
 
-        let ctor = js::load_module(&resolved_path.to_string_lossy().to_string()).await?;
+        Ok(pid)
+    }
 
-        let pid: Pid = *self.next_pid.borrow();
-        self.next_pid.replace(pid + 1);
+    /// Creates a child process sharing the parent's open files.
+    ///
+    /// The child has no program image until it calls execve(); until then it runs inside the
+    /// parent's image, just like vfork() on NOMMU systems.
+    pub fn vfork(&self, ppid: Pid) -> Result<Pid, Errno> {
+        let parent = self.get(ppid)?;
 
-        let p = Process::new(
+        let pid = self.next_pid();
+        let child = Process::new(
             pid,
-            ctor,
-            &resolved_path
-                .file_stem()
-                .unwrap() // already validated above
-                .to_string_lossy()
-                .to_string(),
-            args,
-        )?;
-
-        self.map.borrow_mut().insert(pid, Rc::new(p));
+            ppid,
+            *parent.pgid.borrow(),
+            *parent.sid.borrow(),
+            parent.fds.fork(),
+            parent.signals.borrow().fork(),
+        )
+        .map_err(|_| ENOMEM)?;
+        child.exe.replace(parent.exe.borrow().clone());
+        child.cmdline.replace(parent.cmdline.borrow().clone());
+        child.environ.replace(parent.environ.borrow().clone());
+        child.cwd.replace(parent.cwd.borrow().clone());
+        self.map.borrow_mut().insert(pid, Rc::new(child));
+        parent.children.borrow_mut().insert(pid);
 
         Ok(pid)
     }
 
-    /// Writes data to the standard input of a process.
-    pub fn stdin_write(&self, pid: Pid, data: Vec<u8>) -> Result<usize, Error> {
-        self.map
-            .borrow()
-            .get(&pid)
-            .ok_or(Error::new(&format!("no such process: {}", pid)))?
-            .io
-            .write(STDIN, data)
+    /// Replaces the program image of a process with the given binary file.
+    ///
+    /// Fails with ENOENT if there is no such file, or ENOEXEC if it cannot be loaded.
+    pub async fn execve(
+        &self,
+        pid: Pid,
+        file_path: &str,
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+
+        // BusyBox re-executes itself via /proc/self/exe on NOMMU systems, which is a link to the
+        // executable.
+        let location = self.vfs.lookup(&proc.resolve(AT_FDCWD, file_path)?)?;
+        let program = location.program()?;
+        let file_path = location.path;
+
+        let image = self
+            .load(&program)
+            .await
+            .and_then(|(ctor, wasm)| Image::new(self.this.clone(), pid, ctor, &wasm, argv, envp))
+            .map_err(|err| {
+                js::warn(&format!(
+                    "proc: execve: {}: {}",
+                    file_path,
+                    String::from(err.message())
+                ));
+                ENOEXEC
+            })?;
+        proc.exe.replace(Some(file_path));
+        proc.cmdline.replace(argv.to_vec());
+        proc.environ.replace(envp.to_vec());
+        proc.signals.borrow_mut().exec();
+        proc.fds.exec();
+        if let Some(old) = proc.image.replace(Some(image)) {
+            proc.retired.borrow_mut().push(old);
+        }
+
+        Ok(())
     }
 
-    /// Closes the the standard input of a process.
-    pub fn stdin_close(&self, pid: Pid) -> Result<(), Error> {
-        self.map
-            .borrow()
-            .get(&pid)
-            .ok_or(Error::new(&format!("no such process: {}", pid)))?
-            .io
-            .close(STDIN)
+    /// Loads the JS module of a program and its Wasm binary.
+    ///
+    /// Binaries are fetched once per content ID, and rejected unless their hash matches it.
+    async fn load(&self, program: &Program) -> Result<(Function, Uint8Array), Error> {
+        let ctor = js::load_module(&program.module_url).await?;
+        let cached = self.binaries.borrow().get(&program.cid).cloned();
+        let wasm = match cached {
+            Some(wasm) => wasm,
+            None => {
+                // The URL names the contents, so any cached response is still valid.
+                let wasm = js::fetch(&program.wasm_url, "force-cache").await?;
+                let hash = js::sha256(&wasm).await?;
+                if hash != program.cid {
+                    return Err(Error::new(&format!(
+                        "{}: integrity check failed: SHA-256 is {}",
+                        program.wasm_url, hash
+                    )));
+                }
+                self.binaries
+                    .borrow_mut()
+                    .insert(program.cid.clone(), wasm.clone());
+                wasm
+            }
+        };
+        Ok((ctor, wasm))
+    }
+
+    /// Returns the parent pid of a process.
+    pub fn getppid(&self, pid: Pid) -> Result<Pid, Errno> {
+        Ok(*self.get(pid)?.ppid.borrow())
+    }
+
+    /// Returns the process group of a process, the caller's if pid is 0.
+    pub fn getpgid(&self, caller: Pid, pid: Pid) -> Result<Pid, Errno> {
+        let pid = if pid == 0 { caller } else { pid };
+        Ok(*self.get(pid)?.pgid.borrow())
+    }
+
+    /// Returns the session of a process, the caller's if pid is 0.
+    pub fn getsid(&self, caller: Pid, pid: Pid) -> Result<Pid, Errno> {
+        let pid = if pid == 0 { caller } else { pid };
+        Ok(*self.get(pid)?.sid.borrow())
+    }
+
+    /// Moves a process to a process group, see setpgid(2). Errors are errno values.
+    ///
+    /// A pid of 0 means the caller, a pgid of 0 means a group led by the target process. The
+    /// target must be the caller or one of its children, in the same session.
+    pub fn setpgid(&self, caller: Pid, pid: Pid, pgid: Pid) -> Result<(), Errno> {
+        let pid = if pid == 0 { caller } else { pid };
+        let pgid = if pgid == 0 { pid } else { pgid };
+
+        let proc = self.get(pid)?;
+        if pid != caller && *proc.ppid.borrow() != caller {
+            return Err(ESRCH);
+        }
+        let sid = *proc.sid.borrow();
+        if sid == pid || sid != *self.get(caller)?.sid.borrow() {
+            return Err(EPERM);
+        }
+        // Joining an existing group is only allowed within the same session.
+        if pgid != pid && !self.group(pgid).iter().any(|p| *p.sid.borrow() == sid) {
+            return Err(EPERM);
+        }
+
+        proc.pgid.replace(pgid);
+        Ok(())
     }
 
-    /// Waits until a process produces output on a file descriptor.
-    pub async fn wait_data(&self, pid: Pid, fd: u32) -> Result<Option<Vec<Vec<u8>>>, Error> {
+    /// Creates a new session led by the caller, without a controlling terminal, see setsid(2).
+    ///
+    /// Returns the new session id. Errors are errno values.
+    pub fn setsid(&self, caller: Pid) -> Result<Pid, Errno> {
+        let proc = self.get(caller)?;
+        if !self.group(caller).is_empty() {
+            return Err(EPERM);
+        }
+        proc.sid.replace(caller);
+        proc.pgid.replace(caller);
+        Ok(caller)
+    }
+
+    /// Makes the terminal the controlling terminal of the session led by pid.
+    ///
+    /// The process group of pid becomes the foreground process group.
+    pub fn tty_attach(&self, pid: Pid) -> Result<(), Error> {
+        let proc = self.get(pid)?;
+        if *proc.sid.borrow() != pid {
+            return Err(Error::new(&format!(
+                "proc: pid {}: tty: not a session leader",
+                pid
+            )));
+        }
+        let channel = |fd, name| {
+            proc.fds
+                .get(fd)
+                .filter(|file| file.is_tty())
+                .and_then(|file| file.channel_end().map(|(buf, _)| buf.clone()))
+                .ok_or(Error::new(&format!("proc: pid {}: tty: no {}", pid, name)))
+        };
+        self.tty.replace(Some(Tty {
+            session: pid,
+            pgrp: *proc.pgid.borrow(),
+            input: channel(STDIN, "input")?,
+            backlog: Rc::new(RefCell::new(Vec::new())),
+            output: channel(STDOUT, "output")?,
+        }));
+        Ok(())
+    }
+
+    /// Returns the foreground process group, see tcgetpgrp(3). Errors are errno values.
+    pub fn tcgetpgrp(&self, caller: Pid) -> Result<Pid, Errno> {
+        Ok(self.ctty(caller)?.pgrp)
+    }
+
+    /// Changes the foreground process group, see tcsetpgrp(3). Errors are errno values.
+    ///
+    /// Members of a background group get SIGTTOU instead, unless they ignore or block it.
+    pub fn tcsetpgrp(&self, caller: Pid, pgrp: Pid) -> Result<(), Errno> {
+        let tty = self.ctty(caller)?;
+        if !self
+            .group(pgrp)
+            .iter()
+            .any(|p| *p.sid.borrow() == tty.session)
         {
-            self.map
-                .borrow()
-                .get(&pid)
-                .ok_or(Error::new(&format!("no such process: {}", pid)))?
-                .clone()
+            return Err(EPERM);
         }
-        .io
-        .consume_all(fd)
-        .await
+        self.check_background(caller, SIGTTOU)?;
+
+        self.tty.replace(Some(Tty { pgrp, ..tty }));
+        Ok(())
     }
 
-    /// Waits until a process exits, returning its exit code.
-    pub async fn wait_quit(&self, pid: Pid) -> Result<i32, Error> {
-        let proc = self
-            .map
-            .borrow()
-            .get(&pid)
-            .ok_or(Error::new(&format!("no such process: {}", pid)))?
-            .clone();
-        let exit_code = proc.wait().await?;
-        self.map.borrow_mut().remove(&pid);
-        Ok(exit_code)
+    /// Writes terminal input, to be read by the foreground process group.
+    ///
+    /// What the channel has no space for, e.g. a large paste, is kept in order and written as the
+    /// input is read.
+    pub fn tty_write(&self, data: Vec<u8>) -> Result<(), Error> {
+        let tty = self.tty.borrow().clone();
+        let tty = tty.ok_or(Error::new("proc: tty: detached"))?;
+        if !tty.backlog.borrow().is_empty() {
+            tty.backlog.borrow_mut().extend(data);
+            return Ok(());
+        }
+        let count = tty.input.write(data.clone())?;
+        if count < data.len() {
+            tty.backlog.borrow_mut().extend(&data[count..]);
+            let _ = future_to_promise(Self::tty_flush(tty));
+        }
+        Ok(())
     }
-}
 
-impl Process {
-    fn new(id: Pid, ctor: Function, name: &str, arguments: &[&str]) -> Result<Self, Error> {
-        let state = Rc::new(RefCell::new(State::Running(js::deferred()?)));
-        let module = Rc::new(RefCell::new(None));
-        let io = Rc::new(AsyncIo::new()?);
+    /// Writes the backlog of terminal input as the channel makes room for it.
+    ///
+    /// Input still queued when the channel is closed is discarded.
+    async fn tty_flush(tty: Tty) -> Result<JsValue, JsValue> {
+        while !tty.backlog.borrow().is_empty() {
+            if let Some(promise) = tty.input.writable(1)? {
+                JsFuture::from(promise).await?;
+            }
+            let chunk = {
+                let backlog = tty.backlog.borrow();
+                backlog[..tty.input.space().min(backlog.len())].to_vec()
+            };
+            match tty.input.write(chunk) {
+                Ok(count) => {
+                    tty.backlog.borrow_mut().drain(..count);
+                }
+                Err(_) => tty.backlog.borrow_mut().clear(),
+            }
+        }
+        Ok(JsValue::undefined())
+    }
 
-        let callbacks = Callbacks::new(&state, &module, &io);
-        let promise: Promise = ctor
-            .call1(
-                &JsValue::null(),
-                &js::Builder::new()
-                    .set("thisProgram", name)?
-                    .set("arguments", js::str_array(arguments))?
-                    //.set("print", callbacks.print.as_ref())?
-                    // TODO: Connect to print_err. For now we do 2>&1.
-                    //.set("printErr", callbacks.print.as_ref())?
-                    .set("exit", callbacks.exit.as_ref())?
-                    // OS init:
-                    .set("os.set_module", callbacks.set_module.as_ref())?
-                    .set("os.init_module", callbacks.init_module.as_ref())?
-                    .set("os.init_runtime", callbacks.init_runtime.as_ref())?
-                    // Mocked syscalls & functions:
-                    .set("os.pid", 1)? // TODO: actual pid!
-                    .set("os.ppid", 1)? // TODO: actual ppid!
-                    .set("os.vfork", callbacks.vfork.as_ref())?
-                    .set("os.waitpid", callbacks.waitpid.as_ref())?
-                    .set("os.wait4", callbacks.wait4.as_ref())?
-                    .set("os.read", callbacks.read.as_ref())?
-                    .set("os.write", callbacks.write.as_ref())?
-                    .into(),
-            )?
-            .into();
+    /// Sends a signal generated by the terminal to the foreground process group.
+    pub fn tty_signal(&self, sig: Signal) -> Result<(), Error> {
+        let tty = self.tty.borrow().clone();
+        let tty = tty.ok_or(Error::new("proc: tty: detached"))?;
+        for proc in self.group(tty.pgrp).iter() {
+            self.signal(proc, sig);
+        }
+        Ok(())
+    }
 
-        Ok(Self {
-            id,
-            state,
-            module,
-            io,
-            promise,
-            callbacks,
-        })
+    /// Checks that the caller may read from the terminal.
+    ///
+    /// Only the foreground process group may read, background groups get SIGTTIN.
+    pub fn tty_read(&self, caller: Pid) -> Result<(), Errno> {
+        match self.ctty(caller) {
+            Ok(_) => self.check_background(caller, SIGTTIN),
+            // Not our controlling terminal, nothing to check.
+            Err(_) => Ok(()),
+        }
     }
 
-    /// Waits until the program exits and returns its exit code.
-    async fn wait(&self) -> Result<i32, Error> {
-        JsFuture::from(self.promise.clone()).await?;
-        if let State::Running(def) = self.state.borrow().deref() {
-            JsFuture::from(def.promise()).await?;
+    /// Returns the terminal if it is the controlling terminal of the caller.
+    fn ctty(&self, caller: Pid) -> Result<Tty, Errno> {
+        let sid = *self.get(caller)?.sid.borrow();
+        match &*self.tty.borrow() {
+            Some(tty) if tty.session == sid => Ok(tty.clone()),
+            _ => Err(ENOTTY),
         }
+    }
 
-        match self.state.borrow().deref() {
-            State::Exited(code) => Ok(*code),
-            _ => Err(Error::new(&format!("proc: pid {}: zombie", self.id))),
+    /// Stops a background process group accessing the terminal by sending it a signal.
+    ///
+    /// Fails with EINTR once the signal is sent, or EIO if the signal would be discarded.
+    fn check_background(&self, caller: Pid, sig: Signal) -> Result<(), Errno> {
+        let tty = self.ctty(caller)?;
+        let proc = self.get(caller)?;
+        let pgid = *proc.pgid.borrow();
+        if pgid == tty.pgrp {
+            return Ok(());
+        }
+
+        let (ignored, blocked) = {
+            let signals = proc.signals.borrow();
+            (signals.ignores(sig), signals.blocked().contains(sig))
+        };
+        match sig {
+            // Writers that ignore or block SIGTTOU may proceed.
+            SIGTTOU if ignored || blocked => Ok(()),
+            // Readers that do get an error instead.
+            SIGTTIN if ignored || blocked => Err(EIO),
+            _ => {
+                for member in self.group(pgid).iter() {
+                    self.signal(member, sig);
+                }
+                Err(EINTR)
+            }
         }
     }
-}
 
-impl Callbacks {
-    fn new(
-        state: &Rc<RefCell<State>>,
-        module: &Rc<RefCell<Option<js::Module>>>,
-        io: &Rc<AsyncIo>,
-    ) -> Self {
-        Self {
-            print: Self::print(io.clone(), STDOUT),
-            print_err: Self::print(io.clone(), STDERR),
-            exit: Self::exit(state.clone(), io.clone()),
+    /// Terminates a process, either exited or killed by a signal, and notifies its parent.
+    fn exit(&self, pid: Pid, state: State) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        proc.exit(state)?;
+        self.reparent_children(&proc);
+        self.hangup(&proc);
 
-            set_module: Self::set_module(module.clone()),
-            init_module: Self::init_module(),
-            init_runtime: Self::init_runtime(),
+        let ppid = *proc.ppid.borrow();
+        if self.get(ppid).is_ok() {
+            self.notify_parent(&proc, true);
+        } else if ppid != 0 {
+            // Nobody is left to wait for it, unless it was started by the kernel.
+            self.map.borrow_mut().remove(&pid);
+        }
+        Ok(())
+    }
+
+    /// If a session leader exits, its foreground process group gets SIGHUP and the terminal is
+    /// released.
+    fn hangup(&self, proc: &Process) {
+        let tty = match &*self.tty.borrow() {
+            Some(tty) if tty.session == proc.id => tty.clone(),
+            _ => return,
+        };
+        self.tty.replace(None);
+        for member in self.group(tty.pgrp).iter() {
+            self.signal(member, SIGHUP);
+            self.signal(member, SIGCONT);
+        }
+    }
 
-            vfork: Self::vfork(),
-            waitpid: Self::waitpid(),
-            wait4: Self::wait4(),
-            read: Self::read(module.clone(), io.clone()),
-            write: Self::write(module.clone(), io.clone()),
+    /// Wakes up the parent waiting in wait4() and sends it SIGCHLD.
+    ///
+    /// For stopped and continued children, SIGCHLD is not sent if the parent set SA_NOCLDSTOP.
+    fn notify_parent(&self, proc: &Process, terminated: bool) {
+        let Ok(parent) = self.get(*proc.ppid.borrow()) else {
+            return;
+        };
+        parent.signal_child();
+
+        let flags = parent.signals.borrow().action(SIGCHLD).flags;
+        if terminated || flags & SA_NOCLDSTOP == 0 {
+            self.signal(&parent, SIGCHLD);
         }
     }
 
-    pub fn print(io: Rc<AsyncIo>, fd: u32) -> Closure<dyn Fn(String)> {
-        Closure::new(move |text: String| {
-            if let Err(_) = io.write(fd, text.as_bytes().to_vec()) {
-                js::error(&format!("proc: fd {}: write failed", fd));
+    /// Hands the children of an exiting process over to init.
+    ///
+    /// If init itself is gone, zombies are reaped right away since nobody is left to wait for
+    /// them, and the rest will be reaped as soon as they exit.
+    fn reparent_children(&self, proc: &Process) {
+        let orphans = std::mem::take(&mut *proc.children.borrow_mut());
+        if orphans.is_empty() {
+            return;
+        }
+
+        let init = self.get(INIT_PID).ok().filter(|init| init.id != proc.id);
+        for pid in orphans {
+            let Ok(orphan) = self.get(pid) else {
+                continue;
+            };
+            orphan.ppid.replace(INIT_PID);
+            match &init {
+                Some(init) => {
+                    init.children.borrow_mut().insert(pid);
+                }
+                None if orphan.wait_status().is_some() => {
+                    self.map.borrow_mut().remove(&pid);
+                }
+                None => {}
             }
-        })
+        }
+
+        if let Some(init) = init {
+            init.signal_child();
+        }
     }
 
-    pub fn exit(state: Rc<RefCell<State>>, io: Rc<AsyncIo>) -> Closure<dyn FnMut(i32)> {
-        Closure::new(move |code: i32| match state.borrow().deref() {
-            State::Running(def) => {
-                RefCell::swap(state.as_ref(), &State::Exited(code).into());
-                if let Err(_) = io.close_all() {
-                    js::error("proc: failed to close file descriptors")
+    /// Waits for a child of ppid to change state, see wait4(2).
+    ///
+    /// A positive pid waits for that specific child, -1 waits for any child, 0 for any child in
+    /// the caller's process group and other negative values for any child in process group -pid.
+    /// Returns None if WNOHANG was given and no child has changed state yet. Errors are errno
+    /// values.
+    pub async fn wait4(&self, ppid: Pid, pid: i32, options: i32) -> Result<Option<Waited>, Errno> {
+        if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+            return Err(EINVAL);
+        }
+        let parent = self.get(ppid)?;
+
+        loop {
+            let children: Vec<Rc<Process>> = {
+                let map = self.map.borrow();
+                let pgid = *parent.pgid.borrow();
+                parent
+                    .children
+                    .borrow()
+                    .iter()
+                    .filter_map(|child| map.get(child))
+                    .filter(|child| child.matches(pid, pgid))
+                    .cloned()
+                    .collect()
+            };
+            if children.is_empty() {
+                return Err(ECHILD);
+            }
+
+            if let Some(child) = children.iter().find(|c| c.wait_status().is_some()) {
+                return Ok(Some(self.reap(&parent, child)));
+            }
+            for child in children.iter() {
+                if let Some(status) = child.take_report(options) {
+                    return Ok(Some(Waited {
+                        pid: child.id,
+                        status,
+                        utime: child.utime(),
+                    }));
                 }
-                def.resolve(&JsValue::null());
             }
-            State::Exited(_) => js::error("proc: exit called more than once"),
-        })
+            if options & WNOHANG != 0 {
+                return Ok(None);
+            }
+
+            let promise = parent.child_event().map_err(|_| EINVAL)?;
+            match parent.block_on(promise).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => return Err(EINTR),
+                Err(_) => return Err(EINVAL),
+            }
+        }
     }
 
-    pub fn set_module(module: Rc<RefCell<Option<js::Module>>>) -> Closure<dyn Fn(js::Module)> {
-        Closure::new(move |js_module: js::Module| {
-            module.replace(Some(js_module));
-        })
+    /// Removes a terminated child from the process table.
+    fn reap(&self, parent: &Process, child: &Process) -> Waited {
+        self.map.borrow_mut().remove(&child.id);
+        parent.children.borrow_mut().remove(&child.id);
+
+        let utime = child.utime();
+        parent.usage.borrow_mut().children += utime;
+
+        Waited {
+            pid: child.id,
+            status: child.wait_status().unwrap_or_default(),
+            utime,
+        }
     }
-    pub fn init_module() -> Closure<dyn Fn(Object, Object)> {
-        Closure::new(|env: Object, fs: Object| {
-            if let Err(_) = Reflect::set(&env, &"USER".into(), &JsString::from("snail")) {
-                js::error("proc: module init: failed to set user");
+
+    /// Sends a signal, see kill(2).
+    ///
+    /// A positive pid targets that process, 0 the caller's process group, -1 every process except
+    /// init, and other negative values process group -pid. Signal 0 only checks whether the
+    /// targets exist. Errors are errno values.
+    pub fn kill(&self, sender: Pid, pid: i32, sig: Signal) -> Result<(), Errno> {
+        if sig != 0 && !signal::valid(sig) {
+            return Err(EINVAL);
+        }
+
+        let targets: Vec<Rc<Process>> = match pid {
+            -1 => self
+                .map
+                .borrow()
+                .values()
+                .filter(|proc| proc.id != INIT_PID)
+                .cloned()
+                .collect(),
+            0 => self.group(self.getpgid(sender, 0)?),
+            pid if pid < 0 => self.group(pid.unsigned_abs()),
+            pid => self.get(pid as Pid).into_iter().collect(),
+        };
+        if targets.is_empty() {
+            return Err(ESRCH);
+        }
+
+        if sig != 0 {
+            for proc in targets.iter() {
+                self.signal(proc, sig);
             }
+        }
+        Ok(())
+    }
+
+    /// Posts a signal to a process, waking it up if it can be delivered.
+    fn signal(&self, proc: &Process, sig: Signal) {
+        if proc.terminated() {
+            return;
+        }
+        // Init only receives the signals it has installed handlers for.
+        if proc.id == INIT_PID && proc.signals.borrow().action(sig).handler == SIG_DFL {
+            return;
+        }
+
+        if sig == SIGCONT || sig == SIGKILL {
+            self.resume(proc);
+        }
+        if proc.signals.borrow_mut().post(sig) {
+            proc.interrupt();
+        }
+    }
 
-            // TODO: Write a JS binding for this!
-            let rename: Function = Reflect::get(&fs, &"rename".into()).unwrap().into();
-            if let Err(_) = rename.call2(
-                &fs,
-                &JsString::from("/home/web_user"),
-                &JsString::from("/home/snail"),
-            ) {
-                js::error("proc: module init: failed to rename home dir");
+    /// Stops a running process.
+    fn stop(&self, proc: &Process, sig: Signal) {
+        match proc.state.replace(State::Exited(0)) {
+            State::Running(def) => proc.state.replace(State::Stopped(def)),
+            state => {
+                proc.state.replace(state);
+                return;
             }
-        })
+        };
+        proc.report.replace(Some(sig << 8 | 0x7f));
+        self.notify_parent(proc, false);
     }
 
-    pub fn init_runtime() -> Closure<dyn Fn()> {
-        Closure::new(move || {})
+    /// Continues a stopped process.
+    fn resume(&self, proc: &Process) {
+        match proc.state.replace(State::Exited(0)) {
+            State::Stopped(def) => proc.state.replace(State::Running(def)),
+            state => {
+                proc.state.replace(state);
+                return;
+            }
+        };
+        proc.report.replace(Some(WSTATUS_CONTINUED));
+        if let Some(def) = proc.resume.replace(None) {
+            def.resolve(&JsValue::null());
+        }
+        self.notify_parent(proc, false);
     }
 
-    pub fn vfork() -> Closure<dyn Fn() -> Promise> {
-        Closure::new(move || {
-            #[cfg(feature = "dbg")]
-            js::log("proc: vfork()?");
+    /// Takes pending signals off the queue until one needs a handler to run.
+    ///
+    /// Default actions are applied here: ignored signals are discarded, stop signals block until
+    /// the process is continued, and fatal signals terminate the process.
+    pub async fn deliver(&self, pid: Pid) -> Result<Delivery, Errno> {
+        let proc = self.get(pid)?;
+        loop {
+            if let State::Signaled(sig) = &*proc.state.borrow() {
+                return Ok(Delivery::Terminated(*sig));
+            }
+            if let Some(promise) = proc.stopped().map_err(|_| EIO)? {
+                // Only SIGCONT or SIGKILL can wake up a stopped process.
+                JsFuture::from(promise).await.map_err(|_| EIO)?;
+                continue;
+            }
 
-            Promise::resolve(&1.into())
-        })
+            let next = proc.signals.borrow_mut().next();
+            match next {
+                None => return Ok(Delivery::Done),
+                Some(Disposition::Handle(sig, action)) => return Ok(Delivery::Handle(sig, action)),
+                Some(Disposition::Default(sig, DefaultAction::Terminate | DefaultAction::Core)) => {
+                    self.exit(pid, State::Signaled(sig))?;
+                    return Ok(Delivery::Terminated(sig));
+                }
+                Some(Disposition::Default(sig, DefaultAction::Stop)) => self.stop(&proc, sig),
+                // SIGCONT takes effect as soon as it is sent.
+                Some(Disposition::Default(_, _)) => {}
+            }
+        }
     }
 
-    pub fn waitpid() -> Closure<dyn Fn(u32, u32, i32) -> Promise> {
-        Closure::new(move |pid, status, options| {
-            #[cfg(feature = "dbg")]
-            js::log(&format!("proc: waitpid({}, {}, {})?", pid, status, options));
+    /// Restores the signal mask once a handler returns.
+    pub fn sigreturn(&self, pid: Pid) -> Result<(), Errno> {
+        self.get(pid)?.signals.borrow_mut().sigreturn();
+        Ok(())
+    }
 
-            Promise::resolve(&1.into())
-        })
+    /// Examines and changes a signal action, see sigaction(2). Errors are errno values.
+    pub fn sigaction(
+        &self,
+        pid: Pid,
+        sig: Signal,
+        action: Option<Action>,
+    ) -> Result<Action, Errno> {
+        let proc = self.get(pid)?;
+        let mut signals = proc.signals.borrow_mut();
+        match action {
+            Some(action) => signals.set_action(sig, action).ok_or(EINVAL),
+            None if signal::valid(sig) => Ok(signals.action(sig)),
+            None => Err(EINVAL),
+        }
     }
 
-    pub fn wait4() -> Closure<dyn Fn(u32, u32, i32, u32) -> Promise> {
-        Closure::new(move |pid, status, options, rusage| {
-            #[cfg(feature = "dbg")]
-            js::log(&format!(
-                "proc: wait4({}, {}, {}, {})?",
-                pid, status, options, rusage
-            ));
+    /// Examines and changes the signal mask, see sigprocmask(2). Errors are errno values.
+    pub fn sigprocmask(&self, pid: Pid, how: i32, set: Option<SigSet>) -> Result<SigSet, Errno> {
+        let proc = self.get(pid)?;
+        let mut signals = proc.signals.borrow_mut();
+        match set {
+            Some(set) => signals.procmask(how, set).ok_or(EINVAL),
+            None => Ok(signals.blocked()),
+        }
+    }
+
+    /// Returns the set of pending signals.
+    pub fn sigpending(&self, pid: Pid) -> Result<SigSet, Errno> {
+        Ok(self.get(pid)?.signals.borrow().pending())
+    }
+
+    /// Replaces the signal mask and waits until a signal can be delivered, see sigsuspend(2).
+    ///
+    /// Returns the old mask, which must be restored once the handlers have run.
+    pub async fn sigsuspend(&self, pid: Pid, mask: SigSet) -> Result<SigSet, Errno> {
+        let proc = self.get(pid)?;
+        let old = proc
+            .signals
+            .borrow_mut()
+            .procmask(signal::SIG_SETMASK, mask)
+            .unwrap_or_default();
+        // Never resolves on its own, only a signal wakes us up.
+        let promise = js::deferred().map_err(|_| EIO)?.promise();
+        proc.block_on(promise).await.map_err(|_| EIO)?;
+        Ok(old)
+    }
 
-            Promise::resolve(&(-1).into())
+    /// Sleeps for the given number of milliseconds, see nanosleep(2).
+    ///
+    /// If interrupted by a signal, returns the remaining time.
+    pub async fn nanosleep(&self, pid: Pid, ms: f64) -> Result<Option<f64>, Errno> {
+        let proc = self.get(pid)?;
+        let start = Date::now();
+        Ok(match proc.block_on(js::sleep(ms)).await.map_err(|_| EIO)? {
+            Some(_) => None,
+            None => Some((ms - (Date::now() - start)).max(0.0)),
         })
     }
 
-    pub fn read(
-        module: Rc<RefCell<Option<js::Module>>>,
-        io: Rc<AsyncIo>,
-    ) -> Closure<dyn Fn(i32, u32, u32) -> Promise> {
-        Closure::new(move |fd: i32, buf: u32, count: u32| -> Promise {
-            #[cfg(feature = "dbg")]
-            js::log(&format!("proc: read({}, {}, {})?", fd, buf, count));
+    /// Creates a pipe, returning the file descriptors of its read and write ends.
+    pub fn pipe(&self, pid: Pid, flags: i32) -> Result<(u32, u32), Errno> {
+        if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+            return Err(EINVAL);
+        }
+        let proc = self.get(pid)?;
+        let buf = Rc::new(AsyncBuffer::new());
+        let cloexec = flags & O_CLOEXEC != 0;
 
-            // TODO: Remove this layer of indentation with if let/else.
-            match u32::try_from(fd) {
-                Err(_) => Promise::reject(&format!("proc: read {}: bad file descriptor", fd).into()),
-                Ok(fd) => match io.read_promise(fd, &module, buf, count) {
-                    Err(_) => Promise::reject(&format!("proc: read {}: failed", fd).into()),
-                    Ok(promise) => promise,
-                },
+        let mut fds = [Access::Read, Access::Write].map(|access| {
+            let file = OpenFile::channel(buf.clone(), access, false);
+            file.set_flags(flags);
+            proc.fds.insert(file.into(), 0, cloexec)
+        });
+        match fds {
+            [Some(read), Some(write)] => Ok((read, write)),
+            _ => {
+                for fd in fds.iter_mut().flatten() {
+                    proc.fds.close(*fd);
+                }
+                Err(EMFILE)
             }
-        })
+        }
     }
 
-    pub fn write(
-        module: Rc<RefCell<Option<js::Module>>>,
-        io: Rc<AsyncIo>,
-    ) -> Closure<dyn Fn(i32, u32, u32) -> u32> {
-        Closure::new(move |fd: i32, buf: u32, count: u32| -> u32 {
-            #[cfg(feature = "dbg")]
-            js::log(&format!("proc: write({}, {}, {})?", fd, buf, count));
-            // TODO: implement write!
-            return count;
-        })
+    /// Writes data to a channel or file, see write(2). Errors are errno values.
+    ///
+    /// Writing to a pipe with no readers left raises SIGPIPE and fails with EPIPE.
+    ///
+    /// Blocks while the channel is full or stopped, e.g. while the terminal is catching up, unless
+    /// the file descriptor is non-blocking, in which case it fails with EAGAIN instead. Writes of
+    /// more than PIPE_BUF bytes may be split; if interrupted, the number of bytes written so far is
+    /// returned.
+    pub async fn write(&self, pid: Pid, fd: u32, mut data: Vec<u8>) -> Result<usize, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        if let Some(vfs_file) = file.fs_file() {
+            if file.flags() & O_ACCMODE == O_RDONLY {
+                return Err(EBADF);
+            }
+            return vfs_file.write(&data, file.flags() & O_APPEND != 0);
+        }
+        let buf = proc.channel(fd, Access::Write)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let nonblocking = proc.nonblocking(fd);
+        let mut written = 0;
+        loop {
+            if buf.is_broken() {
+                self.signal(&proc, SIGPIPE);
+                return Err(EPIPE);
+            }
+            if nonblocking && buf.would_block(data.len()) {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(EAGAIN)
+                };
+            }
+            if let Some(promise) = buf.writable(data.len()).map_err(|_| EIO)? {
+                if proc.block_on(promise).await.map_err(|_| EIO)?.is_none() {
+                    return if written > 0 { Ok(written) } else { Err(EINTR) };
+                }
+                continue;
+            }
+            let rest = data.split_off(data.len().min(buf.space()));
+            written += buf.write(data)?;
+            if rest.is_empty() {
+                return Ok(written);
+            }
+            data = rest;
+        }
+    }
+
+    /// Closes a file descriptor, see close(2). Errors are errno values.
+    pub fn close(&self, pid: Pid, fd: u32) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        proc.fds.close(fd).ok_or(EBADF)?;
+        Ok(())
+    }
+
+    /// Duplicates a file descriptor to the lowest free one not less than min.
+    pub fn dup(&self, pid: Pid, fd: u32, min: u32, cloexec: bool) -> Result<u32, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        if min >= MAX_FDS {
+            return Err(EINVAL);
+        }
+        proc.fds.insert(file, min, cloexec).ok_or(EMFILE)
+    }
+
+    /// Duplicates a file descriptor to new, see dup3(2). Errors are errno values.
+    pub fn dup3(&self, pid: Pid, old: u32, new: u32, flags: i32) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        if flags & !O_CLOEXEC != 0 || old == new {
+            return Err(EINVAL);
+        }
+        if new >= MAX_FDS {
+            return Err(EBADF);
+        }
+        proc.fds
+            .dup2(old, new, flags & O_CLOEXEC != 0)
+            .ok_or(EBADF)?;
+        Ok(())
+    }
+
+    /// Manipulates a file descriptor, see fcntl(2). Errors are errno values.
+    pub fn fcntl(&self, pid: Pid, fd: u32, cmd: i32, arg: i32) -> Result<i32, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        match cmd {
+            F_DUPFD | F_DUPFD_CLOEXEC => {
+                let min = u32::try_from(arg).map_err(|_| EINVAL)?;
+                self.dup(pid, fd, min, cmd == F_DUPFD_CLOEXEC)
+                    .map(|fd| fd as i32)
+            }
+            F_GETFD => Ok(proc.fds.cloexec(fd).map_or(0, |c| c as i32 * FD_CLOEXEC)),
+            F_SETFD => {
+                proc.fds.set_cloexec(fd, arg & FD_CLOEXEC != 0);
+                Ok(0)
+            }
+            F_GETFL => Ok(file.flags()),
+            F_SETFL => {
+                file.set_flags(arg);
+                Ok(0)
+            }
+            F_GETPIPE_SZ => Ok(file.channel_end().ok_or(EBADF)?.0.capacity() as i32),
+            F_SETPIPE_SZ => {
+                let (buf, _) = file.channel_end().ok_or(EBADF)?;
+                let size = usize::try_from(arg).map_err(|_| EINVAL)?;
+                if size > PIPE_MAX_SIZE {
+                    return Err(EPERM);
+                }
+                // Rounded up like Linux does, to a power of two number of pages.
+                let size = size.max(PIPE_BUF).next_power_of_two();
+                if !buf.set_capacity(size) {
+                    return Err(EBUSY);
+                }
+                Ok(size as i32)
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Opens a file in the filesystem, see openat(2). Errors are errno values.
+    pub fn open(
+        &self,
+        pid: Pid,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+        mode: u32,
+    ) -> Result<u32, Errno> {
+        let proc = self.get(pid)?;
+        let path = proc.resolve(dirfd, path)?;
+        let location = self.vfs.open(&path, flags, mode & 0o7777)?;
+        let stat = location.stat();
+        let file = match stat.rdev {
+            TTY_DEV | CONSOLE_DEV if stat.mode & S_IFMT == S_IFCHR => {
+                self.open_tty(pid, stat.rdev, flags)?
+            }
+            _ => OpenFile::file(vfs::File::new(location), flags),
+        };
+        proc.fds
+            .insert(file.into(), 0, flags & O_CLOEXEC != 0)
+            .ok_or(EMFILE)
+    }
+
+    /// Opens the terminal as a channel: /dev/tty if it is the caller's controlling terminal, or
+    /// /dev/console whichever session it belongs to. Fails with ENXIO otherwise.
+    fn open_tty(&self, caller: Pid, rdev: u32, flags: i32) -> Result<OpenFile, Errno> {
+        let tty = match rdev {
+            TTY_DEV => self.ctty(caller).map_err(|_| ENXIO)?,
+            _ => self.tty.borrow().clone().ok_or(ENXIO)?,
+        };
+        let access = match flags & O_ACCMODE {
+            O_RDONLY => Access::Read,
+            O_WRONLY => Access::Write,
+            _ => Access::ReadWrite,
+        };
+        let file = OpenFile::terminal(tty.input, tty.output, access);
+        file.set_flags(flags);
+        Ok(file)
+    }
+
+    /// Reads from a file in the filesystem, see read(2). Errors are errno values.
+    pub fn read_file(&self, pid: Pid, fd: u32, count: usize) -> Result<Vec<u8>, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        if file.flags() & O_ACCMODE == O_WRONLY {
+            return Err(EBADF);
+        }
+        file.fs_file().ok_or(EINVAL)?.read(count)
+    }
+
+    /// Repositions the offset of a file, see lseek(2). Errors are errno values.
+    pub fn lseek(&self, pid: Pid, fd: u32, offset: i64, whence: i32) -> Result<u64, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        file.fs_file().ok_or(ESPIPE)?.seek(offset, whence)
+    }
+
+    /// Returns the status of the file behind fd, see fstat(2). Errors are errno values.
+    ///
+    /// Channels and epoll instances are not part of the filesystem, so their status is made up.
+    pub fn fstat(&self, pid: Pid, fd: u32) -> Result<Stat, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        let mode = match &file.target {
+            Target::File(file) => return Ok(file.location.stat()),
+            Target::Channel { tty: true, .. } => S_IFCHR | 0o620,
+            Target::Channel { .. } => S_IFIFO | 0o600,
+            _ => 0o600,
+        };
+        Ok(Stat {
+            rdev: if file.is_tty() { TTY_DEV } else { 0 },
+            mode,
+            nlink: 1,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the status of a file, see stat(2). Errors are errno values.
+    ///
+    /// With AT_SYMLINK_NOFOLLOW, a symbolic link itself is described, see lstat(2).
+    pub fn stat(&self, pid: Pid, dirfd: i32, path: &str, flags: i32) -> Result<Stat, Errno> {
+        if flags & AT_SYMLINK_NOFOLLOW != 0 {
+            let path = self.get(pid)?.resolve(dirfd, path)?;
+            return Ok(self.vfs.lookup_link(&path)?.stat());
+        }
+        Ok(self.location(pid, dirfd, Some(path))?.stat())
+    }
+
+    /// Returns the target of a symbolic link, see readlink(2). Errors are errno values.
+    pub fn readlink(&self, pid: Pid, dirfd: i32, path: &str) -> Result<String, Errno> {
+        let path = self.get(pid)?.path_at(dirfd, path)?;
+        if path == procfs::SELF {
+            return Ok(pid.to_string());
+        }
+        let path = procfs::resolve_self(&path, pid);
+        self.vfs.lookup_link(&path)?.node.readlink()
+    }
+
+    /// Reads directory entries, see getdents64(2). Errors are errno values.
+    pub fn getdents(&self, pid: Pid, fd: u32, count: usize) -> Result<Vec<u8>, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        self.vfs.getdents(file.fs_file().ok_or(ENOTDIR)?, count)
+    }
+
+    /// Changes the size of a file open for writing, see ftruncate(2). Errors are errno values.
+    pub fn ftruncate(&self, pid: Pid, fd: u32, len: i64) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(fd).ok_or(EBADF)?;
+        let len = u64::try_from(len).map_err(|_| EINVAL)?;
+        match file.fs_file() {
+            Some(vfs_file) if file.flags() & O_ACCMODE != O_RDONLY => {
+                vfs_file.location.node.truncate(len)
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Removes a file, or a directory if flags has AT_REMOVEDIR, see unlinkat(2). Errors are
+    /// errno values.
+    pub fn unlink(&self, pid: Pid, dirfd: i32, path: &str, flags: i32) -> Result<(), Errno> {
+        let path = self.get(pid)?.resolve(dirfd, path)?;
+        match flags {
+            0 => self.vfs.unlink(&path),
+            AT_REMOVEDIR => self.vfs.rmdir(&path),
+            _ => Err(EINVAL),
+        }
+    }
+
+    /// Creates a directory, see mkdirat(2). Errors are errno values.
+    pub fn mkdir(&self, pid: Pid, dirfd: i32, path: &str, mode: u32) -> Result<(), Errno> {
+        let path = self.get(pid)?.resolve(dirfd, path)?;
+        self.vfs.mkdir(&path, mode & 0o7777)
+    }
+
+    /// Creates a symbolic link to target, see symlinkat(2). Errors are errno values.
+    pub fn symlink(&self, pid: Pid, target: &str, dirfd: i32, path: &str) -> Result<(), Errno> {
+        let path = self.get(pid)?.resolve(dirfd, path)?;
+        self.vfs.symlink(target, &path)
+    }
+
+    /// Moves a file, see renameat(2). Errors are errno values.
+    pub fn rename(
+        &self,
+        pid: Pid,
+        old_dirfd: i32,
+        old: &str,
+        new_dirfd: i32,
+        new: &str,
+    ) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        let old = proc.resolve(old_dirfd, old)?;
+        let new = proc.resolve(new_dirfd, new)?;
+        self.vfs.rename(&old, &new)
+    }
+
+    /// Changes the working directory to path, or to the directory behind dirfd if there is no
+    /// path, see chdir(2) and fchdir(2). Errors are errno values.
+    pub fn chdir(&self, pid: Pid, dirfd: i32, path: Option<&str>) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        let location = self
+            .vfs
+            .lookup(&proc.resolve(dirfd, path.unwrap_or("."))?)?;
+        if !location.node.is_dir() {
+            return Err(ENOTDIR);
+        }
+        proc.cwd.replace(location.path);
+        Ok(())
+    }
+
+    /// Returns the working directory, see getcwd(3).
+    pub fn getcwd(&self, pid: Pid) -> Result<String, Errno> {
+        Ok(self.get(pid)?.cwd.borrow().clone())
+    }
+
+    /// Checks whether a file may be accessed, see faccessat(2). Errors are errno values.
+    pub fn access(&self, pid: Pid, dirfd: i32, path: &str, mode: u32) -> Result<(), Errno> {
+        let path = self.get(pid)?.resolve(dirfd, path)?;
+        self.vfs.access(&path, mode)
+    }
+
+    /// Changes the permissions of a file, see chmod(2) and location() for the arguments. Errors
+    /// are errno values.
+    pub fn chmod(&self, pid: Pid, dirfd: i32, path: Option<&str>, mode: u32) -> Result<(), Errno> {
+        let location = self.location(pid, dirfd, path)?;
+        location.writable()?;
+        location.node.chmod(mode)
+    }
+
+    /// Changes the access and modification times of a file, leaving those that are None, see
+    /// utimensat(2) and location() for the arguments. Errors are errno values.
+    pub fn utimens(
+        &self,
+        pid: Pid,
+        dirfd: i32,
+        path: Option<&str>,
+        atime: Option<f64>,
+        mtime: Option<f64>,
+    ) -> Result<(), Errno> {
+        let location = self.location(pid, dirfd, path)?;
+        location.writable()?;
+        location.node.set_times(atime, mtime)
+    }
+
+    /// Mounts a filesystem, or changes the flags of a mount if flags has MS_REMOUNT, see mount(2).
+    /// Errors are errno values.
+    ///
+    /// The source is only recorded, as no filesystem is backed by a device. Options only apply to
+    /// new mounts.
+    pub fn mount(
+        &self,
+        pid: Pid,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        flags: u32,
+        options: &str,
+    ) -> Result<(), Errno> {
+        let target = self
+            .vfs
+            .lookup(&self.get(pid)?.resolve(AT_FDCWD, target)?)?
+            .path;
+        if flags & MS_REMOUNT != 0 {
+            let mount = self.vfs.mounts().get(&target).ok_or(EINVAL)?;
+            mount.set_flags(flags);
+            return Ok(());
+        }
+        self.mount_fs(source, &target, fstype, flags, options)
+    }
+
+    /// Mounts a new filesystem on an absolute target, see mount(). Errors are errno values.
+    pub fn mount_fs(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        flags: u32,
+        options: &str,
+    ) -> Result<(), Errno> {
+        let fs = self.new_fs(fstype, options)?;
+        self.vfs.mount(source, target, fstype, fs, flags)
+    }
+
+    /// Unmounts the filesystem mounted on target, see umount2(2). Errors are errno values.
+    ///
+    /// Fails with EBUSY if it is in use by an open file or a working directory, unless flags has
+    /// MNT_FORCE or MNT_DETACH.
+    pub fn umount(&self, pid: Pid, target: &str, flags: i32) -> Result<(), Errno> {
+        if flags & !(MNT_FORCE | MNT_DETACH | UMOUNT_NOFOLLOW) != 0 {
+            return Err(EINVAL);
+        }
+        let target = self.get(pid)?.resolve(AT_FDCWD, target)?;
+        let target = match flags & UMOUNT_NOFOLLOW {
+            0 => self.vfs.lookup(&target)?.path,
+            _ => self.vfs.lookup_link(&target)?.path,
+        };
+        let force = flags & (MNT_FORCE | MNT_DETACH) != 0;
+        let busy = self.map.borrow().values().any(|proc| {
+            let cwd = proc.cwd.borrow();
+            *cwd == target || cwd.starts_with(&format!("{}/", target))
+        });
+        if busy && !force {
+            return Err(EBUSY);
+        }
+        self.vfs.mounts().remove(&target, force)
+    }
+
+    /// Returns statistics of the filesystem containing a file, see statfs(2) and location() for
+    /// the arguments. Errors are errno values.
+    pub fn statfs(&self, pid: Pid, dirfd: i32, path: Option<&str>) -> Result<StatFs, Errno> {
+        Ok(self.location(pid, dirfd, path)?.mount.statfs())
+    }
+
+    /// The filesystem tree shared by all processes.
+    pub fn vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    /// Returns the time of boot, in milliseconds since the epoch.
+    pub fn booted(&self) -> f64 {
+        self.booted
+    }
+
+    /// Returns the pids of all processes, including zombies, in ascending order.
+    pub fn pids(&self) -> Vec<Pid> {
+        let mut pids: Vec<Pid> = self.map.borrow().keys().copied().collect();
+        pids.sort_unstable();
+        pids
+    }
+
+    /// Returns a snapshot of a process.
+    pub fn info(&self, pid: Pid) -> Result<ProcessInfo, Errno> {
+        let proc = self.get(pid)?;
+        let state = match &*proc.state.borrow() {
+            State::Running(_) if proc.interrupt.borrow().is_some() => 'S',
+            State::Running(_) => 'R',
+            State::Stopped(..) => 'T',
+            State::Exited(_) | State::Signaled(_) => 'Z',
+        };
+        let sid = *proc.sid.borrow();
+        let tty = self
+            .tty
+            .borrow()
+            .as_ref()
+            .filter(|tty| tty.session == sid)
+            .map(|tty| tty.pgrp);
+        let utime = proc.utime();
+        let usage = proc.usage.borrow();
+        let signals = proc.signals.borrow();
+        let info = ProcessInfo {
+            pid,
+            ppid: *proc.ppid.borrow(),
+            pgid: *proc.pgid.borrow(),
+            sid,
+            tty,
+            state,
+            cmdline: proc.cmdline.borrow().clone(),
+            environ: proc.environ.borrow().clone(),
+            cwd: proc.cwd.borrow().clone(),
+            exe: proc.exe.borrow().clone(),
+            fds: proc
+                .fds
+                .list()
+                .into_iter()
+                .map(|(fd, file)| (fd, file.link()))
+                .collect(),
+            started: usage.started,
+            utime: utime - usage.children,
+            cutime: usage.children,
+            memory: proc.image.borrow().as_ref().map_or(0, Image::memory),
+            pending: signals.pending(),
+            blocked: signals.blocked(),
+            ignored: signals.ignored(),
+            caught: signals.caught(),
+        };
+        Ok(info)
+    }
+
+    /// Creates a filesystem of the type named by mount(8).
+    ///
+    /// Only tmpfs takes options, the others ignore them.
+    fn new_fs(&self, fstype: &str, options: &str) -> Result<Rc<dyn FileSystem>, Errno> {
+        Ok(match fstype {
+            "devtmpfs" => Rc::new(DevFs::new(self.random.clone())),
+            "proc" => Rc::new(ProcFs::new(self.this.clone())),
+            "ramfs" => Rc::new(MemFs::new()),
+            "tmpfs" => Rc::new(MemFs::tmpfs(options)?),
+            _ => return Err(ENODEV),
+        })
+    }
+
+    /// Returns the node at path relative to dirfd, or the file behind dirfd if there is no path.
+    fn location(&self, pid: Pid, dirfd: i32, path: Option<&str>) -> Result<Location, Errno> {
+        let proc = self.get(pid)?;
+        match path {
+            Some(path) => self.vfs.lookup(&proc.resolve(dirfd, path)?),
+            None => {
+                let fd = u32::try_from(dirfd).map_err(|_| EBADF)?;
+                let file = proc.fds.get(fd).ok_or(EBADF)?;
+                Ok(file.fs_file().ok_or(EINVAL)?.location.clone())
+            }
+        }
+    }
+
+    /// Waits for events on file descriptors, see poll(2). Errors are errno values.
+    ///
+    /// Takes the fd and events of each struct pollfd, and returns the events that are ready, after
+    /// at most timeout milliseconds, or indefinitely if None.
+    pub async fn poll(
+        &self,
+        pid: Pid,
+        fds: &[(i32, i16)],
+        timeout: Option<f64>,
+    ) -> Result<Vec<i16>, Errno> {
+        let proc = self.get(pid)?;
+        let deadline = timeout.map(|ms| Date::now() + ms);
+        loop {
+            let mut channels = Vec::new();
+            let revents: Vec<i16> = fds
+                .iter()
+                .map(|&(fd, events)| match u32::try_from(fd) {
+                    // Negative file descriptors are ignored.
+                    Err(_) => 0,
+                    Ok(fd) => match Self::ready(&proc, fd, 0, &mut channels) {
+                        POLLNVAL => POLLNVAL,
+                        ready => ready & (events | POLLERR | POLLHUP),
+                    },
+                })
+                .collect();
+            let remaining = deadline.map(|deadline| deadline - Date::now());
+            if revents.iter().any(|&events| events != 0) || remaining.is_some_and(|ms| ms <= 0.0) {
+                return Ok(revents);
+            }
+
+            // Wait for anything to change on any of the channels, or for the timeout. The watches
+            // are dropped once done waiting, so idle channels do not pile up watchers.
+            let wait = Array::new();
+            let mut watches = Vec::new();
+            for buf in channels {
+                let (promise, watch) = buf.changed().map_err(|_| EIO)?;
+                wait.push(&promise.into());
+                watches.push(watch);
+            }
+            if let Some(ms) = remaining {
+                wait.push(&js::sleep(ms));
+            }
+            if proc
+                .block_on(Promise::race(&wait))
+                .await
+                .map_err(|_| EIO)?
+                .is_none()
+            {
+                return Err(EINTR);
+            }
+        }
+    }
+
+    /// Returns the events that are ready on a file descriptor, or POLLNVAL if it is not open, and
+    /// adds the channels whose changes might make it ready.
+    ///
+    /// An epoll instance is readable when any of the file descriptors it watches is ready,
+    /// instances it watches included, up to EPOLL_MAX_NESTS deep.
+    fn ready(proc: &Process, fd: u32, depth: usize, channels: &mut Vec<Rc<AsyncBuffer>>) -> i16 {
+        let Some(file) = proc.fds.get(fd) else {
+            return POLLNVAL;
+        };
+        let Target::Epoll(epoll) = &file.target else {
+            if let Some((buf, _)) = file.channel_end() {
+                channels.push(buf.clone());
+            }
+            return file.poll();
+        };
+        if depth == EPOLL_MAX_NESTS {
+            return 0;
+        }
+        let mut events = 0;
+        for (fd, interest) in epoll.armed() {
+            let mask = interest.events as i16 | POLLERR | POLLHUP;
+            if Self::ready(proc, fd, depth + 1, channels) & mask != 0 {
+                events = POLLIN;
+            }
+        }
+        events
+    }
+
+    /// Creates an epoll instance, see epoll_create1(2). Errors are errno values.
+    pub fn epoll_create(&self, pid: Pid, flags: i32) -> Result<u32, Errno> {
+        if flags & !O_CLOEXEC != 0 {
+            return Err(EINVAL);
+        }
+        let proc = self.get(pid)?;
+        let file = OpenFile::epoll(Epoll::new());
+        proc.fds
+            .insert(file.into(), 0, flags & O_CLOEXEC != 0)
+            .ok_or(EMFILE)
+    }
+
+    /// Changes the interest list of an epoll instance, see epoll_ctl(2). Errors are errno values.
+    pub fn epoll_ctl(
+        &self,
+        pid: Pid,
+        epfd: u32,
+        op: i32,
+        fd: u32,
+        interest: Interest,
+    ) -> Result<(), Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(epfd).ok_or(EBADF)?;
+        proc.fds.get(fd).ok_or(EBADF)?;
+        let Target::Epoll(epoll) = &file.target else {
+            return Err(EINVAL);
+        };
+        if fd == epfd {
+            return Err(EINVAL);
+        }
+        let ok = match op {
+            EPOLL_CTL_ADD => epoll.add(fd, interest),
+            EPOLL_CTL_MOD => epoll.modify(fd, interest),
+            EPOLL_CTL_DEL => epoll.remove(fd),
+            _ => return Err(EINVAL),
+        };
+        match (ok, op) {
+            (true, _) => Ok(()),
+            (false, EPOLL_CTL_ADD) => Err(EEXIST),
+            (false, _) => Err(ENOENT),
+        }
+    }
+
+    /// Waits for events on an epoll instance, see epoll_wait(2). Errors are errno values.
+    ///
+    /// Returns up to max ready events along with their data.
+    pub async fn epoll_wait(
+        &self,
+        pid: Pid,
+        epfd: u32,
+        max: usize,
+        timeout: Option<f64>,
+    ) -> Result<Vec<Interest>, Errno> {
+        let proc = self.get(pid)?;
+        let file = proc.fds.get(epfd).ok_or(EBADF)?;
+        let Target::Epoll(epoll) = &file.target else {
+            return Err(EINVAL);
+        };
+        if max == 0 {
+            return Err(EINVAL);
+        }
+
+        let deadline = timeout.map(|ms| Date::now() + ms);
+        loop {
+            let armed = epoll.armed();
+            let fds: Vec<(i32, i16)> = armed
+                .iter()
+                .map(|(fd, interest)| (*fd as i32, interest.events as i16))
+                .collect();
+            let timeout = deadline.map(|deadline| (deadline - Date::now()).max(0.0));
+            let revents = self.poll(pid, &fds, timeout).await?;
+
+            let mut ready = Vec::new();
+            let mut closed = false;
+            for ((fd, interest), events) in armed.into_iter().zip(revents) {
+                if events == POLLNVAL {
+                    // Closed file descriptors are dropped from the interest list.
+                    closed = epoll.remove(fd);
+                } else if events != 0 && ready.len() < max {
+                    epoll.reported(fd);
+                    ready.push(Interest {
+                        events: events as u32,
+                        data: interest.data,
+                    });
+                }
+            }
+            if !ready.is_empty() || !closed {
+                return Ok(ready);
+            }
+        }
+    }
+
+    /// Whether fd refers to the terminal.
+    pub fn isatty(&self, pid: Pid, fd: u32) -> Result<bool, Errno> {
+        let proc = self.get(pid)?;
+        Ok(proc.fds.get(fd).ok_or(EBADF)?.is_tty())
+    }
+
+    /// Closes the the standard input of a process.
+    pub fn stdin_close(&self, pid: Pid) -> Result<(), Error> {
+        self.get(pid)?
+            .fds
+            .close(STDIN)
+            .map(|_| ())
+            .ok_or(Error::new("proc: stdin_close: not open"))
+    }
+
+    /// Returns the channel behind a file descriptor of a process, e.g. to collect its output.
+    pub fn channel(&self, pid: Pid, fd: u32) -> Result<Rc<AsyncBuffer>, Error> {
+        self.get(pid)?
+            .fds
+            .get(fd)
+            .and_then(|file| file.channel_end().map(|(buf, _)| buf.clone()))
+            .ok_or(Error::new(&format!(
+                "proc: pid {}: fd {}: no channel",
+                pid, fd
+            )))
+    }
+
+    /// Returns the processes in a process group.
+    fn group(&self, pgid: Pid) -> Vec<Rc<Process>> {
+        self.map
+            .borrow()
+            .values()
+            .filter(|proc| *proc.pgid.borrow() == pgid)
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until a process exits, returning its exit code.
+    pub async fn wait_quit(&self, pid: Pid) -> Result<i32, Error> {
+        let exit_code = self.get(pid)?.wait().await?;
+        self.map.borrow_mut().remove(&pid);
+        Ok(exit_code)
+    }
+
+    fn get(&self, pid: Pid) -> Result<Rc<Process>, Errno> {
+        self.map.borrow().get(&pid).cloned().ok_or(ESRCH)
+    }
+
+    fn next_pid(&self) -> Pid {
+        self.next_pid.replace_with(|pid| *pid + 1)
+    }
+}
+
+impl Process {
+    fn new(
+        id: Pid,
+        ppid: Pid,
+        pgid: Pid,
+        sid: Pid,
+        fds: FdTable,
+        signals: Signals,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            id,
+            ppid: RefCell::new(ppid),
+            pgid: RefCell::new(pgid),
+            sid: RefCell::new(sid),
+            children: RefCell::new(BTreeSet::new()),
+            state: RefCell::new(State::Running(js::deferred()?)),
+            report: RefCell::new(None),
+            child_event: RefCell::new(None),
+            signals: RefCell::new(signals),
+            interrupt: RefCell::new(None),
+            resume: RefCell::new(None),
+            usage: RefCell::new(Usage {
+                started: vfs::now(),
+                ended: None,
+                blocked: 0.0,
+                children: 0.0,
+            }),
+            fds,
+            exe: RefCell::new(None),
+            cmdline: RefCell::new(Vec::new()),
+            environ: RefCell::new(Vec::new()),
+            cwd: RefCell::new("/".to_string()),
+            image: RefCell::new(None),
+            retired: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Marks the process as exited or signaled, and closes its file descriptors.
+    ///
+    /// Fails with ESRCH if it has already terminated.
+    fn exit(&self, state: State) -> Result<(), Errno> {
+        match self.state.replace(state) {
+            State::Running(def) | State::Stopped(def) => {
+                self.usage.borrow_mut().ended = Some(Date::now());
+                self.fds.close_all();
+                self.report.replace(None);
+                if let Some(resume) = self.resume.replace(None) {
+                    resume.resolve(&JsValue::null());
+                }
+                def.resolve(&JsValue::null());
+                Ok(())
+            }
+            prev => {
+                self.state.replace(prev);
+                Err(ESRCH)
+            }
+        }
+    }
+
+    /// Resolves a path relative to the directory behind dirfd, or to the working directory if
+    /// dirfd is AT_FDCWD, and substitutes /proc/self.
+    fn resolve(&self, dirfd: i32, path: &str) -> Result<String, Errno> {
+        Ok(procfs::resolve_self(&self.path_at(dirfd, path)?, self.id))
+    }
+
+    /// Appends a path to the directory behind dirfd, or to the working directory if dirfd is
+    /// AT_FDCWD, see vfs::append().
+    fn path_at(&self, dirfd: i32, path: &str) -> Result<String, Errno> {
+        if path.is_empty() {
+            return Err(ENOENT);
+        }
+        if path.starts_with('/') || dirfd == AT_FDCWD {
+            return Ok(vfs::append(&self.cwd.borrow(), path));
+        }
+        let fd = u32::try_from(dirfd).map_err(|_| EBADF)?;
+        let file = self.fds.get(fd).ok_or(EBADF)?;
+        match file.fs_file() {
+            Some(dir) if dir.location.node.is_dir() => Ok(vfs::append(&dir.location.path, path)),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    /// Returns the channel behind a file descriptor, if open for the given access.
+    fn channel(&self, fd: u32, access: Access) -> Result<Rc<AsyncBuffer>, Errno> {
+        let file = self.fds.get(fd).ok_or(EBADF)?;
+        file.channel_for(access).cloned().ok_or(EBADF)
+    }
+
+    /// Whether I/O on a file descriptor fails with EAGAIN rather than blocking.
+    fn nonblocking(&self, fd: u32) -> bool {
+        self.fds
+            .get(fd)
+            .is_some_and(|file| file.flags() & O_NONBLOCK != 0)
+    }
+
+    /// Whether the process is selected by the pid argument of wait4().
+    fn matches(&self, pid: i32, pgid: Pid) -> bool {
+        match pid {
+            -1 => true,
+            0 => *self.pgid.borrow() == pgid,
+            pid if pid < 0 => *self.pgid.borrow() == pid.unsigned_abs(),
+            pid => self.id == pid as Pid,
+        }
+    }
+
+    /// Returns the status reported by wait4(), if the process has terminated.
+    fn wait_status(&self) -> Option<i32> {
+        match &*self.state.borrow() {
+            State::Running(_) | State::Stopped(_) => None,
+            State::Exited(code) => Some((code & 0xff) << 8),
+            State::Signaled(sig) => Some(sig & 0x7f),
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        self.wait_status().is_some()
+    }
+
+    /// Takes the stop or continue status not yet reported, if requested by the wait4() options.
+    fn take_report(&self, options: i32) -> Option<i32> {
+        let status = (*self.report.borrow())?;
+        let wanted = if status == WSTATUS_CONTINUED {
+            WCONTINUED
+        } else {
+            WUNTRACED
+        };
+        if options & wanted == 0 {
+            return None;
+        }
+        self.report.replace(None)
+    }
+
+    /// If the process is stopped, returns a promise that resolves once it is continued.
+    fn stopped(&self) -> Result<Option<Promise>, Error> {
+        if !matches!(&*self.state.borrow(), State::Stopped(_)) {
+            return Ok(None);
+        }
+        if let Some(def) = self.resume.borrow().as_ref() {
+            return Ok(Some(def.promise()));
+        }
+        let def = js::deferred()?;
+        let promise = def.promise();
+        self.resume.replace(Some(def));
+        Ok(Some(promise))
+    }
+
+    /// Interrupts the syscall blocked in block_on(), if any.
+    fn interrupt(&self) {
+        if let Some((def, token)) = self.interrupt.replace(None) {
+            def.resolve(&token);
+        }
+    }
+
+    /// Returns a promise that resolves once any child changes state.
+    fn child_event(&self) -> Result<Promise, Error> {
+        if let Some(def) = self.child_event.borrow().as_ref() {
+            return Ok(def.promise());
+        }
+        let def = js::deferred()?;
+        let promise = def.promise();
+        self.child_event.replace(Some(def));
+        Ok(promise)
+    }
+
+    /// Unblocks anyone waiting in child_event().
+    fn signal_child(&self) {
+        if let Some(def) = self.child_event.replace(None) {
+            def.resolve(&JsValue::null());
+        }
+    }
+
+    /// Waits for a promise, accounting the time spent as blocked.
+    ///
+    /// Returns None if the wait was interrupted by a deliverable signal, in which case the syscall
+    /// should fail with EINTR.
+    async fn block_on(&self, promise: Promise) -> Result<Option<JsValue>, JsValue> {
+        if self.signals.borrow().deliverable() {
+            return Ok(None);
+        }
+
+        let def = js::deferred()?;
+        let token: JsValue = Object::new().into();
+        let race = Promise::race(&Array::of2(&promise, &def.promise()));
+        self.interrupt.replace(Some((def, token.clone())));
+
+        let start = Date::now();
+        let res = JsFuture::from(race).await;
+        self.usage.borrow_mut().blocked += Date::now() - start;
+        self.interrupt.replace(None);
+
+        match res {
+            Ok(value) if value == token => Ok(None),
+            res => res.map(Some),
+        }
+    }
+
+    /// Returns the user time in milliseconds, including that of waited-for children.
+    fn utime(&self) -> f64 {
+        let usage = self.usage.borrow();
+        let running = usage.ended.unwrap_or_else(Date::now) - usage.started - usage.blocked;
+        running.max(0.0) + usage.children
+    }
+
+    /// Waits until the program exits and returns its exit code.
+    async fn wait(&self) -> Result<i32, Error> {
+        let promise = self.image.borrow().as_ref().map(|img| img.promise.clone());
+        if let Some(promise) = promise {
+            JsFuture::from(promise).await?;
+        }
+
+        let promise = match &*self.state.borrow() {
+            State::Running(def) | State::Stopped(def) => Some(def.promise()),
+            _ => None,
+        };
+        if let Some(promise) = promise {
+            JsFuture::from(promise).await?;
+        }
+
+        match &*self.state.borrow() {
+            State::Exited(code) => Ok(*code),
+            // Same as the exit status reported by shells.
+            State::Signaled(sig) => Ok(128 + sig),
+            _ => Err(Error::new(&format!("proc: pid {}: zombie", self.id))),
+        }
+    }
+}
+
+impl Image {
+    fn new(
+        kernel: Weak<ProcessManager>,
+        pid: Pid,
+        ctor: Function,
+        wasm: &Uint8Array,
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<Self, Error> {
+        let module = Rc::new(RefCell::new(None));
+        let context = Rc::new(RefCell::new(vec![pid]));
+
+        let callbacks = Callbacks::new(&kernel, &module, &context, envp);
+        let (name, arguments) = argv.split_first().ok_or(Error::new("proc: empty argv"))?;
+        let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+
+        let promise: Promise = ctor
+            .call1(
+                &JsValue::null(),
+                &js::Builder::new()
+                    .set("thisProgram", name)?
+                    .set("arguments", js::str_array(&arguments))?
+                    .set("wasmBinary", wasm.clone())?
+                    .set("exit", callbacks.exit.as_ref())?
+                    // OS init:
+                    .set("os.set_module", callbacks.set_module.as_ref())?
+                    .set("os.init_module", callbacks.init_module.as_ref())?
+                    .set("os.init_runtime", callbacks.init_runtime.as_ref())?
+                    // Mocked syscalls & functions:
+                    .set("os.getpid", callbacks.getpid.as_ref())?
+                    .set("os.getppid", callbacks.getppid.as_ref())?
+                    .set("os.vfork", callbacks.vfork.as_ref())?
+                    .set("os.vfork_exit", callbacks.vfork_exit.as_ref())?
+                    .set("os.execve", callbacks.execve.as_ref())?
+                    .set("os.waitpid", callbacks.waitpid.as_ref())?
+                    .set("os.wait4", callbacks.wait4.as_ref())?
+                    .set("os.read", callbacks.read.as_ref())?
+                    .set("os.write", callbacks.write.as_ref())?
+                    .set("os.pipe", callbacks.pipe.as_ref())?
+                    .set("os.poll", callbacks.poll.as_ref())?
+                    .set("os.epoll_create", callbacks.epoll_create.as_ref())?
+                    .set("os.epoll_ctl", callbacks.epoll_ctl.as_ref())?
+                    .set("os.epoll_wait", callbacks.epoll_wait.as_ref())?
+                    // File descriptors:
+                    .set("os.close", callbacks.close.as_ref())?
+                    .set("os.dup3", callbacks.dup3.as_ref())?
+                    .set("os.fcntl", callbacks.fcntl.as_ref())?
+                    .set("os.isatty", callbacks.isatty.as_ref())?
+                    .set("os.nanosleep", callbacks.nanosleep.as_ref())?
+                    // Files:
+                    .set("os.open", callbacks.open.as_ref())?
+                    .set("os.lseek", callbacks.lseek.as_ref())?
+                    .set("os.fstat", callbacks.fstat.as_ref())?
+                    .set("os.stat", callbacks.stat.as_ref())?
+                    .set("os.readlink", callbacks.readlink.as_ref())?
+                    .set("os.getdents", callbacks.getdents.as_ref())?
+                    .set("os.ftruncate", callbacks.ftruncate.as_ref())?
+                    .set("os.unlink", callbacks.unlink.as_ref())?
+                    .set("os.mkdir", callbacks.mkdir.as_ref())?
+                    .set("os.symlink", callbacks.symlink.as_ref())?
+                    .set("os.rename", callbacks.rename.as_ref())?
+                    .set("os.chdir", callbacks.chdir.as_ref())?
+                    .set("os.getcwd", callbacks.getcwd.as_ref())?
+                    .set("os.access", callbacks.access.as_ref())?
+                    .set("os.chmod", callbacks.chmod.as_ref())?
+                    .set("os.utimens", callbacks.utimens.as_ref())?
+                    .set("os.statfs", callbacks.statfs.as_ref())?
+                    .set("os.mount", callbacks.mount.as_ref())?
+                    .set("os.umount", callbacks.umount.as_ref())?
+                    // Signals:
+                    .set("os.kill", callbacks.kill.as_ref())?
+                    .set("os.sigaction", callbacks.sigaction.as_ref())?
+                    .set("os.sigprocmask", callbacks.sigprocmask.as_ref())?
+                    .set("os.sigpending", callbacks.sigpending.as_ref())?
+                    .set("os.sigsuspend", callbacks.sigsuspend.as_ref())?
+                    .set("os.signal_deliver", callbacks.signal_deliver.as_ref())?
+                    .set("os.sigreturn", callbacks.sigreturn.as_ref())?
+                    // Process groups, sessions & job control:
+                    .set("os.getpgid", callbacks.getpgid.as_ref())?
+                    .set("os.setpgid", callbacks.setpgid.as_ref())?
+                    .set("os.getsid", callbacks.getsid.as_ref())?
+                    .set("os.setsid", callbacks.setsid.as_ref())?
+                    .set("os.tcgetpgrp", callbacks.tcgetpgrp.as_ref())?
+                    .set("os.tcsetpgrp", callbacks.tcsetpgrp.as_ref())?
+                    .into(),
+            )?
+            .into();
+
+        Ok(Self {
+            promise,
+            module,
+            callbacks,
+        })
+    }
+
+    /// Returns the size of the module's memory in bytes, 0 until it is instantiated.
+    fn memory(&self) -> u64 {
+        self.module
+            .borrow()
+            .as_ref()
+            .map_or(0, |module| module.heap().byte_length() as u64)
+    }
+}
+
+impl Callbacks {
+    fn new(
+        kernel: &Weak<ProcessManager>,
+        module: &Rc<RefCell<Option<js::Module>>>,
+        context: &Context,
+        envp: &[String],
+    ) -> Self {
+        Self {
+            exit: Self::exit(kernel.clone(), context.clone()),
+
+            set_module: Self::set_module(module.clone()),
+            init_module: Self::init_module(envp.to_vec()),
+            init_runtime: Self::init_runtime(),
+
+            getpid: Self::getpid(context.clone()),
+            getppid: Self::getppid(kernel.clone(), context.clone()),
+            vfork: Self::vfork(kernel.clone(), context.clone()),
+            vfork_exit: Self::vfork_exit(kernel.clone(), context.clone()),
+            execve: Self::execve(kernel.clone(), module.clone(), context.clone()),
+            waitpid: Self::waitpid(kernel.clone(), module.clone(), context.clone()),
+            wait4: Self::wait4(kernel.clone(), module.clone(), context.clone()),
+            read: Self::read(kernel.clone(), module.clone(), context.clone()),
+            write: Self::write(kernel.clone(), module.clone(), context.clone()),
+            pipe: Self::pipe(kernel.clone(), module.clone(), context.clone()),
+            poll: Self::poll(kernel.clone(), module.clone(), context.clone()),
+            epoll_create: Self::epoll_create(kernel.clone(), context.clone()),
+            epoll_ctl: Self::epoll_ctl(kernel.clone(), module.clone(), context.clone()),
+            epoll_wait: Self::epoll_wait(kernel.clone(), module.clone(), context.clone()),
+
+            close: Self::close(kernel.clone(), context.clone()),
+            dup3: Self::dup3(kernel.clone(), context.clone()),
+            fcntl: Self::fcntl(kernel.clone(), context.clone()),
+            isatty: Self::isatty(kernel.clone(), context.clone()),
+            nanosleep: Self::nanosleep(kernel.clone(), module.clone(), context.clone()),
+
+            open: Self::open(kernel.clone(), module.clone(), context.clone()),
+            lseek: Self::lseek(kernel.clone(), module.clone(), context.clone()),
+            fstat: Self::fstat(kernel.clone(), module.clone(), context.clone()),
+            stat: Self::stat(kernel.clone(), module.clone(), context.clone()),
+            readlink: Self::readlink(kernel.clone(), module.clone(), context.clone()),
+            getdents: Self::getdents(kernel.clone(), module.clone(), context.clone()),
+            ftruncate: Self::ftruncate(kernel.clone(), context.clone()),
+            unlink: Self::unlink(kernel.clone(), module.clone(), context.clone()),
+            mkdir: Self::mkdir(kernel.clone(), module.clone(), context.clone()),
+            symlink: Self::symlink(kernel.clone(), module.clone(), context.clone()),
+            rename: Self::rename(kernel.clone(), module.clone(), context.clone()),
+            chdir: Self::chdir(kernel.clone(), module.clone(), context.clone()),
+            getcwd: Self::getcwd(kernel.clone(), module.clone(), context.clone()),
+            access: Self::access(kernel.clone(), module.clone(), context.clone()),
+            chmod: Self::chmod(kernel.clone(), module.clone(), context.clone()),
+            utimens: Self::utimens(kernel.clone(), module.clone(), context.clone()),
+            statfs: Self::statfs(kernel.clone(), module.clone(), context.clone()),
+            mount: Self::mount(kernel.clone(), module.clone(), context.clone()),
+            umount: Self::umount(kernel.clone(), module.clone(), context.clone()),
+
+            kill: Self::kill(kernel.clone(), context.clone()),
+            sigaction: Self::sigaction(kernel.clone(), module.clone(), context.clone()),
+            sigprocmask: Self::sigprocmask(kernel.clone(), module.clone(), context.clone()),
+            sigpending: Self::sigpending(kernel.clone(), module.clone(), context.clone()),
+            sigsuspend: Self::sigsuspend(kernel.clone(), module.clone(), context.clone()),
+            signal_deliver: Self::signal_deliver(kernel.clone(), module.clone(), context.clone()),
+            sigreturn: Self::sigreturn(kernel.clone(), context.clone()),
+
+            getpgid: Self::getpgid(kernel.clone(), context.clone()),
+            setpgid: Self::setpgid(kernel.clone(), context.clone()),
+            getsid: Self::getsid(kernel.clone(), context.clone()),
+            setsid: Self::setsid(kernel.clone(), context.clone()),
+            tcgetpgrp: Self::tcgetpgrp(kernel.clone(), context.clone()),
+            tcsetpgrp: Self::tcsetpgrp(kernel.clone(), context.clone()),
+        }
+    }
+
+    pub fn exit(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn FnMut(i32)> {
+        Closure::new(move |code: i32| {
+            // The image always exits on behalf of its owner, vforked children use vfork_exit.
+            let Some(pid) = context.borrow().first().copied() else {
+                return; // replaced by execve()
+            };
+            if let Err(errno) = Self::kernel(&kernel).and_then(|k| k.exit(pid, State::Exited(code)))
+            {
+                js::error(&format!("proc: exit: {}", errno));
+            }
+        })
+    }
+
+    pub fn set_module(module: Rc<RefCell<Option<js::Module>>>) -> Closure<dyn Fn(js::Module)> {
+        Closure::new(move |js_module: js::Module| {
+            module.replace(Some(js_module));
+        })
+    }
+
+    pub fn init_module(envp: Vec<String>) -> Closure<dyn Fn(Object)> {
+        Closure::new(move |env: Object| {
+            if envp.is_empty() {
+                for (key, value) in [("USER", "snail"), ("HOME", "/home/snail")] {
+                    if Reflect::set(&env, &key.into(), &JsString::from(value)).is_err() {
+                        js::error(&format!("proc: module init: failed to set {}", key));
+                    }
+                }
+            } else {
+                // Replace the default environment with the one passed to execve().
+                for key in Object::keys(&env).iter() {
+                    let _ = Reflect::delete_property(&env, &key);
+                }
+                for var in envp.iter() {
+                    let (key, value) = var.split_once('=').unwrap_or((var, ""));
+                    if Reflect::set(&env, &key.into(), &value.into()).is_err() {
+                        js::error(&format!("proc: module init: failed to set {}", key));
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn init_runtime() -> Closure<dyn Fn()> {
+        Closure::new(move || {})
+    }
+
+    pub fn getpid(context: Context) -> Closure<dyn Fn() -> Pid> {
+        Closure::new(move || Self::pid(&context))
+    }
+
+    pub fn getppid(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn() -> Pid> {
+        Closure::new(move || {
+            Self::kernel(&kernel)
+                .and_then(|k| k.getppid(Self::pid(&context)))
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn vfork(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn() -> Promise> {
+        Closure::new(move || {
+            let ppid = Self::pid(&context);
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: vfork() from pid {}", ppid));
+
+            match Self::kernel(&kernel).and_then(|k| k.vfork(ppid)) {
+                Ok(pid) => {
+                    // Until execve() or _exit(), the image runs on behalf of the child.
+                    context.borrow_mut().push(pid);
+                    Promise::resolve(&pid.into())
+                }
+                Err(errno) => Promise::resolve(&(-errno).into()),
+            }
+        })
+    }
+
+    pub fn vfork_exit(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn(i32)> {
+        Closure::new(move |code: i32| {
+            let pid = {
+                let mut context = context.borrow_mut();
+                if context.len() < 2 {
+                    js::error("proc: vfork_exit: not a vforked child");
+                    return;
+                }
+                context.pop().unwrap()
+            };
+            if let Err(errno) = Self::kernel(&kernel).and_then(|k| k.exit(pid, State::Exited(code)))
+            {
+                js::error(&format!("proc: vfork_exit: {}", errno));
+            }
+        })
+    }
+
+    pub fn execve(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, u32, u32) -> Promise> {
+        Closure::new(move |path: u32, argv: u32, envp: u32| -> Promise {
+            let Some((path, argv, envp)) = module.borrow().as_ref().map(|m| {
+                (
+                    m.read_cstr(path),
+                    m.read_cstr_array(argv),
+                    m.read_cstr_array(envp),
+                )
+            }) else {
+                return Promise::resolve(&(-EINVAL).into());
+            };
+
+            let pid = Self::pid(&context);
+            #[cfg(feature = "dbg")]
+            js::log(&format!(
+                "proc: execve({}, {:?}, …) in pid {}",
+                path, argv, pid
+            ));
+
+            let kernel = kernel.clone();
+            let context = context.clone();
+            future_to_promise(async move {
+                let res = match kernel.upgrade() {
+                    Some(k) => k.execve(pid, &path, &argv, &envp).await,
+                    None => Err(ESRCH),
+                };
+                if let Err(errno) = res {
+                    return Ok((-errno).into());
+                }
+
+                // The new image runs on behalf of pid, this one no longer does.
+                Self::leave(&context);
+                Ok(0.into())
+            })
+        })
+    }
+
+    pub fn waitpid(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32) -> Promise> {
+        Closure::new(move |pid: i32, status: u32, options: i32| -> Promise {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: waitpid({}, {}, {})?", pid, status, options));
+
+            Self::wait(&kernel, &module, &context, pid, status, options, 0)
+        })
+    }
+
+    pub fn wait4(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32, u32) -> Promise> {
+        Closure::new(
+            move |pid: i32, status: u32, options: i32, rusage: u32| -> Promise {
+                #[cfg(feature = "dbg")]
+                js::log(&format!(
+                    "proc: wait4({}, {}, {}, {})?",
+                    pid, status, options, rusage
+                ));
+
+                Self::wait(&kernel, &module, &context, pid, status, options, rusage)
+            },
+        )
+    }
+
+    /// Shared implementation of waitpid() and wait4().
+    ///
+    /// Resolves to the pid of the child, 0 with WNOHANG, or a negative errno. The wait status and
+    /// resource usage are written to the status and rusage pointers unless they are NULL.
+    fn wait(
+        kernel: &Weak<ProcessManager>,
+        module: &Rc<RefCell<Option<js::Module>>>,
+        context: &Context,
+        pid: i32,
+        status: u32,
+        options: i32,
+        rusage: u32,
+    ) -> Promise {
+        let ppid = Self::pid(context);
+        let kernel = kernel.clone();
+        let module = module.clone();
+        future_to_promise(async move {
+            let res = match kernel.upgrade() {
+                Some(k) => k.wait4(ppid, pid, options).await,
+                None => Err(ESRCH),
+            };
+            let waited = match res {
+                Ok(Some(waited)) => waited,
+                Ok(None) => return Ok(0.into()),
+                Err(errno) => return Ok((-errno).into()),
+            };
+
+            if let Some(module) = module.borrow().as_ref() {
+                if status != 0 {
+                    module.write_u32(status, waited.status as u32);
+                }
+                if rusage != 0 {
+                    // Only ru_utime is filled, see Usage; everything else is zero.
+                    let mut buf = [0u8; RUSAGE_SIZE];
+                    let usec = (waited.utime * 1000.0) as i64;
+                    buf[0..8].copy_from_slice(&(usec / 1_000_000).to_le_bytes());
+                    buf[8..16].copy_from_slice(&(usec % 1_000_000).to_le_bytes());
+                    module.write_bytes(rusage, &buf);
+                }
+            }
+
+            Ok(waited.pid.into())
+        })
+    }
+
+    pub fn read(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> Promise> {
+        Closure::new(move |fd: i32, buf: u32, count: u32| -> Promise {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: read({}, {}, {})?", fd, buf, count));
+
+            let Ok(fd) = Self::fd(fd) else {
+                return Promise::resolve(&(-EBADF).into());
+            };
+            let proc = match Self::process(&kernel, &context) {
+                Ok(proc) => proc,
+                Err(errno) => return Promise::resolve(&(-errno).into()),
+            };
+            if proc
+                .fds
+                .get(fd)
+                .is_some_and(|file| file.fs_file().is_some())
+            {
+                // Files never block.
+                let ret = Self::syscall(&kernel, |k| {
+                    let data = k.read_file(proc.id, fd, count as usize)?;
+                    if let Some(module) = module.borrow().as_ref() {
+                        module.write_bytes(buf, &data);
+                    }
+                    Ok(data.len() as u32)
+                });
+                return Promise::resolve(&ret.into());
+            }
+            let channel = match proc.channel(fd, Access::Read) {
+                Ok(channel) => channel,
+                Err(errno) => return Promise::resolve(&(-errno).into()),
+            };
+            if proc.fds.get(fd).is_some_and(|file| file.is_tty()) {
+                if let Some(Err(errno)) = kernel.upgrade().map(|k| k.tty_read(proc.id)) {
+                    return Promise::resolve(&(-errno).into());
+                }
+            }
+            if proc.nonblocking(fd) && !channel.readable() {
+                return Promise::resolve(&(-EAGAIN).into());
+            }
+            match channel.read_promise(&module, buf, count) {
+                Err(errno) => Promise::resolve(&(-errno).into()),
+                Ok(promise) => future_to_promise(async move {
+                    match proc.block_on(promise).await? {
+                        Some(count) => Ok(count),
+                        None => {
+                            channel.cancel_read();
+                            Ok((-EINTR).into())
+                        }
+                    }
+                }),
+            }
+        })
+    }
+
+    /// Writes the buffers of an array of struct iovec to a channel, see writev(2).
+    ///
+    /// Returns the number of bytes written or a negative errno.
+    pub fn write(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> Promise> {
+        Closure::new(move |fd: i32, iov: u32, iovcnt: u32| -> Promise {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: writev({}, {}, {})?", fd, iov, iovcnt));
+
+            let Ok(fd) = Self::fd(fd) else {
+                return Promise::resolve(&(-EBADF).into());
+            };
+            let Some(data) = module.borrow().as_ref().map(|m| m.read_iovec(iov, iovcnt)) else {
+                return Promise::resolve(&(-EINVAL).into());
+            };
+            let pid = Self::pid(&context);
+            let kernel = kernel.clone();
+            future_to_promise(async move {
+                Ok(match Self::kernel(&kernel)?.write(pid, fd, data).await {
+                    Ok(count) => (count as i32).into(),
+                    Err(errno) => (-errno).into(),
+                })
+            })
+        })
+    }
+
+    /// Creates a pipe, writing the two file descriptors to fds.
+    pub fn pipe(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, i32) -> i32> {
+        Closure::new(move |fds: u32, flags: i32| -> i32 {
+            let pid = Self::pid(&context);
+            let res = Self::syscall(&kernel, |k| {
+                let (read, write) = k.pipe(pid, flags)?;
+                if let Some(module) = module.borrow().as_ref() {
+                    module.write_u32(fds, read);
+                    module.write_u32(fds + 4, write);
+                }
+                Ok(0)
+            });
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: pipe() = {} in pid {}", res, pid));
+            res
+        })
+    }
+
+    /// Waits for events on an array of struct pollfd, writing the revents of each.
+    ///
+    /// Resolves to the number of file descriptors that are ready, or a negative errno. A negative
+    /// timeout waits indefinitely.
+    pub fn poll(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, u32, f64) -> Promise> {
+        Closure::new(move |ptr: u32, nfds: u32, timeout: f64| -> Promise {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: poll({}, {}, {})?", ptr, nfds, timeout));
+
+            let Some(fds) = module.borrow().as_ref().map(|module| {
+                (0..nfds)
+                    .map(|i| {
+                        let pollfd = module.read_bytes(ptr + i * 8, 8);
+                        (
+                            i32::from_le_bytes([pollfd[0], pollfd[1], pollfd[2], pollfd[3]]),
+                            i16::from_le_bytes([pollfd[4], pollfd[5]]),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }) else {
+                return Promise::resolve(&(-EINVAL).into());
+            };
+            let pid = Self::pid(&context);
+            let kernel = kernel.clone();
+            let module = module.clone();
+            future_to_promise(async move {
+                let timeout = (timeout >= 0.0).then_some(timeout);
+                let revents = match Self::kernel(&kernel)?.poll(pid, &fds, timeout).await {
+                    Ok(revents) => revents,
+                    Err(errno) => return Ok((-errno).into()),
+                };
+                if let Some(module) = module.borrow().as_ref() {
+                    for (i, events) in revents.iter().enumerate() {
+                        module.write_bytes(ptr + i as u32 * 8 + 6, &events.to_le_bytes());
+                    }
+                }
+                Ok((revents.iter().filter(|&&events| events != 0).count() as i32).into())
+            })
+        })
+    }
+
+    pub fn epoll_create(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32) -> i32> {
+        Closure::new(move |flags: i32| -> i32 {
+            Self::syscall(&kernel, |k| k.epoll_create(Self::pid(&context), flags))
+        })
+    }
+
+    /// Changes the interest list of an epoll instance, reading a struct epoll_event.
+    pub fn epoll_ctl(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i32, i32, u32) -> i32> {
+        Closure::new(move |epfd: i32, op: i32, fd: i32, event: u32| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: epoll_ctl({}, {}, {})", epfd, op, fd));
+
+            let interest = match module.borrow().as_ref() {
+                Some(module) if event != 0 => Interest {
+                    events: module.read_u32(event),
+                    data: module.read_u32(event + 8) as u64
+                        | (module.read_u32(event + 12) as u64) << 32,
+                },
+                _ => Interest { events: 0, data: 0 },
+            };
+            Self::syscall(&kernel, |k| {
+                let (epfd, fd) = (Self::fd(epfd)?, Self::fd(fd)?);
+                k.epoll_ctl(Self::pid(&context), epfd, op, fd, interest)
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    /// Waits for events on an epoll instance, writing them to an array of struct epoll_event.
+    ///
+    /// Resolves to the number of events, or a negative errno. A negative timeout waits
+    /// indefinitely.
+    pub fn epoll_wait(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32, f64) -> Promise> {
+        Closure::new(
+            move |epfd: i32, events: u32, max: i32, timeout: f64| -> Promise {
+                let (Ok(epfd), Ok(max)) = (Self::fd(epfd), usize::try_from(max)) else {
+                    return Promise::resolve(&(-EINVAL).into());
+                };
+                let pid = Self::pid(&context);
+                let kernel = kernel.clone();
+                let module = module.clone();
+                future_to_promise(async move {
+                    let timeout = (timeout >= 0.0).then_some(timeout);
+                    let k = Self::kernel(&kernel)?;
+                    let ready = match k.epoll_wait(pid, epfd, max, timeout).await {
+                        Ok(ready) => ready,
+                        Err(errno) => return Ok((-errno).into()),
+                    };
+                    if let Some(module) = module.borrow().as_ref() {
+                        for (i, event) in ready.iter().enumerate() {
+                            let ptr = events + i as u32 * EPOLL_EVENT_SIZE;
+                            module.write_u32(ptr, event.events);
+                            module.write_u32(ptr + 8, event.data as u32);
+                            module.write_u32(ptr + 12, (event.data >> 32) as u32);
+                        }
+                    }
+                    Ok((ready.len() as i32).into())
+                })
+            },
+        )
+    }
+
+    pub fn close(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn(i32) -> i32> {
+        Closure::new(move |fd: i32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                k.close(Self::pid(&context), Self::fd(fd)?)?;
+                Ok(0)
+            })
+        })
+    }
+
+    /// Duplicates old to new, returning new.
+    pub fn dup3(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i32, i32) -> i32> {
+        Closure::new(move |old: i32, new: i32, flags: i32| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: dup3({}, {}, {})", old, new, flags));
+
+            Self::syscall(&kernel, |k| {
+                let (old, new) = (Self::fd(old)?, Self::fd(new)?);
+                k.dup3(Self::pid(&context), old, new, flags)?;
+                Ok(new)
+            })
+        })
+    }
+
+    pub fn fcntl(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i32, i32) -> i32> {
+        Closure::new(move |fd: i32, cmd: i32, arg: i32| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: fcntl({}, {}, {})", fd, cmd, arg));
+
+            Self::syscall(&kernel, |k| {
+                k.fcntl(Self::pid(&context), Self::fd(fd)?, cmd, arg)
+                    .map(|ret| ret as u32)
+            })
+        })
+    }
+
+    /// Returns 1 if fd refers to the terminal, 0 if not, or a negative errno.
+    pub fn isatty(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn(i32) -> i32> {
+        Closure::new(move |fd: i32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                k.isatty(Self::pid(&context), Self::fd(fd)?).map(u32::from)
+            })
+        })
+    }
+
+    /// Opens a file relative to dirfd.
+    pub fn open(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, flags: i32, mode: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                #[cfg(feature = "dbg")]
+                js::log(&format!("proc: open({}, {}, {:#o})", dirfd, path, flags));
+                k.open(Self::pid(&context), dirfd, &path, flags, mode)
+            })
+        })
+    }
+
+    /// Repositions the offset of a file, writing the new offset to result as a 64-bit word.
+    pub fn lseek(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i64, i32, u32) -> i32> {
+        Closure::new(
+            move |fd: i32, offset: i64, whence: i32, result: u32| -> i32 {
+                Self::syscall(&kernel, |k| {
+                    let offset = k.lseek(Self::pid(&context), Self::fd(fd)?, offset, whence)?;
+                    if let Some(module) = module.borrow().as_ref() {
+                        module.write_bytes(result, &offset.to_le_bytes());
+                    }
+                    Ok(0)
+                })
+            },
+        )
+    }
+
+    /// Writes the status of the file behind fd to a struct os_stat.
+    pub fn fstat(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32) -> i32> {
+        Closure::new(move |fd: i32, buf: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let stat = k.fstat(Self::pid(&context), Self::fd(fd)?)?;
+                Self::write_stat(&module, buf, &stat)
+            })
+        })
+    }
+
+    /// Writes the status of the file at path, relative to dirfd, to a struct os_stat.
+    pub fn stat(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32, i32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, buf: u32, flags: i32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                let stat = k.stat(Self::pid(&context), dirfd, &path, flags)?;
+                Self::write_stat(&module, buf, &stat)
+            })
+        })
+    }
+
+    /// Writes the target of the symbolic link at path, relative to dirfd, to buf without a
+    /// terminating NUL, truncated to size bytes. Returns the number of bytes written.
+    pub fn readlink(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, buf: u32, size: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                let target = k.readlink(Self::pid(&context), dirfd, &path)?;
+                let target = &target.as_bytes()[..target.len().min(size as usize)];
+                if let Some(module) = module.borrow().as_ref() {
+                    module.write_bytes(buf, target);
+                }
+                Ok(target.len() as u32)
+            })
+        })
+    }
+
+    /// Writes up to count bytes of struct linux_dirent64 records to dirp, returning the number of
+    /// bytes written.
+    pub fn getdents(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |fd: i32, dirp: u32, count: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let records = k.getdents(Self::pid(&context), Self::fd(fd)?, count as usize)?;
+                if let Some(module) = module.borrow().as_ref() {
+                    module.write_bytes(dirp, &records);
+                }
+                Ok(records.len() as u32)
+            })
+        })
+    }
+
+    pub fn ftruncate(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i64) -> i32> {
+        Closure::new(move |fd: i32, len: i64| -> i32 {
+            Self::syscall(&kernel, |k| {
+                k.ftruncate(Self::pid(&context), Self::fd(fd)?, len)
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    pub fn unlink(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, flags: i32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                k.unlink(Self::pid(&context), dirfd, &path, flags)
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    pub fn mkdir(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, mode: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                k.mkdir(Self::pid(&context), dirfd, &path, mode).map(|_| 0)
+            })
+        })
+    }
+
+    /// Creates a symbolic link at path pointing to target, which is not resolved.
+    pub fn symlink(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, i32, u32) -> i32> {
+        Closure::new(move |target: u32, dirfd: i32, path: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let target = Self::read_path(&module, target)?;
+                let path = Self::read_path(&module, path)?;
+                k.symlink(Self::pid(&context), &target, dirfd, &path)
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    pub fn rename(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, i32, u32) -> i32> {
+        Closure::new(
+            move |old_dirfd: i32, old: u32, new_dirfd: i32, new: u32| -> i32 {
+                Self::syscall(&kernel, |k| {
+                    let old = Self::read_path(&module, old)?;
+                    let new = Self::read_path(&module, new)?;
+                    k.rename(Self::pid(&context), old_dirfd, &old, new_dirfd, &new)
+                        .map(|_| 0)
+                })
+            },
+        )
+    }
+
+    /// Changes the working directory to path, or to the directory behind dirfd if path is NULL.
+    pub fn chdir(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_opt_path(&module, path)?;
+                k.chdir(Self::pid(&context), dirfd, path.as_deref())
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    /// Writes the working directory to buf, returning its length including the NUL terminator,
+    /// or -ERANGE if it does not fit in size bytes.
+    pub fn getcwd(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, u32) -> i32> {
+        Closure::new(move |buf: u32, size: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let mut cwd = k.getcwd(Self::pid(&context))?.into_bytes();
+                cwd.push(0);
+                if cwd.len() > size as usize {
+                    return Err(ERANGE);
+                }
+                if let Some(module) = module.borrow().as_ref() {
+                    module.write_bytes(buf, &cwd);
+                }
+                Ok(cwd.len() as u32)
+            })
+        })
+    }
+
+    pub fn access(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, mode: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_path(&module, path)?;
+                k.access(Self::pid(&context), dirfd, &path, mode).map(|_| 0)
+            })
+        })
+    }
+
+    /// Changes the permissions of the file at path, or behind dirfd if path is NULL.
+    pub fn chmod(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, mode: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_opt_path(&module, path)?;
+                k.chmod(Self::pid(&context), dirfd, path.as_deref(), mode)
+                    .map(|_| 0)
+            })
+        })
+    }
+
+    /// Sets the times of the file at path, or behind dirfd if path is NULL, in milliseconds since
+    /// the epoch. NaN leaves a time unchanged.
+    pub fn utimens(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, f64, f64) -> i32> {
+        Closure::new(
+            move |dirfd: i32, path: u32, atime: f64, mtime: f64| -> i32 {
+                Self::syscall(&kernel, |k| {
+                    let path = Self::read_opt_path(&module, path)?;
+                    let time = |ms: f64| (!ms.is_nan()).then_some(ms);
+                    k.utimens(
+                        Self::pid(&context),
+                        dirfd,
+                        path.as_deref(),
+                        time(atime),
+                        time(mtime),
+                    )
+                    .map(|_| 0)
+                })
+            },
+        )
+    }
+
+    /// Writes statistics of the filesystem containing the file at path, or behind dirfd if path
+    /// is NULL, to a struct os_statfs.
+    pub fn statfs(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |dirfd: i32, path: u32, buf: u32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let path = Self::read_opt_path(&module, path)?;
+                let statfs = k.statfs(Self::pid(&context), dirfd, path.as_deref())?;
+                Self::write_statfs(&module, buf, &statfs)
+            })
+        })
+    }
+
+    /// Mounts a filesystem of type fstype on target, with comma-separated options. The source,
+    /// fstype and options may be NULL.
+    pub fn mount(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, u32, u32, u32, u32) -> i32> {
+        Closure::new(
+            move |source: u32, target: u32, fstype: u32, flags: u32, options: u32| -> i32 {
+                Self::syscall(&kernel, |k| {
+                    let source = Self::read_opt_path(&module, source)?.unwrap_or_default();
+                    let target = Self::read_path(&module, target)?;
+                    let fstype = Self::read_opt_path(&module, fstype)?.unwrap_or_default();
+                    let options = Self::read_opt_path(&module, options)?.unwrap_or_default();
+                    let pid = Self::pid(&context);
+                    k.mount(pid, &source, &target, &fstype, flags, &options)
+                        .map(|_| 0)
+                })
+            },
+        )
+    }
+
+    /// Unmounts the filesystem mounted on target.
+    pub fn umount(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, i32) -> i32> {
+        Closure::new(move |target: u32, flags: i32| -> i32 {
+            Self::syscall(&kernel, |k| {
+                let target = Self::read_path(&module, target)?;
+                k.umount(Self::pid(&context), &target, flags).map(|_| 0)
+            })
+        })
+    }
+
+    fn fd(fd: i32) -> Result<u32, Errno> {
+        u32::try_from(fd).map_err(|_| EBADF)
+    }
+
+    /// Sleeps for ms milliseconds.
+    ///
+    /// Resolves to 0, or -EINTR if interrupted, in which case the remaining time in milliseconds
+    /// is written to rem as a double, unless it is NULL.
+    pub fn nanosleep(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(f64, u32) -> Promise> {
+        Closure::new(move |ms: f64, rem: u32| -> Promise {
+            let pid = Self::pid(&context);
+            let kernel = kernel.clone();
+            let module = module.clone();
+            future_to_promise(async move {
+                let remaining = match Self::kernel(&kernel)?.nanosleep(pid, ms).await {
+                    Ok(None) => return Ok(0.into()),
+                    Ok(Some(remaining)) => remaining,
+                    Err(errno) => return Ok((-errno).into()),
+                };
+                if let (Some(module), true) = (module.borrow().as_ref(), rem != 0) {
+                    module.write_bytes(rem, &remaining.to_le_bytes());
+                }
+                Ok((-EINTR).into())
+            })
+        })
+    }
+
+    pub fn kill(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, i32) -> i32> {
+        Closure::new(move |pid: i32, sig: i32| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: kill({}, {})", pid, sig));
+
+            match kernel.upgrade() {
+                Some(k) => k
+                    .kill(Self::pid(&context), pid, sig)
+                    .map_or_else(|e| -e, |_| 0),
+                None => -ESRCH,
+            }
+        })
+    }
+
+    /// Examines and changes a signal action.
+    ///
+    /// Both act and oldact point to a struct os_sigaction, see wrap.c; either may be NULL.
+    pub fn sigaction(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |sig: i32, act: u32, oldact: u32| -> i32 {
+            let Some(module) = module.borrow().as_ref().cloned() else {
+                return -EINVAL;
+            };
+            let Some(kernel) = kernel.upgrade() else {
+                return -ESRCH;
+            };
+
+            let action = (act != 0).then(|| Self::read_action(&module, act));
+            match kernel.sigaction(Self::pid(&context), sig, action) {
+                Ok(old) => {
+                    if oldact != 0 {
+                        Self::write_action(&module, oldact, old);
+                    }
+                    0
+                }
+                Err(errno) => -errno,
+            }
+        })
+    }
+
+    /// Examines and changes the signal mask.
+    ///
+    /// Both set and oldset point to a 64-bit signal set; either may be NULL.
+    pub fn sigprocmask(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(i32, u32, u32) -> i32> {
+        Closure::new(move |how: i32, set: u32, oldset: u32| -> i32 {
+            let Some(module) = module.borrow().as_ref().cloned() else {
+                return -EINVAL;
+            };
+            let Some(kernel) = kernel.upgrade() else {
+                return -ESRCH;
+            };
+
+            let set = (set != 0).then(|| Self::read_sigset(&module, set));
+            match kernel.sigprocmask(Self::pid(&context), how, set) {
+                Ok(old) => {
+                    if oldset != 0 {
+                        Self::write_sigset(&module, oldset, old);
+                    }
+                    0
+                }
+                Err(errno) => -errno,
+            }
+        })
+    }
+
+    pub fn sigpending(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32) -> i32> {
+        Closure::new(move |set: u32| -> i32 {
+            let pending = Self::kernel(&kernel).and_then(|k| k.sigpending(Self::pid(&context)));
+            match (pending, module.borrow().as_ref()) {
+                (Ok(pending), Some(module)) => {
+                    Self::write_sigset(module, set, pending);
+                    0
+                }
+                (Err(errno), _) => -errno,
+                (_, None) => -EINVAL,
+            }
+        })
+    }
+
+    /// Waits for a signal with the mask temporarily replaced.
+    ///
+    /// Resolves to -EINTR once a signal can be delivered. The old mask is written to oldset, so
+    /// that libwrap can restore it after running the handlers.
+    pub fn sigsuspend(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32, u32) -> Promise> {
+        Closure::new(move |set: u32, oldset: u32| -> Promise {
+            let Some(module) = module.borrow().as_ref().cloned() else {
+                return Promise::resolve(&(-EINVAL).into());
+            };
+            let pid = Self::pid(&context);
+            let kernel = kernel.clone();
+            future_to_promise(async move {
+                let mask = Self::read_sigset(&module, set);
+                match Self::kernel(&kernel)?.sigsuspend(pid, mask).await {
+                    Ok(old) => Self::write_sigset(&module, oldset, old),
+                    Err(errno) => return Ok((-errno).into()),
+                }
+                Ok((-EINTR).into())
+            })
+        })
+    }
+
+    /// Takes the next pending signal that needs a handler, applying default actions on the way.
+    ///
+    /// Resolves to the signal number, with its action written to the struct os_sigaction at act;
+    /// to 0 if there is nothing (left) to deliver; or to -sig if the process has been terminated,
+    /// in which case libwrap must not return to the program.
+    pub fn signal_deliver(
+        kernel: Weak<ProcessManager>,
+        module: Rc<RefCell<Option<js::Module>>>,
+        context: Context,
+    ) -> Closure<dyn Fn(u32) -> Promise> {
+        Closure::new(move |act: u32| -> Promise {
+            let pid = Self::pid(&context);
+            let kernel = kernel.clone();
+            let module = module.clone();
+            let context = context.clone();
+            future_to_promise(async move {
+                match Self::kernel(&kernel)?.deliver(pid).await? {
+                    Delivery::Done => Ok(0.into()),
+                    Delivery::Handle(sig, action) => {
+                        if let Some(module) = module.borrow().as_ref() {
+                            Self::write_action(module, act, action);
+                        }
+                        Ok(sig.into())
+                    }
+                    Delivery::Terminated(sig) => {
+                        #[cfg(feature = "dbg")]
+                        js::log(&format!("proc: pid {} terminated by signal {}", pid, sig));
+
+                        Self::leave(&context);
+                        Ok((-sig).into())
+                    }
+                }
+            })
+        })
+    }
+
+    pub fn sigreturn(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn()> {
+        Closure::new(move || {
+            if let Err(errno) = Self::kernel(&kernel).and_then(|k| k.sigreturn(Self::pid(&context)))
+            {
+                js::error(&format!("proc: sigreturn: {}", errno));
+            }
+        })
+    }
+
+    pub fn getpgid(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn(Pid) -> i32> {
+        Closure::new(move |pid: Pid| -> i32 {
+            Self::syscall(&kernel, |k| k.getpgid(Self::pid(&context), pid))
+        })
+    }
+
+    pub fn setpgid(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(Pid, Pid) -> i32> {
+        Closure::new(move |pid: Pid, pgid: Pid| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: setpgid({}, {})", pid, pgid));
+
+            Self::syscall(&kernel, |k| {
+                k.setpgid(Self::pid(&context), pid, pgid).map(|_| 0)
+            })
+        })
+    }
+
+    pub fn getsid(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn(Pid) -> i32> {
+        Closure::new(move |pid: Pid| -> i32 {
+            Self::syscall(&kernel, |k| k.getsid(Self::pid(&context), pid))
+        })
+    }
+
+    pub fn setsid(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn() -> i32> {
+        Closure::new(move || -> i32 { Self::syscall(&kernel, |k| k.setsid(Self::pid(&context))) })
+    }
+
+    pub fn tcgetpgrp(kernel: Weak<ProcessManager>, context: Context) -> Closure<dyn Fn() -> i32> {
+        Closure::new(move || -> i32 {
+            Self::syscall(&kernel, |k| k.tcgetpgrp(Self::pid(&context)))
+        })
+    }
+
+    pub fn tcsetpgrp(
+        kernel: Weak<ProcessManager>,
+        context: Context,
+    ) -> Closure<dyn Fn(Pid) -> i32> {
+        Closure::new(move |pgrp: Pid| -> i32 {
+            #[cfg(feature = "dbg")]
+            js::log(&format!("proc: tcsetpgrp({})", pgrp));
+
+            Self::syscall(&kernel, |k| {
+                k.tcsetpgrp(Self::pid(&context), pgrp).map(|_| 0)
+            })
+        })
+    }
+
+    /// Runs a synchronous syscall, returning its result or a negative errno.
+    fn syscall(
+        kernel: &Weak<ProcessManager>,
+        f: impl FnOnce(&ProcessManager) -> Result<u32, Errno>,
+    ) -> i32 {
+        match kernel.upgrade() {
+            Some(k) => f(&k).map_or_else(|errno| -errno, |value| value as i32),
+            None => -ESRCH,
+        }
+    }
+
+    /// Reads a struct os_sigaction: handler, flags, then the mask as two 32-bit words.
+    fn read_action(module: &js::Module, ptr: u32) -> Action {
+        Action {
+            handler: module.read_u32(ptr),
+            flags: module.read_u32(ptr + 4),
+            mask: Self::read_sigset(module, ptr + 8),
+        }
+    }
+
+    fn write_action(module: &js::Module, ptr: u32, action: Action) {
+        module.write_u32(ptr, action.handler);
+        module.write_u32(ptr + 4, action.flags);
+        Self::write_sigset(module, ptr + 8, action.mask);
+    }
+
+    fn read_sigset(module: &js::Module, ptr: u32) -> SigSet {
+        SigSet::join(module.read_u32(ptr), module.read_u32(ptr + 4))
+    }
+
+    fn write_sigset(module: &js::Module, ptr: u32, set: SigSet) {
+        let (lo, hi) = set.split();
+        module.write_u32(ptr, lo);
+        module.write_u32(ptr + 4, hi);
+    }
+
+    /// Reads a path passed to a syscall.
+    fn read_path(module: &Rc<RefCell<Option<js::Module>>>, ptr: u32) -> Result<String, Errno> {
+        let module = module.borrow();
+        Ok(module.as_ref().ok_or(EINVAL)?.read_cstr(ptr))
+    }
+
+    /// Reads a path passed to a syscall, which may be NULL.
+    fn read_opt_path(
+        module: &Rc<RefCell<Option<js::Module>>>,
+        ptr: u32,
+    ) -> Result<Option<String>, Errno> {
+        match ptr {
+            0 => Ok(None),
+            ptr => Self::read_path(module, ptr).map(Some),
+        }
+    }
+
+    /// Writes a struct os_stat: ino, mode, nlink, size, dev, rdev, blksize and padding, then the
+    /// access, modification and status change times in milliseconds.
+    fn write_stat(
+        module: &Rc<RefCell<Option<js::Module>>>,
+        ptr: u32,
+        stat: &Stat,
+    ) -> Result<u32, Errno> {
+        let mut bytes = Vec::with_capacity(STAT_SIZE);
+        bytes.extend(stat.ino.to_le_bytes());
+        bytes.extend(stat.mode.to_le_bytes());
+        bytes.extend(stat.nlink.to_le_bytes());
+        bytes.extend(stat.size.to_le_bytes());
+        bytes.extend(stat.dev.to_le_bytes());
+        bytes.extend(stat.rdev.to_le_bytes());
+        bytes.extend(BLKSIZE.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        for time in [stat.atime, stat.mtime, stat.ctime] {
+            bytes.extend(time.to_le_bytes());
+        }
+        module
+            .borrow()
+            .as_ref()
+            .ok_or(EINVAL)?
+            .write_bytes(ptr, &bytes);
+        Ok(0)
+    }
+
+    /// Writes a struct os_statfs: type and block size, the counts of blocks and inodes, total and
+    /// free, then the maximum name length and the mount flags.
+    fn write_statfs(
+        module: &Rc<RefCell<Option<js::Module>>>,
+        ptr: u32,
+        statfs: &StatFs,
+    ) -> Result<u32, Errno> {
+        let mut bytes = Vec::with_capacity(STATFS_SIZE);
+        bytes.extend(statfs.magic.to_le_bytes());
+        bytes.extend(BLKSIZE.to_le_bytes());
+        for count in [statfs.blocks, statfs.bfree, statfs.files, statfs.ffree] {
+            bytes.extend(count.to_le_bytes());
+        }
+        bytes.extend(statfs.namelen.to_le_bytes());
+        bytes.extend(statfs.flags.to_le_bytes());
+        module
+            .borrow()
+            .as_ref()
+            .ok_or(EINVAL)?
+            .write_bytes(ptr, &bytes);
+        Ok(0)
+    }
+
+    /// Returns the pid on whose behalf the image is currently running.
+    fn pid(context: &Context) -> Pid {
+        context.borrow().last().copied().unwrap_or_default()
+    }
+
+    /// Stops running on behalf of the current pid, e.g. once it has called execve() or has been
+    /// terminated: the image resumes the vfork() parent, if any, or no longer runs at all.
+    fn leave(context: &Context) {
+        let mut context = context.borrow_mut();
+        if context.len() > 1 {
+            context.pop();
+        } else {
+            context.clear();
+        }
+    }
+
+    fn kernel(kernel: &Weak<ProcessManager>) -> Result<Rc<ProcessManager>, Errno> {
+        kernel.upgrade().ok_or(ESRCH)
+    }
+
+    fn process(kernel: &Weak<ProcessManager>, context: &Context) -> Result<Rc<Process>, Errno> {
+        Self::kernel(kernel)?.get(Self::pid(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::SIGTSTP;
+
+    fn process(id: Pid, pgid: Pid) -> Process {
+        Process::new(id, 0, pgid, pgid, FdTable::new(), Signals::new()).unwrap()
+    }
+
+    /// Adds a session leader started by the kernel, like exec() without loading a program.
+    fn spawn(processes: &ProcessManager) -> Pid {
+        let pid = processes.next_pid();
+        let proc = process(pid, pid);
+        processes.map.borrow_mut().insert(pid, Rc::new(proc));
+        pid
+    }
+
+    #[test]
+    fn test_vfork() {
+        let processes = ProcessManager::new(Some(0));
+        let parent = spawn(&processes);
+        let child = processes.vfork(parent).unwrap();
+        assert_eq!(processes.getppid(child), Ok(parent));
+        assert!(processes
+            .get(parent)
+            .unwrap()
+            .children
+            .borrow()
+            .contains(&child));
+        assert_eq!(processes.getpgid(child, 0), Ok(parent));
+        assert_eq!(processes.getsid(child, 0), Ok(parent));
+        assert_eq!(processes.vfork(42), Err(ESRCH));
+    }
+
+    #[test]
+    fn test_reparent_children() {
+        let processes = ProcessManager::new(Some(0));
+        let init = spawn(&processes);
+        let parent = processes.vfork(init).unwrap();
+        let running = processes.vfork(parent).unwrap();
+        let zombie = processes.vfork(parent).unwrap();
+        processes
+            .get(zombie)
+            .unwrap()
+            .state
+            .replace(State::Exited(0));
+
+        processes.reparent_children(&processes.get(parent).unwrap());
+        assert!(processes.get(parent).unwrap().children.borrow().is_empty());
+        assert_eq!(processes.getppid(running), Ok(INIT_PID));
+        assert_eq!(processes.getppid(zombie), Ok(INIT_PID));
+        let init = processes.get(init).unwrap();
+        assert_eq!(
+            *init.children.borrow(),
+            BTreeSet::from([parent, running, zombie])
+        );
+
+        // Without init, zombies are reaped right away.
+        processes.map.borrow_mut().remove(&INIT_PID);
+        processes.reparent_children(&init);
+        assert!(processes.get(running).is_ok());
+        assert!(processes.get(zombie).is_err());
+    }
+
+    #[test]
+    fn test_setpgid_setsid() {
+        let processes = ProcessManager::new(Some(0));
+        let leader = spawn(&processes);
+        let child = processes.vfork(leader).unwrap();
+        let other = spawn(&processes);
+
+        // Session leaders cannot move, nor start another session.
+        assert_eq!(processes.setpgid(leader, 0, 0), Err(EPERM));
+        assert_eq!(processes.setsid(leader), Err(EPERM));
+        // Only the caller and its children can be moved, within the session.
+        assert_eq!(processes.setpgid(other, child, 0), Err(ESRCH));
+        assert_eq!(processes.setpgid(leader, child, other), Err(EPERM));
+        assert_eq!(processes.setpgid(leader, child, 0), Ok(()));
+        assert_eq!(processes.getpgid(child, 0), Ok(child));
+        assert_eq!(processes.setpgid(child, 0, leader), Ok(()));
+
+        assert_eq!(processes.setsid(child), Ok(child));
+        assert_eq!(processes.getpgid(child, 0), Ok(child));
+        assert_eq!(processes.setpgid(leader, child, leader), Err(EPERM));
+    }
+
+    #[test]
+    fn test_wait_status() {
+        let proc = process(2, 2);
+        assert_eq!(proc.wait_status(), None);
+        proc.state.replace(State::Exited(0x1ff));
+        assert_eq!(proc.wait_status(), Some(0xff00));
+        proc.state.replace(State::Signaled(SIGKILL));
+        assert_eq!(proc.wait_status(), Some(SIGKILL));
+    }
+
+    #[test]
+    fn test_take_report() {
+        let proc = process(2, 2);
+        proc.report.replace(Some(SIGTSTP << 8 | 0x7f));
+        assert_eq!(proc.take_report(WCONTINUED), None);
+        assert_eq!(proc.take_report(WUNTRACED), Some(0x147f));
+
+        proc.report.replace(Some(WSTATUS_CONTINUED));
+        assert_eq!(proc.take_report(WUNTRACED), None);
+        assert_eq!(proc.take_report(WCONTINUED), Some(WSTATUS_CONTINUED));
+        assert_eq!(proc.take_report(WCONTINUED), None); // reported once
+    }
+
+    #[test]
+    fn test_matches() {
+        let proc = process(5, 3);
+        assert!(proc.matches(5, 1));
+        assert!(!proc.matches(6, 3));
+        assert!(proc.matches(0, 3));
+        assert!(!proc.matches(0, 4));
+        assert!(proc.matches(-1, 4));
+        assert!(proc.matches(-3, 4));
+        assert!(!proc.matches(-4, 3));
     }
 }
@@ -0,0 +1,389 @@
+use std::{
+    fmt::Write,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    compilation_mode::COMPILATION_MODE,
+    errno::Errno::{self, ENOENT},
+    os::VERSION,
+    proc::{Pid, ProcessInfo, ProcessManager},
+    signal::SigSet,
+    vfs::{self, DirEntry, FileSystem, Ino, Node, StatFs, Synthetic, S_IFDIR, S_IFLNK, S_IFREG},
+};
+
+/// Magic number reported by statfs(), see statfs(2).
+const PROC_SUPER_MAGIC: u32 = 0x9fa0;
+
+/// Path of the link to the caller's own directory, see resolve_self().
+pub const SELF: &str = "/proc/self";
+
+/// Clock ticks per second, the unit of times in /proc/<pid>/stat, see sysconf(_SC_CLK_TCK).
+const CLK_TCK: f64 = 100.0;
+
+/// Page size, the unit of the resident set size in /proc/<pid>/stat: a WebAssembly page, as
+/// returned by getpagesize().
+const PAGE_SIZE: u64 = 65536;
+
+/// Memory reported as installed: the address space of a single wasm32 module.
+pub const MEM_TOTAL: u64 = 1 << 32;
+
+/// Device number of the terminal, as if it was /dev/pts/0.
+const TTY_NR: u32 = 136 << 8;
+
+/// Files at the root, besides the process directories, and their inode numbers.
+const FILES: [(&str, Ino); 6] = [
+    ("loadavg", 2),
+    ("meminfo", 3),
+    ("mounts", 4),
+    ("stat", 5),
+    ("uptime", 6),
+    ("version", 7),
+];
+
+/// Inode number of /proc/self.
+const SELF_INO: Ino = 8;
+
+/// Entries of a process directory, and their inode numbers relative to the directory's.
+const PROCESS_FILES: [(&str, Ino); 7] = [
+    ("cmdline", 1),
+    ("cwd", 2),
+    ("environ", 3),
+    ("exe", 4),
+    ("fd", 5),
+    ("stat", 6),
+    ("status", 7),
+];
+
+/// Inode numbers of the links in fd/, relative to the process directory's.
+const FD_INO: Ino = 0x1000;
+
+/// Process information pseudo-filesystem, see proc(5).
+///
+/// Files are generated from the kernel state each time they are looked up, so an open file keeps
+/// showing the state at the time it was opened.
+pub struct ProcFs {
+    processes: Weak<ProcessManager>,
+}
+
+impl ProcFs {
+    pub fn new(processes: Weak<ProcessManager>) -> Self {
+        Self { processes }
+    }
+
+    fn processes(&self) -> Result<Rc<ProcessManager>, Errno> {
+        self.processes.upgrade().ok_or(ENOENT)
+    }
+
+    fn root(&self) -> Result<Rc<dyn Node>, Errno> {
+        let mut entries: Vec<DirEntry> = self
+            .processes()?
+            .pids()
+            .into_iter()
+            .map(|pid| entry(&pid.to_string(), process_ino(pid), S_IFDIR))
+            .collect();
+        entries.push(entry("self", SELF_INO, S_IFLNK));
+        for (name, ino) in FILES {
+            entries.push(entry(name, ino, S_IFREG));
+        }
+        Ok(Rc::new(Synthetic::dir(1, 0o555, entries)))
+    }
+
+    /// Generates a file at the root.
+    fn file(&self, name: &str) -> Result<String, Errno> {
+        let processes = self.processes()?;
+        let infos: Vec<ProcessInfo> = processes
+            .pids()
+            .into_iter()
+            .filter_map(|pid| processes.info(pid).ok())
+            .collect();
+        let uptime = (vfs::now() - processes.booted()) / 1e3;
+        let busy = infos.iter().map(|info| info.utime).sum::<f64>() / 1e3;
+        let idle = (uptime - busy).max(0.0);
+        let running = infos.iter().filter(|info| info.state == 'R').count();
+
+        let mut out = String::new();
+        match name {
+            "loadavg" => {
+                let last = infos.last().map_or(0, |info| info.pid);
+                let total = infos.len();
+                writeln!(out, "0.00 0.00 0.00 {}/{} {}", running, total, last).ok();
+            }
+            "meminfo" => {
+                let used: u64 = infos.iter().map(|info| info.memory).sum();
+                let free = MEM_TOTAL.saturating_sub(used);
+                for (key, bytes) in [
+                    ("MemTotal", MEM_TOTAL),
+                    ("MemFree", free),
+                    ("MemAvailable", free),
+                    ("Buffers", 0),
+                    ("Cached", 0),
+                    ("SwapTotal", 0),
+                    ("SwapFree", 0),
+                ] {
+                    writeln!(out, "{:<15} {:>8} kB", format!("{}:", key), bytes / 1024).ok();
+                }
+            }
+            "mounts" => out = processes.vfs().mounts().to_string(),
+            "stat" => {
+                // A single CPU, busy whenever a process is not blocked.
+                let user = (busy * CLK_TCK) as u64;
+                let idle = (idle * CLK_TCK) as u64;
+                for cpu in ["cpu ", "cpu0"] {
+                    writeln!(out, "{} {} 0 0 {} 0 0 0 0 0 0", cpu, user, idle).ok();
+                }
+                writeln!(out, "btime {}", (processes.booted() / 1e3) as u64).ok();
+                writeln!(out, "procs_running {}", running).ok();
+                writeln!(out, "procs_blocked {}", infos.len() - running).ok();
+            }
+            "uptime" => {
+                writeln!(out, "{:.2} {:.2}", uptime, idle).ok();
+            }
+            "version" => {
+                writeln!(
+                    out,
+                    "SnailOS version {}-{} (wasm32)",
+                    VERSION, COMPILATION_MODE
+                )
+                .ok();
+            }
+            _ => return Err(ENOENT),
+        }
+        Ok(out)
+    }
+
+    /// Looks up path within the directory of a process.
+    fn process(&self, pid: Pid, path: &[&str]) -> Result<Rc<dyn Node>, Errno> {
+        let processes = self.processes()?;
+        let info = processes.info(pid).map_err(|_| ENOENT)?;
+        let base = process_ino(pid);
+        let node: Synthetic = match path {
+            [] => {
+                let entries = PROCESS_FILES
+                    .iter()
+                    .map(|&(name, ino)| {
+                        let kind = match name {
+                            "cwd" | "exe" => S_IFLNK,
+                            "fd" => S_IFDIR,
+                            _ => S_IFREG,
+                        };
+                        entry(name, base + ino, kind)
+                    })
+                    .collect();
+                Synthetic::dir(base, 0o555, entries)
+            }
+            ["cmdline"] => Synthetic::file(base + 1, 0o444, nul_separated(&info.cmdline)),
+            ["cwd"] => Synthetic::link(base + 2, info.cwd),
+            ["environ"] => Synthetic::file(base + 3, 0o400, nul_separated(&info.environ)),
+            ["exe"] => Synthetic::link(base + 4, info.exe.ok_or(ENOENT)?),
+            ["fd"] => {
+                let entries = info
+                    .fds
+                    .iter()
+                    .map(|(fd, _)| entry(&fd.to_string(), base + FD_INO + *fd as Ino, S_IFLNK))
+                    .collect();
+                Synthetic::dir(base + 5, 0o500, entries)
+            }
+            ["fd", fd] => {
+                let (fd, link) = info
+                    .fds
+                    .into_iter()
+                    .find(|(n, _)| n.to_string() == *fd)
+                    .ok_or(ENOENT)?;
+                Synthetic::link(base + FD_INO + fd as Ino, link)
+            }
+            ["stat"] => {
+                let stat = stat(&info, processes.booted());
+                Synthetic::file(base + 6, 0o444, stat.into_bytes())
+            }
+            ["status"] => Synthetic::file(base + 7, 0o444, status(&info).into_bytes()),
+            _ => return Err(ENOENT),
+        };
+        Ok(Rc::new(node))
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn lookup(&self, path: &str) -> Result<Rc<dyn Node>, Errno> {
+        let parts: Vec<&str> = vfs::components(path).collect();
+        match parts.as_slice() {
+            [] => self.root(),
+            // Substituted before lookups, see resolve_self().
+            ["self", ..] => Err(ENOENT),
+            [name, rest @ ..] => match FILES.iter().find(|(file, _)| file == name) {
+                Some(&(name, ino)) if rest.is_empty() => {
+                    let data = self.file(name)?.into_bytes();
+                    Ok(Rc::new(Synthetic::file(ino, 0o444, data)))
+                }
+                _ => self.process(name.parse().map_err(|_| ENOENT)?, rest),
+            },
+        }
+    }
+
+    fn statfs(&self) -> StatFs {
+        StatFs {
+            magic: PROC_SUPER_MAGIC,
+            namelen: 255,
+            ..Default::default()
+        }
+    }
+}
+
+/// Replaces /proc/self with the directory of the given process in an absolute path.
+///
+/// What /proc/self refers to depends on the caller, which filesystems know nothing about, so it
+/// is substituted while resolving paths rather than looked up. This assumes procfs is mounted on
+/// /proc, as it is at boot.
+pub fn resolve_self(path: &str, pid: Pid) -> String {
+    match path.strip_prefix(SELF) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("/proc/{}{}", pid, rest),
+        _ => path.to_string(),
+    }
+}
+
+fn process_ino(pid: Pid) -> Ino {
+    (pid as Ino) << 16
+}
+
+fn entry(name: &str, ino: Ino, kind: u32) -> DirEntry {
+    DirEntry {
+        name: name.to_string(),
+        ino,
+        kind: (kind >> 12) as u8,
+    }
+}
+
+fn nul_separated(items: &[String]) -> Vec<u8> {
+    items
+        .iter()
+        .flat_map(|item| item.bytes().chain(std::iter::once(0)))
+        .collect()
+}
+
+/// Returns the name of the executable, as in the second field of /proc/<pid>/stat.
+fn comm(info: &ProcessInfo) -> String {
+    let path = info
+        .exe
+        .as_deref()
+        .or(info.cmdline.first().map(String::as_str));
+    let name = path.and_then(|path| path.rsplit('/').next()).unwrap_or("");
+    // TASK_COMM_LEN, including the terminating NUL.
+    name.chars().take(15).collect()
+}
+
+/// Formats /proc/<pid>/stat, see proc_pid_stat(5).
+fn stat(info: &ProcessInfo, booted: f64) -> String {
+    let ticks = |ms: f64| (ms.max(0.0) / 1e3 * CLK_TCK) as u64;
+    let tpgid = info.tty.map_or(-1, |pgrp| pgrp as i64);
+    let mut fields = vec![
+        info.ppid.to_string(),
+        info.pgid.to_string(),
+        info.sid.to_string(),
+        info.tty.map_or(0, |_| TTY_NR).to_string(),
+        tpgid.to_string(),
+    ];
+    // flags, minflt, cminflt, majflt and cmajflt.
+    fields.extend(["0"; 5].map(String::from));
+    fields.extend([
+        ticks(info.utime).to_string(),
+        "0".to_string(),
+        ticks(info.cutime).to_string(),
+        "0".to_string(),
+        // priority, nice, num_threads and itrealvalue.
+        "20".to_string(),
+        "0".to_string(),
+        "1".to_string(),
+        "0".to_string(),
+        ticks(info.started - booted).to_string(),
+        info.memory.to_string(),
+        info.memory.div_ceil(PAGE_SIZE).to_string(),
+        u64::MAX.to_string(),
+    ]);
+    // The remaining fields, up to exit_code, are not tracked.
+    fields.resize(49, "0".to_string());
+    format!(
+        "{} ({}) {} {}\n",
+        info.pid,
+        comm(info),
+        info.state,
+        fields.join(" ")
+    )
+}
+
+/// Formats /proc/<pid>/status, see proc_pid_status(5).
+fn status(info: &ProcessInfo) -> String {
+    let state = match info.state {
+        'R' => "R (running)",
+        'S' => "S (sleeping)",
+        'T' => "T (stopped)",
+        _ => "Z (zombie)",
+    };
+    let mut out = String::new();
+    writeln!(out, "Name:\t{}", comm(info)).ok();
+    writeln!(out, "State:\t{}", state).ok();
+    writeln!(out, "Tgid:\t{}", info.pid).ok();
+    writeln!(out, "Pid:\t{}", info.pid).ok();
+    writeln!(out, "PPid:\t{}", info.ppid).ok();
+    writeln!(out, "Uid:\t0\t0\t0\t0").ok();
+    writeln!(out, "Gid:\t0\t0\t0\t0").ok();
+    writeln!(out, "FDSize:\t{}", info.fds.len()).ok();
+    writeln!(out, "VmSize:\t{:>8} kB", info.memory / 1024).ok();
+    writeln!(out, "VmRSS:\t{:>8} kB", info.memory / 1024).ok();
+    writeln!(out, "Threads:\t1").ok();
+    for (key, set) in [
+        ("SigPnd", info.pending),
+        ("SigBlk", info.blocked),
+        ("SigIgn", info.ignored),
+        ("SigCgt", info.caught),
+    ] {
+        let SigSet(bits) = set;
+        writeln!(out, "{}:\t{:016x}", key, bits).ok();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ProcessInfo {
+        ProcessInfo {
+            pid: 7,
+            ppid: 1,
+            pgid: 7,
+            sid: 1,
+            tty: Some(7),
+            state: 'S',
+            cmdline: vec!["ls".to_string(), "-l".to_string()],
+            environ: Vec::new(),
+            cwd: "/".to_string(),
+            exe: Some("/bin/busybox".to_string()),
+            fds: Vec::new(),
+            started: 2500.0,
+            utime: 120.0,
+            cutime: 0.0,
+            memory: 1 << 20,
+            pending: SigSet::default(),
+            blocked: SigSet(1 << 1),
+            ignored: SigSet::default(),
+            caught: SigSet::default(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_self() {
+        assert_eq!(resolve_self("/proc/self", 3), "/proc/3");
+        assert_eq!(resolve_self("/proc/self/fd/0", 3), "/proc/3/fd/0");
+        assert_eq!(resolve_self("/proc/selfish", 3), "/proc/selfish");
+    }
+
+    #[test]
+    fn test_stat() {
+        let stat = stat(&info(), 500.0);
+        assert!(stat.starts_with("7 (busybox) S 1 7 1 34816 7 0 0 0 0 0 12 0 0 0 20 0 1 0 200 "));
+        // Up to exit_code.
+        assert_eq!(stat.split(' ').count(), 52);
+        assert_eq!(nul_separated(&info().cmdline), b"ls\0-l\0");
+        assert!(status(&info()).contains("SigBlk:\t0000000000000002\n"));
+    }
+}
@@ -0,0 +1,434 @@
+/// Signal number, following Linux, which is what musl (and therefore Emscripten) uses.
+pub type Signal = i32;
+
+pub const SIGHUP: Signal = 1;
+pub const SIGINT: Signal = 2;
+pub const SIGQUIT: Signal = 3;
+pub const SIGILL: Signal = 4;
+pub const SIGTRAP: Signal = 5;
+pub const SIGABRT: Signal = 6;
+pub const SIGBUS: Signal = 7;
+pub const SIGFPE: Signal = 8;
+pub const SIGKILL: Signal = 9;
+pub const SIGSEGV: Signal = 11;
+pub const SIGPIPE: Signal = 13;
+pub const SIGCHLD: Signal = 17;
+pub const SIGCONT: Signal = 18;
+pub const SIGSTOP: Signal = 19;
+pub const SIGTSTP: Signal = 20;
+pub const SIGTTIN: Signal = 21;
+pub const SIGTTOU: Signal = 22;
+pub const SIGURG: Signal = 23;
+pub const SIGXCPU: Signal = 24;
+pub const SIGXFSZ: Signal = 25;
+pub const SIGWINCH: Signal = 28;
+pub const SIGSYS: Signal = 31;
+
+/// Highest signal number, including real-time signals.
+pub const SIGMAX: Signal = 64;
+
+// Special handler values.
+pub const SIG_DFL: u32 = 0;
+pub const SIG_IGN: u32 = 1;
+
+// Flags for sigaction().
+pub const SA_NOCLDSTOP: u32 = 1;
+pub const SA_NODEFER: u32 = 0x40000000;
+pub const SA_RESETHAND: u32 = 0x80000000;
+
+// Values of the how argument of sigprocmask().
+pub const SIG_BLOCK: i32 = 0;
+pub const SIG_UNBLOCK: i32 = 1;
+pub const SIG_SETMASK: i32 = 2;
+
+/// Set of signals, bit n - 1 represents signal n.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SigSet(pub u64);
+
+/// What happens when a signal is delivered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Action {
+    /// SIG_DFL, SIG_IGN or a function pointer in the module's table.
+    pub handler: u32,
+    /// Signals blocked while the handler runs.
+    pub mask: SigSet,
+    pub flags: u32,
+}
+
+/// Action taken for a signal with the SIG_DFL disposition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Core,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+/// Outcome of taking the next pending signal off the queue.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Disposition {
+    /// Run the handler in the module.
+    Handle(Signal, Action),
+    /// Apply the default action.
+    Default(Signal, DefaultAction),
+}
+
+/// Per-process signal state.
+///
+/// Handlers live in the Wasm module; the kernel only keeps track of dispositions, masks and
+/// pending signals, and tells libwrap which handler to run at syscall boundaries.
+#[derive(Clone, Default)]
+pub struct Signals {
+    actions: Vec<Action>,
+    blocked: SigSet,
+    pending: SigSet,
+    // Masks to restore when the running handlers return.
+    saved: Vec<SigSet>,
+}
+
+impl SigSet {
+    pub fn contains(&self, sig: Signal) -> bool {
+        valid(sig) && self.0 & bit(sig) != 0
+    }
+
+    pub fn insert(&mut self, sig: Signal) {
+        if valid(sig) {
+            self.0 |= bit(sig);
+        }
+    }
+
+    pub fn remove(&mut self, sig: Signal) {
+        if valid(sig) {
+            self.0 &= !bit(sig);
+        }
+    }
+
+    /// Splits the set into low and high 32-bit words, as passed through libwrap.
+    pub fn split(&self) -> (u32, u32) {
+        (self.0 as u32, (self.0 >> 32) as u32)
+    }
+
+    pub fn join(lo: u32, hi: u32) -> Self {
+        Self(lo as u64 | (hi as u64) << 32)
+    }
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Self {
+            actions: vec![Action::default(); SIGMAX as usize],
+            ..Default::default()
+        }
+    }
+
+    /// Signal state inherited by a child: dispositions and mask, but no pending signals.
+    pub fn fork(&self) -> Self {
+        Self {
+            actions: self.actions.clone(),
+            blocked: self.blocked,
+            ..Default::default()
+        }
+    }
+
+    /// Resets handled signals to their default on execve(); ignored signals stay ignored.
+    pub fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = Action::default();
+            }
+        }
+        self.saved.clear();
+    }
+
+    pub fn action(&self, sig: Signal) -> Action {
+        if !valid(sig) {
+            return Action::default();
+        }
+        self.actions[sig as usize - 1]
+    }
+
+    /// Replaces the action for a signal, returning the old one.
+    ///
+    /// Returns None for invalid signals and for SIGKILL and SIGSTOP, which cannot be caught.
+    pub fn set_action(&mut self, sig: Signal, action: Action) -> Option<Action> {
+        if !valid(sig) || sig == SIGKILL || sig == SIGSTOP {
+            return None;
+        }
+        let old = std::mem::replace(&mut self.actions[sig as usize - 1], action);
+        if self.ignores(sig) {
+            // Setting a signal to be ignored discards it, even if it is blocked.
+            self.pending.remove(sig);
+        }
+        Some(old)
+    }
+
+    pub fn blocked(&self) -> SigSet {
+        self.blocked
+    }
+
+    pub fn pending(&self) -> SigSet {
+        self.pending
+    }
+
+    /// Returns the signals whose disposition is SIG_IGN.
+    pub fn ignored(&self) -> SigSet {
+        self.with_handler(|handler| handler == SIG_IGN)
+    }
+
+    /// Returns the signals that have a handler.
+    pub fn caught(&self) -> SigSet {
+        self.with_handler(|handler| handler != SIG_DFL && handler != SIG_IGN)
+    }
+
+    fn with_handler(&self, pred: impl Fn(u32) -> bool) -> SigSet {
+        let mut set = SigSet::default();
+        for (i, action) in self.actions.iter().enumerate() {
+            if pred(action.handler) {
+                set.insert(i as Signal + 1);
+            }
+        }
+        set
+    }
+
+    /// Updates the signal mask as sigprocmask() would, returning the old mask.
+    pub fn procmask(&mut self, how: i32, set: SigSet) -> Option<SigSet> {
+        let old = self.blocked;
+        self.blocked = match how {
+            SIG_BLOCK => SigSet(old.0 | set.0),
+            SIG_UNBLOCK => SigSet(old.0 & !set.0),
+            SIG_SETMASK => set,
+            _ => return None,
+        };
+        self.blocked.remove(SIGKILL);
+        self.blocked.remove(SIGSTOP);
+        Some(old)
+    }
+
+    /// Whether the signal would be discarded right away.
+    pub fn ignores(&self, sig: Signal) -> bool {
+        match self.action(sig).handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(sig) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// Marks a signal as pending.
+    ///
+    /// Returns whether the signal can be delivered right away, in which case anything the process
+    /// is blocked on should be interrupted.
+    pub fn post(&mut self, sig: Signal) -> bool {
+        match sig {
+            SIGCONT => {
+                for stop in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU] {
+                    self.pending.remove(stop);
+                }
+            }
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => self.pending.remove(SIGCONT),
+            _ => {}
+        }
+        if !self.blocked.contains(sig) && self.ignores(sig) {
+            return false;
+        }
+        self.pending.insert(sig);
+        self.deliverable()
+    }
+
+    /// Whether any pending signal is not blocked.
+    pub fn deliverable(&self) -> bool {
+        self.pending.0 & !self.blocked.0 != 0
+    }
+
+    /// Takes the lowest deliverable signal off the queue.
+    ///
+    /// Ignored signals are discarded. If a handler is to be run, the mask is updated accordingly;
+    /// call sigreturn() once the handler returns to restore it.
+    pub fn next(&mut self) -> Option<Disposition> {
+        loop {
+            let ready = self.pending.0 & !self.blocked.0;
+            if ready == 0 {
+                return None;
+            }
+            let sig = ready.trailing_zeros() as Signal + 1;
+            self.pending.remove(sig);
+
+            let action = self.action(sig);
+            match action.handler {
+                SIG_IGN => continue,
+                SIG_DFL => match default_action(sig) {
+                    DefaultAction::Ignore => continue,
+                    action => return Some(Disposition::Default(sig, action)),
+                },
+                _ => {
+                    self.saved.push(self.blocked);
+                    self.blocked.0 |= action.mask.0;
+                    if action.flags & SA_NODEFER == 0 {
+                        self.blocked.insert(sig);
+                    }
+                    if action.flags & SA_RESETHAND != 0 {
+                        self.actions[sig as usize - 1] = Action::default();
+                    }
+                    return Some(Disposition::Handle(sig, action));
+                }
+            }
+        }
+    }
+
+    /// Restores the mask that was in effect before the last handler ran.
+    pub fn sigreturn(&mut self) {
+        if let Some(mask) = self.saved.pop() {
+            self.blocked = mask;
+        }
+    }
+}
+
+pub fn valid(sig: Signal) -> bool {
+    (1..=SIGMAX).contains(&sig)
+}
+
+pub fn default_action(sig: Signal) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ
+        | SIGSYS => DefaultAction::Core,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+fn bit(sig: Signal) -> u64 {
+    1 << (sig - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLER: u32 = 42;
+    // Only read by libwrap, to restart interrupted syscalls.
+    const SA_RESTART: u32 = 0x10000000;
+
+    #[test]
+    fn test_default_actions() {
+        let mut signals = Signals::new();
+
+        assert!(!signals.post(SIGCHLD)); // ignored by default
+        assert!(signals.post(SIGPIPE));
+        assert!(signals.post(SIGINT));
+
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGINT, DefaultAction::Terminate))
+        );
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGPIPE, DefaultAction::Terminate))
+        );
+        assert_eq!(signals.next(), None);
+    }
+
+    #[test]
+    fn test_blocked() {
+        let mut signals = Signals::new();
+        signals.procmask(SIG_BLOCK, SigSet(bit(SIGINT) | bit(SIGKILL)));
+
+        assert!(!signals.post(SIGINT));
+        assert!(signals.pending().contains(SIGINT));
+        assert_eq!(signals.next(), None);
+
+        // SIGKILL cannot be blocked.
+        assert!(signals.post(SIGKILL));
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGKILL, DefaultAction::Terminate))
+        );
+
+        signals.procmask(SIG_UNBLOCK, SigSet(bit(SIGINT)));
+        assert!(signals.deliverable());
+    }
+
+    #[test]
+    fn test_handler() {
+        let mut signals = Signals::new();
+        let action = Action {
+            handler: HANDLER,
+            mask: SigSet(bit(SIGQUIT)),
+            flags: SA_RESETHAND,
+        };
+        assert_eq!(signals.set_action(SIGINT, action), Some(Action::default()));
+        assert_eq!(signals.set_action(SIGKILL, action), None);
+        assert_eq!(signals.caught(), SigSet(bit(SIGINT)));
+        assert_eq!(signals.ignored(), SigSet::default());
+
+        signals.post(SIGINT);
+        assert_eq!(signals.next(), Some(Disposition::Handle(SIGINT, action)));
+        assert!(signals.blocked().contains(SIGINT));
+        assert!(signals.blocked().contains(SIGQUIT));
+        assert_eq!(signals.action(SIGINT), Action::default());
+
+        signals.sigreturn();
+        assert_eq!(signals.blocked(), SigSet::default());
+    }
+
+    #[test]
+    fn test_restart() {
+        // libwrap restarts an interrupted syscall if no handler ran, or if the handlers that did
+        // were installed with SA_RESTART, even if they reset the disposition.
+        let mut signals = Signals::new();
+        let action = Action {
+            handler: HANDLER,
+            flags: SA_RESTART | SA_RESETHAND,
+            ..Default::default()
+        };
+        signals.set_action(SIGINT, action);
+
+        assert!(signals.post(SIGINT));
+        assert_eq!(signals.next(), Some(Disposition::Handle(SIGINT, action)));
+        signals.sigreturn();
+        assert!(signals.post(SIGTSTP));
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGTSTP, DefaultAction::Stop))
+        );
+        assert!(signals.post(SIGCONT));
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGCONT, DefaultAction::Continue))
+        );
+    }
+
+    #[test]
+    fn test_stop_continue() {
+        let mut signals = Signals::new();
+        signals.procmask(SIG_BLOCK, SigSet(bit(SIGTSTP)));
+
+        signals.post(SIGTSTP);
+        signals.post(SIGCONT);
+        assert!(!signals.pending().contains(SIGTSTP));
+        assert_eq!(
+            signals.next(),
+            Some(Disposition::Default(SIGCONT, DefaultAction::Continue))
+        );
+    }
+
+    #[test]
+    fn test_exec() {
+        let mut signals = Signals::new();
+        let handled = Action {
+            handler: HANDLER,
+            ..Default::default()
+        };
+        let ignored = Action {
+            handler: SIG_IGN,
+            ..Default::default()
+        };
+        signals.set_action(SIGINT, handled);
+        signals.set_action(SIGQUIT, ignored);
+
+        signals.exec();
+        assert_eq!(signals.action(SIGINT), Action::default());
+        assert_eq!(signals.action(SIGQUIT), ignored);
+    }
+}
@@ -0,0 +1,263 @@
+use std::rc::Rc;
+
+use crate::{
+    errno::Errno::{self, EEXIST, EINVAL, ENOENT, ENOTDIR, EPERM, EROFS},
+    fd::{O_CREAT, O_TRUNC, O_WRONLY},
+    vfs::{self, Location, Vfs, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG},
+};
+
+/// Identifies the format of snapshots, followed by VERSION.
+const MAGIC: &[u8; 8] = b"snailfs\0";
+const VERSION: u32 = 1;
+
+/// Serialises the tree under the directory at path, so that restore() can recreate it.
+///
+/// The snapshot is MAGIC and VERSION, then a record per directory, regular file and symbolic
+/// link, parents first: mode, access and modification times, path relative to the tree, and
+/// contents or link target. Numbers are little-endian, and byte strings prefixed with their
+/// 64-bit length. Other kinds of files and other mounts are left out.
+pub fn export(vfs: &Vfs, path: &str) -> Result<Vec<u8>, Errno> {
+    let mut snapshot = MAGIC.to_vec();
+    snapshot.extend(VERSION.to_le_bytes());
+    export_dir(vfs, &vfs.lookup(path)?, "", &mut snapshot)?;
+    Ok(snapshot)
+}
+
+/// Recreates the tree of a snapshot in the directory at path, replacing the files that exist.
+///
+/// Fails with EINVAL if the snapshot is malformed, with what comes before restored.
+pub fn restore(vfs: &Vfs, path: &str, snapshot: &[u8]) -> Result<(), Errno> {
+    let mut unpacker = Unpacker::new(vfs, path)?;
+    let mut reader = Reader(snapshot);
+    if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+        return Err(EINVAL);
+    }
+    while !reader.0.is_empty() {
+        let mode = reader.u32()?;
+        let atime = reader.f64()?;
+        let mtime = reader.f64()?;
+        let rel = std::str::from_utf8(reader.bytes()?).map_err(|_| EINVAL)?;
+        unpacker.add(rel, mode, atime, mtime, reader.bytes()?)?;
+    }
+    unpacker.finish()
+}
+
+/// Recreates directories, regular files and symbolic links under a directory, as found in an
+/// archive, see restore().
+pub struct Unpacker<'a> {
+    vfs: &'a Vfs,
+    root: String,
+    // Directories along with their times, which are set last as adding entries changes them.
+    dirs: Vec<(Location, f64, f64)>,
+}
+
+impl<'a> Unpacker<'a> {
+    pub fn new(vfs: &'a Vfs, path: &str) -> Result<Self, Errno> {
+        Ok(Self {
+            vfs,
+            root: vfs.lookup(path)?.path,
+            dirs: Vec::new(),
+        })
+    }
+
+    /// Creates or replaces the node at a path relative to the directory, where data is the
+    /// contents of a file or the target of a link. Missing parents are created.
+    ///
+    /// Fails with EINVAL if the node would end up outside of the directory, be it through ".."
+    /// or links.
+    pub fn add(
+        &mut self,
+        rel: &str,
+        mode: u32,
+        atime: f64,
+        mtime: f64,
+        data: &[u8],
+    ) -> Result<(), Errno> {
+        let vfs = self.vfs;
+        let parts: Vec<&str> = vfs::components(rel).collect();
+        if parts.is_empty() || parts.iter().any(|part| *part == "." || *part == "..") {
+            return Err(EINVAL);
+        }
+        let mut path = self.root.clone();
+        for part in &parts[..parts.len() - 1] {
+            path = vfs::join(&path, part);
+            match vfs.lookup_link(&path) {
+                Ok(location) if location.node.is_dir() => {}
+                Ok(location) if location.is_link() => return Err(EINVAL),
+                Ok(_) => return Err(ENOTDIR),
+                Err(ENOENT) => vfs.mkdir(&path, 0o755)?,
+                Err(errno) => return Err(errno),
+            }
+        }
+        let path = vfs::join(&path, parts[parts.len() - 1]);
+        if mode & S_IFMT != S_IFDIR && vfs.lookup_link(&path).is_ok_and(|old| old.is_link()) {
+            vfs.unlink(&path)?;
+        }
+
+        let perm = mode & 0o7777;
+        let location = match mode & S_IFMT {
+            S_IFDIR => match vfs.mkdir(&path, perm) {
+                Err(EEXIST | EROFS) if vfs.lookup_link(&path)?.node.is_dir() => {
+                    // The root of a mount, e.g. /dev, is left as its filesystem has it, which may
+                    // not allow changes anyway.
+                    let location = vfs.lookup(&path)?;
+                    if location.path == location.mount.target {
+                        return Ok(());
+                    }
+                    match location.node.chmod(perm) {
+                        Err(EPERM | EROFS) => return Ok(()),
+                        result => result?,
+                    }
+                    location
+                }
+                result => result.and_then(|_| vfs.lookup(&path))?,
+            },
+            S_IFREG => {
+                let location = vfs.open(&path, O_WRONLY | O_CREAT | O_TRUNC, perm)?;
+                location.node.write_at(0, data)?;
+                location
+            }
+            S_IFLNK => {
+                let target = std::str::from_utf8(data).map_err(|_| EINVAL)?;
+                return vfs.symlink(target, &path);
+            }
+            _ => return Err(EINVAL),
+        };
+        location.node.chmod(perm)?;
+        location.node.set_times(Some(atime), Some(mtime))?;
+        if location.node.is_dir() {
+            self.dirs.push((location, atime, mtime));
+        }
+        Ok(())
+    }
+
+    /// Looks up the node at a path relative to the directory, e.g. the target of a hard link.
+    ///
+    /// Fails with EINVAL if the path goes through ".." or links, which could lead outside of the
+    /// directory.
+    pub fn lookup(&self, rel: &str) -> Result<Location, Errno> {
+        let vfs = self.vfs;
+        let mut location = vfs.lookup(&self.root)?;
+        for part in vfs::components(rel) {
+            if part == "." || part == ".." {
+                return Err(EINVAL);
+            }
+            if !location.node.is_dir() {
+                return Err(ENOTDIR);
+            }
+            location = vfs.lookup_link(&vfs::join(&location.path, part))?;
+            if location.is_link() {
+                return Err(EINVAL);
+            }
+        }
+        Ok(location)
+    }
+
+    /// Sets the times of the directories.
+    pub fn finish(self) -> Result<(), Errno> {
+        for (dir, atime, mtime) in self.dirs {
+            dir.node.set_times(Some(atime), Some(mtime))?;
+        }
+        Ok(())
+    }
+}
+
+fn export_dir(vfs: &Vfs, dir: &Location, rel: &str, out: &mut Vec<u8>) -> Result<(), Errno> {
+    for entry in dir.node.entries()? {
+        let location = vfs.lookup_link(&vfs::join(&dir.path, &entry.name))?;
+        if !Rc::ptr_eq(&location.mount, &dir.mount) {
+            continue;
+        }
+        let stat = location.stat();
+        let data = match stat.mode & S_IFMT {
+            S_IFDIR => Vec::new(),
+            S_IFREG => location.node.read_at(0, stat.size as usize)?,
+            S_IFLNK => location.node.readlink()?.into_bytes(),
+            _ => continue,
+        };
+        let rel = match rel {
+            "" => entry.name,
+            rel => format!("{}/{}", rel, entry.name),
+        };
+        out.extend(stat.mode.to_le_bytes());
+        out.extend(stat.atime.to_le_bytes());
+        out.extend(stat.mtime.to_le_bytes());
+        for bytes in [rel.as_bytes(), &data] {
+            out.extend((bytes.len() as u64).to_le_bytes());
+            out.extend(bytes);
+        }
+        if location.node.is_dir() {
+            export_dir(vfs, &location, &rel, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the fields of a snapshot in order, failing with EINVAL past its end.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Errno> {
+        if len > self.0.len() {
+            return Err(EINVAL);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Errno> {
+        self.take(N)?.try_into().map_err(|_| EINVAL)
+    }
+
+    fn u32(&mut self) -> Result<u32, Errno> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, Errno> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    /// Reads a byte string prefixed with its length.
+    fn bytes(&mut self) -> Result<&'a [u8], Errno> {
+        let len = u64::from_le_bytes(self.array()?);
+        self.take(usize::try_from(len).map_err(|_| EINVAL)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemFs;
+
+    #[test]
+    fn test_round_trip() {
+        let vfs = Vfs::new(Rc::new(MemFs::new()));
+        vfs.mkdir("/home/snail/d", 0o700).unwrap();
+        let file = vfs
+            .open("/home/snail/d/f", O_WRONLY | O_CREAT, 0o640)
+            .unwrap();
+        file.node.write_at(0, b"hello").unwrap();
+        file.node.set_times(Some(1.0), Some(2.0)).unwrap();
+        vfs.symlink("d/f", "/home/snail/l").unwrap();
+        let snapshot = export(&vfs, "/home/snail").unwrap();
+
+        let copy = Vfs::new(Rc::new(MemFs::new()));
+        restore(&copy, "/home/snail", &snapshot).unwrap();
+        let file = copy.lookup("/home/snail/l").unwrap();
+        assert_eq!(file.path, "/home/snail/d/f");
+        assert_eq!(file.node.read_at(0, 16).unwrap(), b"hello");
+        let stat = file.stat();
+        assert_eq!(
+            (stat.mode, stat.atime, stat.mtime),
+            (S_IFREG | 0o640, 1.0, 2.0)
+        );
+        let dir = copy.lookup("/home/snail/d").unwrap();
+        assert_eq!(dir.stat().mode, S_IFDIR | 0o700);
+        assert_eq!(export(&copy, "/home/snail").unwrap(), snapshot);
+
+        let truncated = &snapshot[..snapshot.len() - 1];
+        assert_eq!(restore(&copy, "/home/snail", truncated), Err(EINVAL));
+        assert_eq!(restore(&copy, "/home/snail", b"snailfs"), Err(EINVAL));
+    }
+}
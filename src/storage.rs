@@ -0,0 +1,54 @@
+use std::cell::RefCell;
+
+use js_sys::{Error, Uint8Array};
+use wasm_bindgen::JsCast;
+
+use crate::js::HostStorage;
+
+/// Keeps snapshots of the home directory across boots, see snapshot::export().
+///
+/// Where they end up is up to the host page, which passes a storage object to boot(), with a
+/// load() method returning the last snapshot saved, if any, and a save(snapshot) method replacing
+/// it. Both are synchronous, so that the page can save when it is hidden.
+pub trait Storage {
+    /// Returns the last snapshot saved, if any.
+    fn load(&self) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Replaces the last snapshot.
+    fn save(&self, snapshot: &[u8]) -> Result<(), Error>;
+}
+
+impl Storage for HostStorage {
+    fn load(&self) -> Result<Option<Vec<u8>>, Error> {
+        let snapshot = HostStorage::load(self)?;
+        if snapshot.is_null() || snapshot.is_undefined() {
+            return Ok(None);
+        }
+        let snapshot = snapshot
+            .dyn_into::<Uint8Array>()
+            .map_err(|_| Error::new("snapshot is not a Uint8Array"))?;
+        Ok(Some(snapshot.to_vec()))
+    }
+
+    fn save(&self, snapshot: &[u8]) -> Result<(), Error> {
+        Ok(HostStorage::save(self, &snapshot.into())?)
+    }
+}
+
+/// Storage in memory, used when the host page passes none: snapshots last until the page is
+/// closed.
+#[derive(Default)]
+pub struct MemoryStorage {
+    snapshot: RefCell<Option<Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.snapshot.borrow().clone())
+    }
+
+    fn save(&self, snapshot: &[u8]) -> Result<(), Error> {
+        self.snapshot.replace(Some(snapshot.to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    errno::Errno::{self, EINVAL},
+    snapshot::Unpacker,
+    vfs::{self, Location, Stat, Vfs, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG},
+};
+
+/// Size of headers and the unit of file contents, see tar(5).
+const BLOCK_SIZE: usize = 512;
+
+/// Longest name and link target that fit in a ustar header, longer ones go in pax records.
+const NAME_SIZE: usize = 100;
+
+/// Name of pax extended headers, as written by GNU tar.
+const PAX_HEADER: &str = "././@PaxHeader";
+
+/// Packs the file or directory at path into a tar archive, see tar(5).
+///
+/// Entries are named relative to the parent of path, and keep their modes and modification times.
+/// Names and link targets too long for ustar are stored in pax records. Only directories, regular
+/// files and symbolic links are packed, and other mounts are left out.
+pub fn pack(vfs: &Vfs, path: &str) -> Result<Vec<u8>, Errno> {
+    let location = vfs.lookup(path)?;
+    let name = location
+        .path
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut archive = Vec::new();
+    pack_node(vfs, &location, &name, &mut archive)?;
+    archive.extend([0; 2 * BLOCK_SIZE]);
+    Ok(archive)
+}
+
+/// Unpacks a tar archive into the directory at path, see tar(5).
+///
+/// Reads ustar, pax and GNU archives: directories, regular files and symbolic links keep their
+/// modes and modification times, hard links become copies, and other kinds of files are left out.
+/// Fails with EINVAL if the archive is malformed, or if an entry would end up outside of the
+/// directory, with what comes before unpacked.
+pub fn unpack(vfs: &Vfs, path: &str, archive: &[u8]) -> Result<(), Errno> {
+    let mut unpacker = Unpacker::new(vfs, path)?;
+    // Records of pax global headers, and those of the extended header or GNU long names that
+    // apply to the next entry only.
+    let mut global: HashMap<String, String> = HashMap::new();
+    let mut next = HashMap::new();
+    let mut rest = archive;
+    while rest.len() >= BLOCK_SIZE {
+        let (header, after) = rest.split_at(BLOCK_SIZE);
+        if header.iter().all(|&byte| byte == 0) {
+            // End of archive.
+            break;
+        }
+        if checksum(header) != number(&header[148..156])? {
+            return Err(EINVAL);
+        }
+        let kind = header[156];
+        let mut records = match kind {
+            b'g' | b'x' | b'L' | b'K' => HashMap::new(),
+            _ => global.clone().into_iter().chain(next.drain()).collect(),
+        };
+        // Sizes of 8 GiB and more are only in the pax records.
+        let size = match records.get("size") {
+            Some(size) => size.parse().map_err(|_| EINVAL)?,
+            None => usize::try_from(number(&header[124..136])?).map_err(|_| EINVAL)?,
+        };
+        let padded = size.checked_next_multiple_of(BLOCK_SIZE).ok_or(EINVAL)?;
+        if padded > after.len() {
+            return Err(EINVAL);
+        }
+        let data = &after[..size];
+        rest = &after[padded..];
+
+        match kind {
+            b'g' => global.extend(pax_records(data)?),
+            b'x' => next.extend(pax_records(data)?),
+            b'L' => {
+                next.insert("path".to_string(), string(data)?);
+            }
+            b'K' => {
+                next.insert("linkpath".to_string(), string(data)?);
+            }
+            _ => {
+                let name = match records.remove("path") {
+                    Some(path) => path,
+                    None => ustar_name(header)?,
+                };
+                let Some(rel) = relative(&name)? else {
+                    // The directory itself.
+                    continue;
+                };
+                let link = match records.remove("linkpath") {
+                    Some(link) => link,
+                    None => string(&header[157..257])?,
+                };
+                let mtime = match records.get("mtime") {
+                    Some(mtime) => mtime.parse().map_err(|_| EINVAL)?,
+                    None => number(&header[136..148])? as f64,
+                } * 1000.0;
+                let atime = match records.get("atime") {
+                    Some(atime) => atime.parse::<f64>().map_err(|_| EINVAL)? * 1000.0,
+                    None => mtime,
+                };
+                let perm = number(&header[100..108])? as u32 & 0o7777;
+                match kind {
+                    b'0' | b'\0' | b'7' => {
+                        unpacker.add(&rel, S_IFREG | perm, atime, mtime, data)?;
+                    }
+                    b'1' => {
+                        let target = unpacker.lookup(&relative(&link)?.ok_or(EINVAL)?)?;
+                        let stat = target.stat();
+                        if stat.mode & S_IFMT != S_IFREG {
+                            return Err(EINVAL);
+                        }
+                        let data = target.node.read_at(0, stat.size as usize)?;
+                        unpacker.add(&rel, stat.mode, atime, mtime, &data)?;
+                    }
+                    b'2' => unpacker.add(&rel, S_IFLNK | 0o777, atime, mtime, link.as_bytes())?,
+                    b'5' => unpacker.add(&rel, S_IFDIR | perm, atime, mtime, &[])?,
+                    // Devices and FIFOs.
+                    _ => {}
+                }
+            }
+        }
+    }
+    unpacker.finish()
+}
+
+/// Appends the entries of a node and whatever is under it. The root directory has no name, and no
+/// entry of its own.
+fn pack_node(vfs: &Vfs, location: &Location, name: &str, out: &mut Vec<u8>) -> Result<(), Errno> {
+    let stat = location.stat();
+    let (kind, data, link) = match stat.mode & S_IFMT {
+        S_IFDIR => (b'5', Vec::new(), String::new()),
+        S_IFREG => (
+            b'0',
+            location.node.read_at(0, stat.size as usize)?,
+            String::new(),
+        ),
+        S_IFLNK => (b'2', Vec::new(), location.node.readlink()?),
+        _ => return Ok(()),
+    };
+    if kind != b'5' {
+        write_entry(out, name, &stat, kind, &link, &data);
+        return Ok(());
+    }
+    if !name.is_empty() {
+        write_entry(out, &format!("{}/", name), &stat, kind, &link, &data);
+    }
+    for entry in location.node.entries()? {
+        let child = vfs.lookup_link(&vfs::join(&location.path, &entry.name))?;
+        if !Rc::ptr_eq(&child.mount, &location.mount) {
+            continue;
+        }
+        let name = match name {
+            "" => entry.name,
+            name => format!("{}/{}", name, entry.name),
+        };
+        pack_node(vfs, &child, &name, out)?;
+    }
+    Ok(())
+}
+
+/// Appends a header and the contents padded to blocks, preceded by a pax extended header if the
+/// name or link target is too long.
+fn write_entry(out: &mut Vec<u8>, name: &str, stat: &Stat, kind: u8, link: &str, data: &[u8]) {
+    let mut records = String::new();
+    if name.len() > NAME_SIZE {
+        records += &pax_record("path", name);
+    }
+    if link.len() > NAME_SIZE {
+        records += &pax_record("linkpath", link);
+    }
+    if !records.is_empty() {
+        let pax = Stat {
+            mode: 0o644,
+            size: records.len() as u64,
+            ..*stat
+        };
+        write_entry(out, PAX_HEADER, &pax, b'x', "", records.as_bytes());
+    }
+
+    let mut header = [0; BLOCK_SIZE];
+    let mut field = |range: std::ops::Range<usize>, value: &[u8]| {
+        let len = value.len().min(range.len());
+        header[range.start..range.start + len].copy_from_slice(&value[..len]);
+    };
+    field(0..100, truncate(name, NAME_SIZE).as_bytes());
+    field(100..108, octal(stat.mode as u64 & 0o7777, 8).as_bytes());
+    field(108..116, octal(0, 8).as_bytes());
+    field(116..124, octal(0, 8).as_bytes());
+    field(124..136, octal(data.len() as u64, 12).as_bytes());
+    field(136..148, octal((stat.mtime / 1000.0) as u64, 12).as_bytes());
+    field(156..157, &[kind]);
+    field(157..257, truncate(link, NAME_SIZE).as_bytes());
+    field(257..263, b"ustar\0");
+    field(263..265, b"00");
+    let sum = format!("{:06o}\0 ", checksum(&header));
+    header[148..156].copy_from_slice(sum.as_bytes());
+
+    out.extend(header);
+    out.extend(data);
+    out.resize(out.len().next_multiple_of(BLOCK_SIZE), 0);
+}
+
+/// Formats a pax extended header record, whose length counts its own digits.
+fn pax_record(key: &str, value: &str) -> String {
+    let record = format!(" {}={}\n", key, value);
+    let mut len = record.len() + 1;
+    while len.to_string().len() + record.len() != len {
+        len = len.to_string().len() + record.len();
+    }
+    format!("{}{}", len, record)
+}
+
+/// Formats a numeric field of the given size: octal digits and a NUL.
+fn octal(value: u64, size: usize) -> String {
+    format!("{:0width$o}\0", value, width = size - 1)
+}
+
+/// Returns the longest prefix of s that fits in len bytes.
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Returns the path of an entry relative to the archive, without "." components, or None for the
+/// archive itself. Fails with EINVAL on "..".
+fn relative(name: &str) -> Result<Option<String>, Errno> {
+    let parts: Vec<&str> = vfs::components(name).filter(|part| *part != ".").collect();
+    if parts.contains(&"..") {
+        return Err(EINVAL);
+    }
+    Ok(Some(parts.join("/")).filter(|rel| !rel.is_empty()))
+}
+
+/// Returns the name of an entry, joined with its ustar prefix.
+fn ustar_name(header: &[u8]) -> Result<String, Errno> {
+    let name = string(&header[..100])?;
+    if &header[257..262] != b"ustar" || header[345] == 0 {
+        return Ok(name);
+    }
+    Ok(format!("{}/{}", string(&header[345..500])?, name))
+}
+
+/// Parses pax extended header records, "<length> <key>=<value>\n" each.
+fn pax_records(mut data: &[u8]) -> Result<HashMap<String, String>, Errno> {
+    let mut records = HashMap::new();
+    while !data.is_empty() {
+        let space = data.iter().position(|&byte| byte == b' ').ok_or(EINVAL)?;
+        let len: usize = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len > space && len <= data.len())
+            .ok_or(EINVAL)?;
+        let record = data[space + 1..len].strip_suffix(b"\n").ok_or(EINVAL)?;
+        let record = std::str::from_utf8(record).map_err(|_| EINVAL)?;
+        let (key, value) = record.split_once('=').ok_or(EINVAL)?;
+        records.insert(key.to_string(), value.to_string());
+        data = &data[len..];
+    }
+    Ok(records)
+}
+
+/// Parses a NUL-terminated string field.
+fn string(field: &[u8]) -> Result<String, Errno> {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8(field[..len].to_vec()).map_err(|_| EINVAL)
+}
+
+/// Parses a numeric field: octal digits up to a space or NUL, or big-endian binary if the high bit
+/// of the first byte is set, as written by GNU tar.
+fn number(field: &[u8]) -> Result<u64, Errno> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold((field[0] & 0x7f) as u64, |number, &byte| {
+                number.checked_mul(256).map(|number| number | byte as u64)
+            })
+            .ok_or(EINVAL);
+    }
+    let digits: Vec<u8> = field
+        .iter()
+        .copied()
+        .skip_while(|&byte| byte == b' ')
+        .take_while(|&byte| byte != b' ' && byte != 0)
+        .collect();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    let digits = std::str::from_utf8(&digits).map_err(|_| EINVAL)?;
+    u64::from_str_radix(digits, 8).map_err(|_| EINVAL)
+}
+
+/// Sums the bytes of a header, counting those of the checksum field as spaces.
+fn checksum(header: &[u8]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as u64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binfs::BinFs,
+        devfs::{DevFs, Random},
+        fd::{O_CREAT, O_WRONLY},
+        memfs::MemFs,
+        mount::MS_RDONLY,
+    };
+
+    /// Returns a ustar header and the contents padded to blocks.
+    fn entry(name: &str, kind: u8, data: &[u8], link: &str) -> Vec<u8> {
+        let mut header = [0; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000750");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"00000000012");
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        let sum = format!("{:06o}\0 ", checksum(&header));
+        header[148..156].copy_from_slice(sum.as_bytes());
+        let mut entry = header.to_vec();
+        entry.extend(data);
+        entry.resize(entry.len().next_multiple_of(BLOCK_SIZE), 0);
+        entry
+    }
+
+    #[test]
+    fn test_pack() {
+        let vfs = Vfs::new(Rc::new(MemFs::new()));
+        let long = format!("/home/snail/{}", "ü".repeat(60));
+        vfs.mkdir(&long, 0o700).unwrap();
+        let file = vfs
+            .open("/home/snail/f", O_WRONLY | O_CREAT, 0o600)
+            .unwrap();
+        file.node.write_at(0, &[7; 1000]).unwrap();
+        file.node.set_times(None, Some(5000.0)).unwrap();
+        vfs.symlink(&long, "/home/snail/l").unwrap();
+        let archive = pack(&vfs, "/home/snail").unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+
+        let copy = Vfs::new(Rc::new(MemFs::new()));
+        unpack(&copy, "/tmp", &archive).unwrap();
+        let file = copy.lookup("/tmp/snail/f").unwrap();
+        assert_eq!(file.node.read_at(0, 2000).unwrap(), [7; 1000]);
+        let stat = file.stat();
+        assert_eq!((stat.mode, stat.mtime), (S_IFREG | 0o600, 5000.0));
+        let link = copy.lookup_link("/tmp/snail/l").unwrap();
+        assert_eq!(link.node.readlink().unwrap(), long);
+        let dir = copy.lookup(&long.replace("/home", "/tmp")).unwrap();
+        assert_eq!(dir.stat().mode, S_IFDIR | 0o700);
+
+        let archive = pack(&vfs, "/home/snail/f").unwrap();
+        assert_eq!(&archive[..2], b"f\0");
+        assert_eq!(archive.len(), 5 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_unpack() {
+        let long = format!("d/{}", "n".repeat(120));
+        // The length of the record counts its own 3 digits.
+        let record = format!(" path={}\n", long);
+        let record = format!("{}{}", record.len() + 3, record);
+        let archive = [
+            entry("./", b'5', b"", ""),
+            entry("d/", b'5', b"", ""),
+            entry("d/f", b'0', b"hi", ""),
+            entry("PaxHeaders/long", b'x', record.as_bytes(), ""),
+            entry("truncated", b'0', b"long", ""),
+            entry("l", b'2', b"", "d/f"),
+            entry("./h", b'1', b"", "./d/f"),
+            vec![0; 2 * BLOCK_SIZE],
+        ]
+        .concat();
+
+        let vfs = Vfs::new(Rc::new(MemFs::new()));
+        unpack(&vfs, "/tmp", &archive).unwrap();
+        let file = vfs.lookup("/tmp/l").unwrap();
+        assert_eq!(file.path, "/tmp/d/f");
+        assert_eq!(file.node.read_at(0, 16).unwrap(), b"hi");
+        let stat = file.stat();
+        assert_eq!((stat.mode, stat.mtime), (S_IFREG | 0o750, 10000.0));
+        assert_eq!(vfs.lookup("/tmp/d").unwrap().stat().mtime, 10000.0);
+        let file = vfs.lookup(&format!("/tmp/{}", long)).unwrap();
+        assert_eq!(file.node.read_at(0, 16).unwrap(), b"long");
+        let file = vfs.lookup_link("/tmp/h").unwrap();
+        assert_eq!(file.node.read_at(0, 16).unwrap(), b"hi");
+
+        let escape = entry("../x", b'0', b"", "");
+        assert_eq!(unpack(&vfs, "/tmp", &escape), Err(EINVAL));
+        let secret = vfs
+            .open("/home/snail/secret", O_WRONLY | O_CREAT, 0o600)
+            .unwrap();
+        secret.node.write_at(0, b"secret").unwrap();
+        let escape = [
+            entry("esc", b'2', b"", "/home/snail"),
+            entry("copy", b'1', b"", "esc/secret"),
+        ]
+        .concat();
+        assert_eq!(unpack(&vfs, "/tmp", &escape), Err(EINVAL));
+        assert!(vfs.lookup_link("/tmp/copy").is_err());
+        let mut corrupt = entry("x", b'0', b"", "");
+        corrupt[0] = b'y';
+        assert_eq!(unpack(&vfs, "/tmp", &corrupt), Err(EINVAL));
+    }
+
+    #[test]
+    fn test_unpack_mounts() {
+        let vfs = Vfs::new(Rc::new(MemFs::new()));
+        let bin = BinFs::new("/bin", "/usr/wasm", "").unwrap();
+        vfs.mount("/bin", "/bin", "binfs", Rc::new(bin), MS_RDONLY)
+            .unwrap();
+        let dev = DevFs::new(Rc::new(Random::new(Some(0))));
+        vfs.mount("devtmpfs", "/dev", "devtmpfs", Rc::new(dev), 0)
+            .unwrap();
+        let archive = [
+            entry("./", b'5', b"", ""),
+            entry("./bin/", b'5', b"", ""),
+            entry("./dev/", b'5', b"", ""),
+            entry("./dev/null", b'3', b"", ""),
+            entry("./etc/", b'5', b"", ""),
+            vec![0; 2 * BLOCK_SIZE],
+        ]
+        .concat();
+
+        unpack(&vfs, "/", &archive).unwrap();
+        assert_eq!(vfs.lookup("/bin").unwrap().stat().mode, S_IFDIR | 0o555);
+        assert!(vfs.lookup("/dev/null").is_ok());
+        assert_eq!(vfs.lookup("/etc").unwrap().stat().mode, S_IFDIR | 0o750);
+    }
+}
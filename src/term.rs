@@ -1,24 +1,60 @@
 use std::{cell::RefCell, rc::Rc};
 
-use js_sys::{Array, Error, Function, Reflect, Uint8Array};
-use wasm_bindgen::{closure::Closure, JsCast};
+use js_sys::{Array, Error, Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
 use web_sys::window;
 
 use crate::{
     js,
-    proc::{Pid, ProcessManager},
+    proc::ProcessManager,
+    signal::{Signal, SIGINT, SIGQUIT, SIGTSTP},
 };
 
+// Control characters that generate signals, as with the default termios settings.
+const VINTR: char = '\x03'; // Ctrl-C
+const VQUIT: char = '\x1c'; // Ctrl-\
+const VSUSP: char = '\x1a'; // Ctrl-Z
+
+// Control characters for output flow control, see IXON in termios(3).
+const VSTOP: char = '\x13'; // Ctrl-S
+const VSTART: char = '\x11'; // Ctrl-Q
+
+// Output is paused once xterm.js has more than HIGH_WATERMARK bytes left to process, and resumed
+// once it gets below LOW_WATERMARK.
+const HIGH_WATERMARK: usize = 128 * 1024;
+const LOW_WATERMARK: usize = 16 * 1024;
+
 pub struct Terminal {
     term: Rc<js::Terminal>,
     term_fit_addon: js::FitAddon,
-    owner: Rc<RefCell<Option<Pid>>>,
+    flow: Rc<RefCell<Flow>>,
 
     #[allow(dead_code)]
     callbacks: Callbacks,
     disposables: Disposables,
 }
 
+/// Output flow control state, shared with the input callback.
+#[derive(Default)]
+struct Flow {
+    // Bytes handed to xterm.js that it has not processed yet.
+    pending: usize,
+    // Whether pending went above the high watermark and has not gone below the low one since.
+    paused: bool,
+    // Whether output was stopped with VSTOP (XOFF), until VSTART (XON).
+    stopped: bool,
+    // Deferred objects waiting for output to be resumed.
+    waiting: Vec<js::Deferred>,
+}
+
+/// Output processing of data written to the terminal, see OPOST in termios(3).
+pub struct Output {
+    // Translate "\n" to "\r\n", see ONLCR.
+    onlcr: bool,
+    // Incomplete UTF-8 sequence at the end of the last write, held back until the rest arrives.
+    partial: Vec<u8>,
+}
+
 struct Callbacks {
     on_data: Closure<dyn Fn(String)>,
 }
@@ -36,8 +72,8 @@ impl Terminal {
         let term: Rc<js::Terminal> =
             Rc::new(Reflect::construct(&terminal, &Array::of1(&js::Builder::new().into()))?.into());
 
-        let owner = Rc::new(RefCell::new(None)); // detached
-        let callbacks = Callbacks::new(proc, &owner, &term);
+        let flow = Rc::new(RefCell::new(Flow::default()));
+        let callbacks = Callbacks::new(proc, &term, &flow);
         let disposables = Disposables {
             on_data: term.on_data(&callbacks.on_data)?,
         };
@@ -49,7 +85,7 @@ impl Terminal {
         Ok(Self {
             term,
             term_fit_addon,
-            owner,
+            flow,
             callbacks,
             disposables,
         })
@@ -80,59 +116,242 @@ impl Terminal {
             // heap, but then we'd need to keep the memory alive until the write callback fires.
             let array = Uint8Array::new_with_length(data.len() as u32);
             array.copy_from(data);
-            Ok(self.term.write(&array)?)
+
+            let len = data.len();
+            self.flow.borrow_mut().written(len);
+            let flow = self.flow.clone();
+            let callback = Closure::once_into_js(move || flow.borrow_mut().processed(len));
+            Ok(self.term.write(&array, callback.unchecked_ref())?)
         } else {
             Ok(())
         }
     }
 
+    /// Returns a promise that resolves once more output can be written, or None if it can be
+    /// written right away.
+    ///
+    /// Output has to wait while xterm.js is catching up, or while it is stopped with VSTOP.
+    pub fn ready(&self) -> Result<Option<Promise>, Error> {
+        let mut flow = self.flow.borrow_mut();
+        if flow.ready() {
+            return Ok(None);
+        }
+        let def = js::deferred()?;
+        let promise = def.promise();
+        flow.waiting.push(def);
+        Ok(Some(promise))
+    }
+
+    /// Writes data with SGR parameters applied, resetting all attributes afterwards.
+    pub fn write_styled(&self, data: &[u8], style: &str) -> Result<(), Error> {
+        self.write(format!("\x1b[{}m", style).as_bytes())?;
+        self.write(data)?;
+        self.write(b"\x1b[0m")
+    }
+
     pub fn writeln(&self, data: &[u8]) -> Result<(), Error> {
         self.write(data)?;
         self.write(b"\r\n")
     }
+}
+
+impl Flow {
+    fn ready(&self) -> bool {
+        !self.paused && !self.stopped
+    }
+
+    fn written(&mut self, len: usize) {
+        self.pending += len;
+        if self.pending > HIGH_WATERMARK {
+            self.paused = true;
+        }
+    }
+
+    fn processed(&mut self, len: usize) {
+        self.pending = self.pending.saturating_sub(len);
+        if self.pending < LOW_WATERMARK {
+            self.paused = false;
+            self.resume();
+        }
+    }
+
+    /// Stops or restarts output, see IXON.
+    fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+        self.resume();
+    }
+
+    fn resume(&mut self) {
+        if self.ready() {
+            for def in self.waiting.drain(..) {
+                def.resolve(&JsValue::undefined());
+            }
+        }
+    }
+}
+
+impl Output {
+    pub fn new() -> Self {
+        Self {
+            onlcr: true,
+            partial: Vec::new(),
+        }
+    }
 
-    pub fn attach_to(&self, pid: Pid) {
-        self.owner.borrow_mut().replace(pid);
+    /// Returns the bytes that are ready to be displayed.
+    pub fn process(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = std::mem::take(&mut self.partial);
+        for &b in data {
+            if b == b'\n' && self.onlcr {
+                out.push(b'\r');
+            }
+            out.push(b);
+        }
+        let complete = Self::complete_len(&out);
+        self.partial = out.split_off(complete);
+        out
+    }
+
+    /// Returns whatever is left over, e.g. once the writer is gone.
+    pub fn flush(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.partial)
+    }
+
+    /// Length of the data without an incomplete UTF-8 sequence at the end.
+    fn complete_len(data: &[u8]) -> usize {
+        // Find the start of the last sequence, at most 3 bytes back.
+        for (i, &b) in data.iter().enumerate().rev().take(4) {
+            if b & 0xc0 == 0x80 {
+                continue; // continuation byte
+            }
+            let len = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1, // ASCII or invalid, not held back
+            };
+            return if data.len() - i < len { i } else { data.len() };
+        }
+        data.len()
     }
 }
 
 impl Callbacks {
-    fn new(
-        proc: &Rc<ProcessManager>,
-        pid: &Rc<RefCell<Option<Pid>>>,
-        term: &Rc<js::Terminal>,
-    ) -> Self {
+    fn new(proc: &Rc<ProcessManager>, term: &Rc<js::Terminal>, flow: &Rc<RefCell<Flow>>) -> Self {
         Self {
-            on_data: Self::on_data(proc.clone(), pid.clone(), term.clone()),
+            on_data: Self::on_data(proc.clone(), term.clone(), flow.clone()),
         }
     }
 
+    /// Input goes to the foreground process group of the session the terminal is attached to.
+    ///
+    /// VSTOP and VSTART stop and restart output instead, and are not passed on.
     fn on_data(
         proc: Rc<ProcessManager>,
-        pid: Rc<RefCell<Option<Pid>>>,
         term: Rc<js::Terminal>,
+        flow: Rc<RefCell<Flow>>,
     ) -> Closure<dyn Fn(String)> {
         Closure::new(move |input: String| {
-            // Replace "\r" => "\n".
-            let text = if input == "\r" {
-                "\n".to_string()
-            } else {
-                input
-            };
-            // Echo back everything.
-            term.write_string(&text);
-
-            match *pid.borrow() {
-                None => js::warn("term: on_data: detached"),
-                Some(pid) => {
-                    if let Err(err) = proc.stdin_write(pid, text.as_bytes().to_vec()) {
-                        js::log(&format!(
-                            "term: on_data: write error: {}",
-                            err.as_string().unwrap_or("n/a".to_string())
-                        ));
-                    }
+            // Pasted input may contain control characters anywhere, so split around them.
+            let mut start = 0;
+            for (i, c) in input.char_indices() {
+                let sig = Self::signal(c);
+                if sig.is_none() && c != VSTOP && c != VSTART {
+                    continue;
                 }
+                Self::forward(&proc, &term, &input[start..i]);
+                match sig {
+                    Some(sig) => Self::interrupt(&proc, &term, c, sig),
+                    None => flow.borrow_mut().set_stopped(c == VSTOP),
+                }
+                start = i + c.len_utf8();
             }
+            Self::forward(&proc, &term, &input[start..]);
         })
     }
+
+    /// Echoes the text and writes it to the standard input of the foreground process group.
+    fn forward(proc: &ProcessManager, term: &js::Terminal, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        // Replace "\r" => "\n".
+        let text = if input == "\r" { "\n" } else { input };
+        // Echo back everything, with the same output processing as written data (ONLCR).
+        term.write_string(&text.replace('\n', "\r\n"));
+
+        if let Err(err) = proc.tty_write(text.as_bytes().to_vec()) {
+            js::log(&format!(
+                "term: on_data: write error: {}",
+                String::from(err.message())
+            ));
+        }
+    }
+
+    /// Sends the signal generated by a control character to the foreground process group.
+    fn interrupt(proc: &ProcessManager, term: &js::Terminal, c: char, sig: Signal) {
+        // Echo as ^C, ^\ or ^Z.
+        term.write_string(&format!("^{}", (c as u8 + b'@') as char));
+
+        if let Err(err) = proc.tty_signal(sig) {
+            js::log(&format!(
+                "term: signal {}: {}",
+                sig,
+                String::from(err.message())
+            ));
+        }
+    }
+
+    /// Returns the signal generated by a control character, if any.
+    fn signal(c: char) -> Option<Signal> {
+        match c {
+            VINTR => Some(SIGINT),
+            VQUIT => Some(SIGQUIT),
+            VSUSP => Some(SIGTSTP),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow() {
+        let mut flow = Flow::default();
+        flow.written(HIGH_WATERMARK + 1);
+        assert!(!flow.ready());
+        flow.processed(HIGH_WATERMARK - LOW_WATERMARK);
+        assert!(!flow.ready()); // still above the low watermark
+        flow.processed(2);
+        assert!(flow.ready());
+
+        flow.set_stopped(true);
+        assert!(!flow.ready());
+        flow.set_stopped(false);
+        assert!(flow.ready());
+    }
+
+    #[test]
+    fn test_onlcr() {
+        let mut output = Output::new();
+        assert_eq!(output.process(b"a\nb\n"), b"a\r\nb\r\n");
+        assert_eq!(output.process(b"\x1b[1m\n"), b"\x1b[1m\r\n");
+    }
+
+    #[test]
+    fn test_partial_utf8() {
+        let mut output = Output::new();
+        let text = "é€😀".as_bytes();
+        assert_eq!(output.process(&text[..1]), b"");
+        assert_eq!(output.process(&text[1..3]), "é".as_bytes());
+        assert_eq!(output.process(&text[3..7]), "€".as_bytes());
+        assert_eq!(output.process(&text[7..]), "😀".as_bytes());
+
+        // Invalid bytes are passed through, for the terminal to replace.
+        assert_eq!(output.process(b"\xffa\x80"), b"\xffa\x80");
+        assert_eq!(output.process(b"\xf0\x9f"), b"");
+        assert_eq!(output.flush(), b"\xf0\x9f");
+    }
 }
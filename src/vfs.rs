@@ -0,0 +1,824 @@
+use std::{cell::RefCell, rc::Rc};
+
+use js_sys::Date;
+
+use crate::{
+    errno::Errno::{
+        self, EACCES, EBUSY, EEXIST, EINVAL, EISDIR, ELOOP, ENOENT, ENOEXEC, ENOTDIR, EPERM, EROFS,
+        EXDEV,
+    },
+    fd::{O_ACCMODE, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_TRUNC},
+    mount::{Mount, MountTable},
+};
+
+pub type Ino = u64;
+
+// File types and permission bits of st_mode, see inode(7).
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFLNK: u32 = 0o120000;
+
+// Values of the whence argument of lseek().
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+const SEEK_END: i32 = 2;
+
+// Permissions checked by access().
+const R_OK: u32 = 4;
+const W_OK: u32 = 2;
+const X_OK: u32 = 1;
+
+/// Most symbolic links followed while resolving a path, see path_resolution(7).
+const MAXSYMLINKS: usize = 40;
+
+/// Preferred I/O size and block size reported by stat() and statfs().
+pub const BLKSIZE: u32 = 4096;
+
+/// Directories of the root filesystem created at boot, and their permissions.
+const DIRS: [(&str, u32); 7] = [
+    ("/bin", 0o755),
+    ("/dev", 0o755),
+    ("/home", 0o755),
+    ("/home/snail", 0o755),
+    ("/proc", 0o555),
+    ("/run", 0o755),
+    ("/tmp", 0o1777),
+];
+
+/// The filesystem tree shared by all processes, made of the filesystems in the mount table.
+///
+/// Paths are absolute and normalized, see join(), so ".." never has to be looked up and crosses
+/// mount points like any other component. Symbolic links are followed by lookup(), which
+/// normalizes the path again.
+pub struct Vfs {
+    mounts: MountTable,
+}
+
+/// A filesystem driver.
+///
+/// Paths are relative to the root of the filesystem, but start with "/" all the same. Drivers are
+/// read-only unless they override the methods changing the tree.
+pub trait FileSystem {
+    /// Returns the node at path.
+    fn lookup(&self, path: &str) -> Result<Rc<dyn Node>, Errno>;
+
+    /// Creates a regular file, where path does not exist yet.
+    fn create(&self, _path: &str, _mode: u32) -> Result<Rc<dyn Node>, Errno> {
+        Err(EROFS)
+    }
+
+    fn mkdir(&self, _path: &str, _mode: u32) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+
+    /// Creates a symbolic link to target, where path does not exist yet.
+    fn symlink(&self, _path: &str, _target: &str) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+
+    /// Removes anything but a directory.
+    fn unlink(&self, _path: &str) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+
+    /// Removes an empty directory.
+    fn rmdir(&self, _path: &str) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+
+    /// Moves a node, replacing whatever is at the new path, see rename(2).
+    fn rename(&self, _old: &str, _new: &str) -> Result<(), Errno> {
+        Err(EROFS)
+    }
+
+    fn statfs(&self) -> StatFs;
+
+    /// Returns the program implementing the executable at path, if it is one.
+    fn program(&self, _path: &str) -> Option<Program> {
+        None
+    }
+}
+
+/// Where an executable is loaded from.
+pub struct Program {
+    // JS module exporting the factory of the Emscripten module.
+    pub module_url: String,
+    // Wasm binary the module instantiates.
+    pub wasm_url: String,
+    // Content ID of the Wasm binary: its SHA-256 hash, in hex.
+    pub cid: String,
+}
+
+/// A file or directory of some filesystem.
+pub trait Node {
+    fn stat(&self) -> Stat;
+
+    fn is_dir(&self) -> bool {
+        self.stat().mode & S_IFMT == S_IFDIR
+    }
+
+    /// Reads up to count bytes at offset.
+    fn read_at(&self, offset: u64, count: usize) -> Result<Vec<u8>, Errno>;
+
+    /// Writes data at offset, returning the number of bytes written.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(EINVAL)
+    }
+
+    /// Changes the size, see truncate(2).
+    fn truncate(&self, _len: u64) -> Result<(), Errno> {
+        Err(EINVAL)
+    }
+
+    /// Returns the entries of a directory, without "." and "..".
+    fn entries(&self) -> Result<Vec<DirEntry>, Errno> {
+        Err(ENOTDIR)
+    }
+
+    /// Changes the permissions, see chmod(2).
+    fn chmod(&self, _mode: u32) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+
+    /// Sets the access and modification times, leaving those that are None, see utimensat(2).
+    fn set_times(&self, _atime: Option<f64>, _mtime: Option<f64>) -> Result<(), Errno> {
+        Err(EPERM)
+    }
+
+    /// Returns the target of a symbolic link, see readlink(2).
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(EINVAL)
+    }
+}
+
+/// File status, see stat(2).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stat {
+    // Device number of the mount, filled in by Location::stat().
+    pub dev: u32,
+    // Device number of a device file, see makedev(3).
+    pub rdev: u32,
+    pub ino: Ino,
+    pub mode: u32,
+    pub nlink: u32,
+    pub size: u64,
+    // Times in milliseconds since the epoch.
+    pub atime: f64,
+    pub mtime: f64,
+    pub ctime: f64,
+}
+
+/// Filesystem statistics, see statfs(2). Sizes are in blocks of BLKSIZE bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatFs {
+    pub magic: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub namelen: u32,
+    // Mount flags, see statvfs(3).
+    pub flags: u32,
+}
+
+/// A directory entry, see getdents64(2).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: Ino,
+    // File type, one of the DT_* values.
+    pub kind: u8,
+}
+
+/// A node, along with the mount it was found in and its absolute path.
+#[derive(Clone)]
+pub struct Location {
+    pub mount: Rc<Mount>,
+    pub node: Rc<dyn Node>,
+    pub path: String,
+}
+
+/// State of a file or directory opened in the filesystem.
+///
+/// Shared by duplicated file descriptors, like the rest of the open file description. Keeps its
+/// mount busy, see umount(2).
+pub struct File {
+    pub location: Location,
+    // Byte offset for files, index of the next entry for directories.
+    offset: RefCell<u64>,
+}
+
+/// A read-only file or directory whose contents are generated by its filesystem.
+pub struct Synthetic {
+    stat: Stat,
+    contents: Contents,
+}
+
+enum Contents {
+    File(Vec<u8>),
+    Dir(Vec<DirEntry>),
+    Link(String),
+}
+
+impl Vfs {
+    /// Creates a tree on the given root filesystem, populated with the standard directories.
+    pub fn new(root: Rc<dyn FileSystem>) -> Self {
+        let vfs = Self {
+            mounts: MountTable::new(),
+        };
+        vfs.mounts.add("rootfs", "/", "rootfs", root, 0).ok();
+        for (path, mode) in DIRS {
+            if vfs.mkdir(path, 0).is_ok() {
+                vfs.lookup(path).and_then(|dir| dir.node.chmod(mode)).ok();
+            }
+        }
+        vfs
+    }
+
+    pub fn mounts(&self) -> &MountTable {
+        &self.mounts
+    }
+
+    /// Mounts a filesystem on an existing directory, see mount(2).
+    pub fn mount(
+        &self,
+        source: &str,
+        target: &str,
+        fstype: &str,
+        fs: Rc<dyn FileSystem>,
+        flags: u32,
+    ) -> Result<(), Errno> {
+        let location = self.lookup(target)?;
+        if !location.node.is_dir() {
+            return Err(ENOTDIR);
+        }
+        self.mounts.add(source, &location.path, fstype, fs, flags)
+    }
+
+    /// Returns the node at path, following symbolic links.
+    pub fn lookup(&self, path: &str) -> Result<Location, Errno> {
+        self.resolve(path, true)
+    }
+
+    /// Returns the node at path, following symbolic links but for the last component, see
+    /// lstat(2).
+    pub fn lookup_link(&self, path: &str) -> Result<Location, Errno> {
+        self.resolve(path, false)
+    }
+
+    /// Opens or creates a file, see open(2).
+    ///
+    /// Handles O_CREAT, O_EXCL, O_TRUNC and O_DIRECTORY; the other flags are up to the caller.
+    pub fn open(&self, path: &str, flags: i32, mode: u32) -> Result<Location, Errno> {
+        let create = flags & O_CREAT != 0;
+        let location = match self.lookup(path) {
+            Ok(_) if create && flags & O_EXCL != 0 => return Err(EEXIST),
+            Ok(location) => location,
+            Err(ENOENT) if create => {
+                let path = self.creatable(path, flags & O_EXCL != 0)?;
+                let (mount, rel) = self.writable(&path)?;
+                let node = mount.fs.create(&rel, mode)?;
+                Location { mount, node, path }
+            }
+            Err(errno) => return Err(errno),
+        };
+
+        let writes = flags & O_ACCMODE != O_RDONLY;
+        if location.node.is_dir() {
+            if writes || create {
+                return Err(EISDIR);
+            }
+        } else if flags & O_DIRECTORY != 0 {
+            return Err(ENOTDIR);
+        } else if writes {
+            location.writable()?;
+            if flags & O_TRUNC != 0 {
+                location.node.truncate(0)?;
+            }
+        }
+        Ok(location)
+    }
+
+    /// Creates a directory, see mkdir(2).
+    pub fn mkdir(&self, path: &str, mode: u32) -> Result<(), Errno> {
+        if self.lookup_link(path).is_ok() {
+            return Err(EEXIST);
+        }
+        let (mount, rel) = self.writable(path)?;
+        mount.fs.mkdir(&rel, mode)
+    }
+
+    /// Creates a symbolic link to target, see symlink(2).
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), Errno> {
+        if target.is_empty() {
+            return Err(ENOENT);
+        }
+        if self.lookup_link(path).is_ok() {
+            return Err(EEXIST);
+        }
+        let (mount, rel) = self.writable(path)?;
+        mount.fs.symlink(&rel, target)
+    }
+
+    /// Removes a file, see unlink(2).
+    pub fn unlink(&self, path: &str) -> Result<(), Errno> {
+        if self.lookup_link(path)?.node.is_dir() {
+            return Err(EISDIR);
+        }
+        let (mount, rel) = self.writable(path)?;
+        mount.fs.unlink(&rel)
+    }
+
+    /// Removes an empty directory, see rmdir(2).
+    pub fn rmdir(&self, path: &str) -> Result<(), Errno> {
+        if !self.lookup_link(path)?.node.is_dir() {
+            return Err(ENOTDIR);
+        }
+        let (mount, rel) = self.writable(path)?;
+        if rel == "/" {
+            // The root of a filesystem is a mount point.
+            return Err(EBUSY);
+        }
+        mount.fs.rmdir(&rel)
+    }
+
+    /// Moves a file or directory within a filesystem, see rename(2).
+    pub fn rename(&self, old: &str, new: &str) -> Result<(), Errno> {
+        self.lookup_link(old)?;
+        let (old_mount, old_rel) = self.writable(old)?;
+        let (new_mount, new_rel) = self.writable(new)?;
+        if old_rel == "/" || new_rel == "/" {
+            return Err(EBUSY);
+        }
+        if !Rc::ptr_eq(&old_mount, &new_mount) {
+            return Err(EXDEV);
+        }
+        old_mount.fs.rename(&old_rel, &new_rel)
+    }
+
+    /// Checks whether the file may be read, written or executed, see access(2).
+    ///
+    /// There is a single user, so only the owner permissions count.
+    pub fn access(&self, path: &str, mode: u32) -> Result<(), Errno> {
+        let location = self.lookup(path)?;
+        let allowed = (location.node.stat().mode >> 6) & (R_OK | W_OK | X_OK);
+        if mode & !allowed != 0 {
+            return Err(EACCES);
+        }
+        if mode & W_OK != 0 {
+            location.writable()?;
+        }
+        Ok(())
+    }
+
+    /// Reads directory entries as struct linux_dirent64 records, see getdents64(2).
+    ///
+    /// Returns at most count bytes, or nothing at the end of the directory. Fails with EINVAL if
+    /// the next entry does not fit.
+    pub fn getdents(&self, file: &File, count: usize) -> Result<Vec<u8>, Errno> {
+        let dir = &file.location;
+        let entries = dir.node.entries()?;
+        // The parent of a mount point is in the filesystem it is mounted on.
+        let parent = self.lookup(&join(&dir.path, ".."))?.stat();
+        let dots = [
+            DirEntry::new(".", &dir.stat()),
+            DirEntry::new("..", &parent),
+        ];
+
+        let mut offset = file.offset.borrow_mut();
+        let mut records = Vec::new();
+        for entry in dots.into_iter().chain(entries).skip(*offset as usize) {
+            // d_ino, d_off, d_reclen and d_type, then the name, padded to 8 bytes.
+            let reclen = (8 + 8 + 2 + 1 + entry.name.len() + 1).next_multiple_of(8);
+            if records.len() + reclen > count {
+                if records.is_empty() {
+                    return Err(EINVAL);
+                }
+                break;
+            }
+            *offset += 1;
+            let start = records.len();
+            records.extend(entry.ino.to_le_bytes());
+            records.extend(offset.to_le_bytes());
+            records.extend((reclen as u16).to_le_bytes());
+            records.push(entry.kind);
+            records.extend(entry.name.as_bytes());
+            records.resize(start + reclen, 0);
+        }
+        Ok(records)
+    }
+
+    /// Resolves a path component by component, restarting whenever a symbolic link is followed.
+    ///
+    /// ".." refers to the parent of the directory resolved so far, which may be on another mount,
+    /// see path_resolution(7). The returned location has the path with all links followed.
+    fn resolve(&self, path: &str, follow: bool) -> Result<Location, Errno> {
+        let mut path = path.to_string();
+        'links: for _ in 0..=MAXSYMLINKS {
+            let parts: Vec<&str> = components(&path).collect();
+            let mut location = self.lookup_node("/")?;
+            for (i, part) in parts.iter().enumerate() {
+                if !location.node.is_dir() {
+                    return Err(ENOTDIR);
+                }
+                // The path resolved so far has no links, so ".." drops its last component.
+                let dir = location.path;
+                location = self.lookup_node(&join(&dir, part))?;
+                if location.is_link() && (follow || i + 1 < parts.len()) {
+                    let target = location.node.readlink()?;
+                    path = append(&append(&dir, &target), &parts[i + 1..].join("/"));
+                    continue 'links;
+                }
+            }
+            return Ok(location);
+        }
+        Err(ELOOP)
+    }
+
+    /// Follows the links in the directories leading to path.
+    ///
+    /// Fails with EINVAL if path ends with "." or "..", which cannot be created or removed.
+    fn parent_resolved(&self, path: &str) -> Result<String, Errno> {
+        match path.rsplit_once('/') {
+            Some((_, "." | "..")) => Err(EINVAL),
+            Some((dir, name)) if !name.is_empty() => {
+                let dir = if dir.is_empty() { "/" } else { dir };
+                Ok(join(&self.lookup(dir)?.path, name))
+            }
+            _ => Ok(path.to_string()),
+        }
+    }
+
+    /// Resolves a path to a file about to be created, following dangling links to their target.
+    ///
+    /// With exclusive set, links are not followed and fail with EEXIST, see O_EXCL.
+    fn creatable(&self, path: &str, exclusive: bool) -> Result<String, Errno> {
+        let mut path = self.parent_resolved(path)?;
+        for _ in 0..=MAXSYMLINKS {
+            match self.lookup_link(&path) {
+                Ok(_) if exclusive => return Err(EEXIST),
+                Ok(location) if location.is_link() => {
+                    let target = location.node.readlink()?;
+                    path = self.parent_resolved(&append(&join(&path, ".."), &target))?;
+                }
+                Ok(_) => return Err(EEXIST),
+                Err(ENOENT) => return Ok(path),
+                Err(errno) => return Err(errno),
+            }
+        }
+        Err(ELOOP)
+    }
+
+    /// Returns the node at a path without links, as found by its filesystem.
+    fn lookup_node(&self, path: &str) -> Result<Location, Errno> {
+        let (mount, rel) = self.mounts.resolve(path);
+        let node = mount.fs.lookup(&rel)?;
+        Ok(Location {
+            mount,
+            node,
+            path: path.to_string(),
+        })
+    }
+
+    /// Resolves a path about to be changed, failing with EROFS if its mount is read-only.
+    ///
+    /// Links are followed in the directories leading to it, but not in its last component.
+    fn writable(&self, path: &str) -> Result<(Rc<Mount>, String), Errno> {
+        let (mount, rel) = self.mounts.resolve(&self.parent_resolved(path)?);
+        if mount.read_only() {
+            return Err(EROFS);
+        }
+        Ok((mount, rel))
+    }
+}
+
+impl Location {
+    /// Returns the status of the node, with its mount as the device.
+    pub fn stat(&self) -> Stat {
+        Stat {
+            dev: self.mount.dev,
+            ..self.node.stat()
+        }
+    }
+
+    /// Fails with EROFS if the node is on a read-only mount.
+    pub fn writable(&self) -> Result<(), Errno> {
+        if self.mount.read_only() {
+            return Err(EROFS);
+        }
+        Ok(())
+    }
+
+    pub fn is_link(&self) -> bool {
+        self.node.stat().mode & S_IFMT == S_IFLNK
+    }
+
+    /// Returns the program implementing the executable.
+    ///
+    /// Fails with ENOEXEC if its filesystem does not provide one.
+    pub fn program(&self) -> Result<Program, Errno> {
+        if self.node.is_dir() {
+            return Err(EACCES);
+        }
+        let rel = self.mount.relative(&self.path);
+        self.mount.fs.program(&rel).ok_or(ENOEXEC)
+    }
+}
+
+impl File {
+    pub fn new(location: Location) -> Self {
+        Self {
+            location,
+            offset: RefCell::new(0),
+        }
+    }
+
+    /// Reads up to count bytes at the file offset, advancing it.
+    pub fn read(&self, count: usize) -> Result<Vec<u8>, Errno> {
+        let mut offset = self.offset.borrow_mut();
+        let data = self.location.node.read_at(*offset, count)?;
+        *offset += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Writes data at the file offset, or at the end of the file if append is set.
+    pub fn write(&self, data: &[u8], append: bool) -> Result<usize, Errno> {
+        let mut offset = self.offset.borrow_mut();
+        if append {
+            *offset = self.location.node.stat().size;
+        }
+        let count = self.location.node.write_at(*offset, data)?;
+        *offset += count as u64;
+        Ok(count)
+    }
+
+    /// Repositions the file offset, see lseek(2).
+    pub fn seek(&self, offset: i64, whence: i32) -> Result<u64, Errno> {
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *self.offset.borrow(),
+            SEEK_END => self.location.node.stat().size,
+            _ => return Err(EINVAL),
+        };
+        let offset = (base as i64)
+            .checked_add(offset)
+            .and_then(|offset| u64::try_from(offset).ok())
+            .ok_or(EINVAL)?;
+        self.offset.replace(offset);
+        Ok(offset)
+    }
+}
+
+impl DirEntry {
+    pub fn new(name: &str, stat: &Stat) -> Self {
+        Self {
+            name: name.to_string(),
+            ino: stat.ino,
+            // DT_* values are the S_IF* file types, shifted.
+            kind: ((stat.mode & S_IFMT) >> 12) as u8,
+        }
+    }
+}
+
+impl Synthetic {
+    pub fn file(ino: Ino, mode: u32, data: Vec<u8>) -> Self {
+        Self {
+            stat: Self::new_stat(ino, S_IFREG | mode, 1),
+            contents: Contents::File(data),
+        }
+    }
+
+    pub fn dir(ino: Ino, mode: u32, entries: Vec<DirEntry>) -> Self {
+        Self {
+            stat: Self::new_stat(ino, S_IFDIR | mode, 2),
+            contents: Contents::Dir(entries),
+        }
+    }
+
+    pub fn link(ino: Ino, target: String) -> Self {
+        Self {
+            stat: Self::new_stat(ino, S_IFLNK | 0o777, 1),
+            contents: Contents::Link(target),
+        }
+    }
+
+    fn new_stat(ino: Ino, mode: u32, nlink: u32) -> Stat {
+        let now = now();
+        Stat {
+            ino,
+            mode,
+            nlink,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            ..Default::default()
+        }
+    }
+}
+
+impl Node for Synthetic {
+    fn stat(&self) -> Stat {
+        match &self.contents {
+            Contents::File(data) => Stat {
+                size: data.len() as u64,
+                ..self.stat
+            },
+            Contents::Link(target) => Stat {
+                size: target.len() as u64,
+                ..self.stat
+            },
+            Contents::Dir(_) => self.stat,
+        }
+    }
+
+    fn read_at(&self, offset: u64, count: usize) -> Result<Vec<u8>, Errno> {
+        match &self.contents {
+            Contents::File(data) => {
+                let start = data.len().min(offset as usize);
+                let end = data.len().min(start.saturating_add(count));
+                Ok(data[start..end].to_vec())
+            }
+            Contents::Dir(_) => Err(EISDIR),
+            Contents::Link(_) => Err(EINVAL),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<DirEntry>, Errno> {
+        match &self.contents {
+            Contents::Dir(entries) => Ok(entries.clone()),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    fn readlink(&self) -> Result<String, Errno> {
+        match &self.contents {
+            Contents::Link(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+}
+
+/// Resolves path relative to the absolute path of a directory, removing "." and ".." components.
+pub fn join(dir: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        components(dir).collect()
+    };
+    for part in components(path) {
+        match part {
+            "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// Appends a path to a directory, unless it is absolute, leaving "." and ".." to be resolved as
+/// links are followed, unlike join().
+pub fn append(dir: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    format!("{}/{}", dir.trim_end_matches('/'), path)
+}
+
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+/// Current time in milliseconds since the epoch, or 0 in native unit tests.
+pub fn now() -> f64 {
+    if cfg!(target_arch = "wasm32") {
+        Date::now()
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fd::{O_RDWR, O_WRONLY},
+        memfs::MemFs,
+        mount::MS_RDONLY,
+    };
+
+    fn vfs() -> Vfs {
+        let vfs = Vfs::new(Rc::new(MemFs::new()));
+        vfs.mount("none", "/tmp", "ramfs", Rc::new(MemFs::new()), 0)
+            .unwrap();
+        vfs
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join("/home/snail", "f"), "/home/snail/f");
+        assert_eq!(join("/home/snail", "../x/./y/"), "/home/x/y");
+        assert_eq!(join("/home", "/tmp/../.."), "/");
+    }
+
+    #[test]
+    fn test_open() {
+        let vfs = vfs();
+        assert_eq!(vfs.open("/tmp/f", O_RDONLY, 0).err(), Some(ENOENT));
+        let file = File::new(vfs.open("/tmp/f", O_WRONLY | O_CREAT, 0o666).unwrap());
+        file.write(b"hi\n", false).unwrap();
+        assert_eq!(file.seek(0, SEEK_CUR), Ok(3));
+        assert_eq!(file.seek(-4, SEEK_END), Err(EINVAL));
+
+        let file = File::new(vfs.open("/tmp/f", O_RDWR, 0).unwrap());
+        assert_eq!(file.read(16).unwrap(), b"hi\n");
+        assert_eq!(file.read(16).unwrap(), b"");
+        file.seek(0, SEEK_SET).unwrap();
+        file.write(b"!", true).unwrap();
+        assert_eq!(file.location.stat().size, 4);
+
+        let flags = O_WRONLY | O_CREAT | O_EXCL;
+        assert_eq!(vfs.open("/tmp/f", flags, 0).err(), Some(EEXIST));
+        vfs.open("/tmp/f", O_WRONLY | O_TRUNC, 0).unwrap();
+        assert_eq!(file.location.stat().size, 0);
+        assert_eq!(vfs.open("/tmp", O_WRONLY, 0).err(), Some(EISDIR));
+        assert_eq!(vfs.open("/tmp/f/g", O_RDONLY, 0).err(), Some(ENOTDIR));
+    }
+
+    #[test]
+    fn test_mounts() {
+        let vfs = vfs();
+        vfs.open("/home/f", O_CREAT, 0o666).unwrap();
+        assert_eq!(vfs.rename("/home/f", "/tmp/f"), Err(EXDEV));
+        assert_eq!(vfs.rmdir("/tmp"), Err(EBUSY));
+        assert_eq!(vfs.mkdir("/tmp", 0o777), Err(EEXIST));
+
+        let root = vfs.lookup("/").unwrap().stat();
+        let tmp = vfs.lookup("/tmp").unwrap().stat();
+        assert_ne!(root.dev, tmp.dev);
+        // ".." of a mount point is in the filesystem it is mounted on.
+        let file = File::new(vfs.open("/tmp", O_RDONLY, 0).unwrap());
+        let records = vfs.getdents(&file, 1024).unwrap();
+        assert_eq!(records[24..32], root.ino.to_le_bytes());
+
+        let ro = Rc::new(MemFs::new());
+        vfs.mount("none", "/home", "ramfs", ro, MS_RDONLY).unwrap();
+        assert_eq!(vfs.mkdir("/home/d", 0o777), Err(EROFS));
+        assert_eq!(vfs.access("/home", W_OK), Err(EROFS));
+    }
+
+    #[test]
+    fn test_getdents() {
+        let vfs = vfs();
+        let file = File::new(vfs.lookup("/home").unwrap());
+        // ".", ".." and "snail" take 24 bytes each.
+        assert_eq!(vfs.getdents(&file, 16), Err(EINVAL));
+        assert_eq!(vfs.getdents(&file, 50).unwrap().len(), 48);
+        let last = vfs.getdents(&file, 1024).unwrap();
+        assert_eq!(&last[19..24], b"snail");
+        assert_eq!(last[18], 4); // DT_DIR
+        assert!(vfs.getdents(&file, 1024).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let vfs = vfs();
+        vfs.mkdir("/tmp/d", 0o777).unwrap();
+        vfs.symlink("d", "/tmp/l").unwrap();
+        vfs.symlink("/tmp/l/f", "/tmp/f").unwrap();
+        assert_eq!(vfs.symlink("d", "/tmp/l").err(), Some(EEXIST));
+        assert_eq!(vfs.symlink("", "/tmp/e").err(), Some(ENOENT));
+
+        let flags = O_WRONLY | O_CREAT;
+        assert_eq!(vfs.open("/tmp/f", flags | O_EXCL, 0).err(), Some(EEXIST));
+        assert_eq!(vfs.open("/tmp/f", flags, 0o666).unwrap().path, "/tmp/d/f");
+        assert_eq!(vfs.lookup("/tmp/f").unwrap().path, "/tmp/d/f");
+        assert!(vfs.lookup_link("/tmp/f").unwrap().is_link());
+
+        vfs.symlink("loop", "/tmp/loop").unwrap();
+        assert_eq!(vfs.lookup("/tmp/loop").err(), Some(ELOOP));
+        assert_eq!(vfs.open("/tmp/loop", flags, 0).err(), Some(ELOOP));
+    }
+
+    #[test]
+    fn test_dotdot() {
+        let vfs = vfs();
+        vfs.mkdir("/tmp/d", 0o777).unwrap();
+        vfs.mkdir("/tmp/d/e", 0o777).unwrap();
+        vfs.symlink("/tmp/d/e", "/home/l").unwrap();
+        vfs.open("/tmp/f", O_WRONLY | O_CREAT, 0o666).unwrap();
+
+        // ".." is resolved after links are followed, and crosses mounts.
+        assert_eq!(vfs.lookup("/home/l/..").unwrap().path, "/tmp/d");
+        assert_eq!(vfs.lookup("/home/l/../../..").unwrap().path, "/");
+        assert_eq!(vfs.lookup("/tmp/f/..").err(), Some(ENOTDIR));
+        assert_eq!(vfs.lookup("/tmp/f/.").err(), Some(ENOTDIR));
+        assert_eq!(vfs.rmdir("/tmp/d/e/..").err(), Some(EINVAL));
+        vfs.mkdir("/home/l/../g", 0o777).unwrap();
+        assert!(vfs.lookup("/tmp/d/g").unwrap().node.is_dir());
+    }
+}
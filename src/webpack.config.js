@@ -25,6 +25,14 @@ module.exports = {
   },
   devServer: {
     static: [{
+      // Wasm binaries are named after their contents, so they never change.
+      directory: path.join(__dirname, "usr/wasm"),
+      publicPath: "/usr/wasm",
+      staticOptions: {
+        immutable: true,
+        maxAge: "1y",
+      },
+    }, {
       directory: __dirname,
     }],
     compress: true,
@@ -1,2 +1,2 @@
 // Signal the process manager that module module initialisation has completed.
-OS.init_module(ENV, FS);
+OS.init_module(ENV);
@@ -0,0 +1,46 @@
+#ifndef SNAILOS_WRAP_VFORK_H_
+#define SNAILOS_WRAP_VFORK_H_
+
+#include <setjmp.h>
+#include <unistd.h>
+
+#include <sys/types.h>
+
+/*
+ * vfork() emulation.
+ *
+ * A WebAssembly module cannot be duplicated, so the child returned by vfork()
+ * runs inside the parent's module, exactly like on NOMMU systems. The process
+ * manager attributes every syscall made in the meantime to the child. Once the
+ * child calls execve() or _exit(), we jump back to the vfork() call site and
+ * return the child's pid to the parent.
+ *
+ * This has to be a macro: setjmp() must be called from the frame that called
+ * vfork(), since that frame is guaranteed to be alive until the child calls
+ * execve() or _exit(). BusyBox's xvfork() is a macro for the same reason.
+ *
+ * This header is force-included when compiling packages, see //pkg/busybox.
+ */
+
+jmp_buf *__snail_vfork_jmp(void);
+pid_t __snail_vfork_child(void);
+pid_t __snail_vfork_parent(void);
+
+/*
+ * setjmp() may only appear on its own as the controlling expression of an if
+ * statement, or in a few other contexts (C11 7.13.1.1), hence the statement
+ * expression. A vforked child may vfork again, so each call saves its own
+ * jmp_buf.
+ */
+#define vfork()                                                                \
+  ({                                                                           \
+    pid_t __snail_vfork_pid;                                                   \
+    if (setjmp(*__snail_vfork_jmp())) {                                        \
+      __snail_vfork_pid = __snail_vfork_parent();                              \
+    } else {                                                                   \
+      __snail_vfork_pid = __snail_vfork_child();                               \
+    }                                                                          \
+    __snail_vfork_pid;                                                         \
+  })
+
+#endif // SNAILOS_WRAP_VFORK_H_
@@ -1,19 +1,48 @@
+#define _GNU_SOURCE
+
 #include <errno.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <math.h>
+#include <poll.h>
+#include <setjmp.h>
+#include <signal.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdnoreturn.h>
+#include <string.h>
+#include <time.h>
 #include <unistd.h>
 
+#include <sys/epoll.h>
+#include <sys/mount.h>
+#include <sys/select.h>
+#include <sys/stat.h>
+#include <sys/statfs.h>
 #include <sys/types.h>
+#include <sys/uio.h>
 #include <sys/wait.h>
 
 #include <emscripten.h>
+#include <wasi/api.h>
+
+#include "vfork.h"
 
 // JavaScript connectors wrappers:
 
-EM_JS(pid_t, js_getpid, (), { return OS.pid; });
+EM_JS(pid_t, js_getpid, (), { return OS.getpid(); });
 
-EM_JS(pid_t, js_getppid, (), { return OS.ppid; });
+EM_JS(pid_t, js_getppid, (), { return OS.getppid(); });
 
 EM_ASYNC_JS(pid_t, js_vfork, (), { return await OS.vfork(); });
 
+EM_JS(void, js_vfork_exit, (int status), { OS.vfork_exit(status); });
+
+EM_ASYNC_JS(int, js_execve,
+            (const char *path, char *const argv[], char *const envp[]),
+            { return await OS.execve(path, argv, envp); });
+
 EM_ASYNC_JS(pid_t, js_waitpid, (pid_t pid, int *status, int options),
             { return await OS.waitpid(pid, status, options); });
 
@@ -24,34 +53,956 @@ EM_ASYNC_JS(pid_t, js_wait4,
 EM_ASYNC_JS(ssize_t, js_read, (int fd, void *buf, size_t count),
             { return await OS.read(fd, buf, count); });
 
-EM_JS(ssize_t, js_write, (int fd, const void *buf, size_t count),
-      { return OS.write(fd, buf, count); });
+EM_ASYNC_JS(ssize_t, js_write, (int fd, const struct iovec *iov, int iovcnt),
+            { return await OS.write(fd, iov, iovcnt); });
+
+EM_JS(int, js_pipe, (int fds[2], int flags), { return OS.pipe(fds, flags); });
+
+EM_ASYNC_JS(int, js_poll, (struct pollfd * fds, nfds_t nfds, double timeout),
+            { return await OS.poll(fds, nfds, timeout); });
+
+EM_JS(int, js_epoll_create, (int flags), { return OS.epoll_create(flags); });
+
+EM_JS(int, js_epoll_ctl, (int epfd, int op, int fd, struct epoll_event *event),
+      { return OS.epoll_ctl(epfd, op, fd, event); });
+
+EM_ASYNC_JS(int, js_epoll_wait,
+            (int epfd, struct epoll_event *events, int maxevents,
+             double timeout),
+            { return await OS.epoll_wait(epfd, events, maxevents, timeout); });
+
+EM_JS(int, js_close, (int fd), { return OS.close(fd); });
+
+EM_JS(int, js_dup3, (int oldfd, int newfd, int flags),
+      { return OS.dup3(oldfd, newfd, flags); });
+
+EM_JS(int, js_fcntl, (int fd, int cmd, int arg),
+      { return OS.fcntl(fd, cmd, arg); });
+
+EM_JS(int, js_isatty, (int fd), { return OS.isatty(fd); });
+
+EM_ASYNC_JS(int, js_nanosleep, (double ms, double *rem),
+            { return await OS.nanosleep(ms, rem); });
+
+// File status as understood by the process manager, times in milliseconds.
+struct os_stat {
+  uint64_t ino;
+  uint32_t mode;
+  uint32_t nlink;
+  uint64_t size;
+  uint32_t dev;
+  uint32_t rdev;
+  uint32_t blksize;
+  uint32_t pad;
+  double atime;
+  double mtime;
+  double ctime;
+};
+
+EM_JS(int, js_open, (int dirfd, const char *path, int flags, mode_t mode),
+      { return OS.open(dirfd, path, flags, mode); });
+
+EM_JS(int, js_lseek, (int fd, off_t offset, int whence, off_t *result),
+      { return OS.lseek(fd, offset, whence, result); });
+
+EM_JS(int, js_fstat, (int fd, struct os_stat *st),
+      { return OS.fstat(fd, st); });
+
+EM_JS(int, js_stat,
+      (int dirfd, const char *path, struct os_stat *st, int flags),
+      { return OS.stat(dirfd, path, st, flags); });
+
+EM_JS(int, js_readlink, (int dirfd, const char *path, char *buf, size_t size),
+      { return OS.readlink(dirfd, path, buf, size); });
+
+EM_JS(int, js_getdents, (int fd, void *dirp, size_t count),
+      { return OS.getdents(fd, dirp, count); });
+
+EM_JS(int, js_ftruncate, (int fd, off_t length),
+      { return OS.ftruncate(fd, length); });
+
+EM_JS(int, js_unlink, (int dirfd, const char *path, int flags),
+      { return OS.unlink(dirfd, path, flags); });
+
+EM_JS(int, js_mkdir, (int dirfd, const char *path, mode_t mode),
+      { return OS.mkdir(dirfd, path, mode); });
+
+EM_JS(int, js_symlink, (const char *target, int dirfd, const char *path),
+      { return OS.symlink(target, dirfd, path); });
+
+EM_JS(int, js_rename,
+      (int olddirfd, const char *oldpath, int newdirfd, const char *newpath),
+      { return OS.rename(olddirfd, oldpath, newdirfd, newpath); });
+
+EM_JS(int, js_chdir, (int dirfd, const char *path),
+      { return OS.chdir(dirfd, path); });
+
+EM_JS(int, js_getcwd, (char *buf, size_t size),
+      { return OS.getcwd(buf, size); });
+
+EM_JS(int, js_access, (int dirfd, const char *path, int mode),
+      { return OS.access(dirfd, path, mode); });
+
+EM_JS(int, js_chmod, (int dirfd, const char *path, mode_t mode),
+      { return OS.chmod(dirfd, path, mode); });
+
+EM_JS(int, js_utimens, (int dirfd, const char *path, double atime, double mtime),
+      { return OS.utimens(dirfd, path, atime, mtime); });
+
+// Filesystem statistics as understood by the process manager.
+struct os_statfs {
+  uint32_t type;
+  uint32_t bsize;
+  uint64_t blocks;
+  uint64_t bfree;
+  uint64_t files;
+  uint64_t ffree;
+  uint32_t namelen;
+  uint32_t flags;
+};
+
+EM_JS(int, js_statfs, (int dirfd, const char *path, struct os_statfs *buf),
+      { return OS.statfs(dirfd, path, buf); });
+
+EM_JS(int, js_mount,
+      (const char *source, const char *target, const char *fstype,
+       unsigned long flags, const char *options),
+      { return OS.mount(source, target, fstype, flags, options); });
+
+EM_JS(int, js_umount, (const char *target, int flags),
+      { return OS.umount(target, flags); });
+
+// Signal action as understood by the process manager.
+struct os_sigaction {
+  uint32_t handler;
+  uint32_t flags;
+  uint64_t mask; // bit n - 1 represents signal n
+};
+
+EM_JS(int, js_kill, (pid_t pid, int sig), { return OS.kill(pid, sig); });
+
+EM_JS(int, js_sigaction,
+      (int sig, const struct os_sigaction *act, struct os_sigaction *oldact),
+      { return OS.sigaction(sig, act, oldact); });
+
+EM_JS(int, js_sigprocmask, (int how, const uint64_t *set, uint64_t *oldset),
+      { return OS.sigprocmask(how, set, oldset); });
+
+EM_JS(int, js_sigpending, (uint64_t * set), { return OS.sigpending(set); });
+
+EM_ASYNC_JS(int, js_sigsuspend, (const uint64_t *set, uint64_t *oldset),
+            { return await OS.sigsuspend(set, oldset); });
+
+EM_ASYNC_JS(int, js_signal_deliver, (struct os_sigaction * act),
+            { return await OS.signal_deliver(act); });
+
+EM_JS(void, js_sigreturn, (), { OS.sigreturn(); });
+
+EM_JS(pid_t, js_getpgid, (pid_t pid), { return OS.getpgid(pid); });
+
+EM_JS(int, js_setpgid, (pid_t pid, pid_t pgid),
+      { return OS.setpgid(pid, pgid); });
+
+EM_JS(pid_t, js_getsid, (pid_t pid), { return OS.getsid(pid); });
+
+EM_JS(pid_t, js_setsid, (), { return OS.setsid(); });
+
+EM_JS(pid_t, js_tcgetpgrp, (), { return OS.tcgetpgrp(); });
+
+EM_JS(int, js_tcsetpgrp, (pid_t pgrp), { return OS.tcsetpgrp(pgrp); });
+
+// Errors:
+//
+// The process manager returns errors as negative Linux errno values, which
+// differ from the module's own (WASI) numbering.
+
+static const int linux_errno[] = {
+    [1] = EPERM, [2] = ENOENT, [3] = ESRCH, [4] = EINTR, [5] = EIO, [6] = ENXIO,
+    [7] = E2BIG, [8] = ENOEXEC, [9] = EBADF, [10] = ECHILD, [11] = EAGAIN,
+    [12] = ENOMEM, [13] = EACCES, [14] = EFAULT, [15] = ENOTBLK, [16] = EBUSY,
+    [17] = EEXIST, [18] = EXDEV, [19] = ENODEV, [20] = ENOTDIR, [21] = EISDIR,
+    [22] = EINVAL, [23] = ENFILE, [24] = EMFILE, [25] = ENOTTY, [26] = ETXTBSY,
+    [27] = EFBIG, [28] = ENOSPC, [29] = ESPIPE, [30] = EROFS, [31] = EMLINK,
+    [32] = EPIPE, [33] = EDOM, [34] = ERANGE, [35] = EDEADLK,
+    [36] = ENAMETOOLONG, [37] = ENOLCK, [38] = ENOSYS, [39] = ENOTEMPTY,
+    [40] = ELOOP,
+};
+
+// Translates a Linux errno to the module's own.
+static int from_linux(int err) {
+  if (err > 0 && err < (int)(sizeof(linux_errno) / sizeof(*linux_errno))) {
+    return linux_errno[err];
+  }
+  return EIO;
+}
+
+// Returns the result of a syscall, setting errno if it failed.
+static int syscall_ret(int ret) {
+  if (ret < 0) {
+    errno = from_linux(-ret);
+    return -1;
+  }
+  return ret;
+}
+
+// Returns the result of a raw syscall, which is -errno if it failed.
+static int raw_ret(int ret) { return ret < 0 ? -from_linux(-ret) : ret; }
 
 // Process management:
 
+// Maximum number of nested vforked children, each running on behalf of the
+// previous one.
+#define VFORK_MAX 16
+
+// Stack of the vforked children we are running on behalf of, innermost last:
+// where their parent called vfork(), and their pid. The extra slot is where
+// vfork() calls that overflow the stack save their context, it is never jumped
+// to: __snail_vfork_child() fails.
+static struct {
+  jmp_buf jmp;
+  pid_t pid;
+} vfork_stack[VFORK_MAX + 1];
+static int vfork_depth = 0;
+
+static int handle_signals(void);
+static bool restart(int ret, bool restartable);
+
+jmp_buf *__snail_vfork_jmp(void) { return &vfork_stack[vfork_depth].jmp; }
+
+pid_t __snail_vfork_child(void) {
+  if (vfork_depth == VFORK_MAX) {
+    errno = EAGAIN;
+    return -1;
+  }
+  pid_t pid = js_vfork();
+  if (pid < 0) {
+    return syscall_ret(pid);
+  }
+  vfork_stack[vfork_depth++].pid = pid;
+  return 0;
+}
+
+pid_t __snail_vfork_parent(void) { return vfork_stack[--vfork_depth].pid; }
+
+// Resumes the parent of the innermost vforked child, once it has called
+// execve() or exited.
+static noreturn void vfork_resume(void) {
+  longjmp(vfork_stack[vfork_depth - 1].jmp, 1);
+}
+
 pid_t __wrap_getpid() { return js_getpid(); }
 
 pid_t __wrap_getppid() { return js_getppid(); }
 
-pid_t __wrap_vfork() { return js_vfork(); }
+int __wrap_execve(const char *path, char *const argv[], char *const envp[]) {
+  int ret = js_execve(path, argv, envp);
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  if (vfork_depth) {
+    // The child is now running its own image, resume the parent.
+    vfork_resume();
+  }
+  // This image has been replaced, stop executing it.
+  _Exit(0);
+}
+
+noreturn void __real__Exit(int status);
+
+noreturn void __wrap__Exit(int status) {
+  if (vfork_depth) {
+    // Only the child exits, resume the parent.
+    js_vfork_exit(status);
+    vfork_resume();
+  }
+  __real__Exit(status);
+}
 
 pid_t __wrap_waitpid(pid_t pid, int *status, int options) {
-  return js_waitpid(pid, status, options);
+  pid_t ret;
+  do {
+    ret = js_waitpid(pid, status, options);
+  } while (restart(ret, true));
+  return syscall_ret(ret);
 }
 
+pid_t __wrap_getpgid(pid_t pid) { return syscall_ret(js_getpgid(pid)); }
+
+pid_t __wrap_getpgrp(void) { return js_getpgid(0); }
+
+int __wrap_setpgid(pid_t pid, pid_t pgid) {
+  if (pid < 0 || pgid < 0) {
+    errno = EINVAL;
+    return -1;
+  }
+  return syscall_ret(js_setpgid(pid, pgid));
+}
+
+pid_t __wrap_getsid(pid_t pid) { return syscall_ret(js_getsid(pid)); }
+
+pid_t __wrap_setsid(void) { return syscall_ret(js_setsid()); }
+
 pid_t __wrap___syscall_wait4(pid_t pid, int *status, int options,
                              struct rusage *rusage) {
-  // TODO: It is probably a better idea to only mock waitpid,
-  // which is likely the thing that ends up calling the wait4 syscall.
-  return js_wait4(pid, status, options, rusage);
+  // Raw syscalls return -errno, the libc wrapper sets errno.
+  pid_t ret;
+  do {
+    ret = js_wait4(pid, status, options, rusage);
+  } while (restart(ret, true));
+  return raw_ret(ret);
 }
 
 // I/O:
+//
+// File descriptors belong to the process manager. They refer to channels
+// (pipes and the terminal), files and devices in its filesystem, or epoll
+// instances.
+//
+// libc also does I/O through raw syscalls and WASI rather than the functions
+// below, so those are wrapped as well and the Emscripten FS is never used.
+
+// open() and fopen() end up here.
+int __wrap___syscall_openat(int dirfd, const char *path, int flags, ...) {
+  mode_t mode = 0;
+  if (flags & O_CREAT) {
+    va_list ap;
+    va_start(ap, flags);
+    mode = va_arg(ap, mode_t);
+    va_end(ap);
+  }
+  return raw_ret(js_open(dirfd, path, flags, mode));
+}
+
+// All reads end up here, including those done by stdio through WASI.
+static ssize_t fd_read(int fd, void *buf, size_t count) {
+  ssize_t ret;
+  do {
+    ret = js_read(fd, buf, count);
+  } while (restart(ret, true));
+  return syscall_ret(ret);
+}
 
 ssize_t __wrap_read(int fd, void *buf, size_t count) {
-  return js_read(fd, buf, count);
+  return fd_read(fd, buf, count);
+}
+
+// Only fills the first non-empty buffer, as reads may always come up short.
+__wasi_errno_t __wrap___wasi_fd_read(__wasi_fd_t fd, const __wasi_iovec_t *iovs,
+                                     size_t iovs_len, __wasi_size_t *nread) {
+  size_t i = 0;
+  while (i + 1 < iovs_len && iovs[i].buf_len == 0) {
+    i++;
+  }
+  ssize_t ret = iovs_len ? fd_read(fd, iovs[i].buf, iovs[i].buf_len) : 0;
+  if (ret < 0) {
+    return errno;
+  }
+  *nread = ret;
+  return __WASI_ERRNO_SUCCESS;
+}
+
+// All writes end up here, including those done by stdio through WASI.
+static ssize_t fd_write(int fd, const struct iovec *iov, int iovcnt) {
+  ssize_t ret;
+  // Writing to a broken pipe raises SIGPIPE.
+  do {
+    ret = js_write(fd, iov, iovcnt);
+  } while (restart(ret, true));
+  return syscall_ret(ret);
 }
 
 ssize_t __wrap_write(int fd, const void *buf, size_t count) {
-  return js_write(fd, buf, count);
+  struct iovec iov = {.iov_base = (void *)buf, .iov_len = count};
+  return fd_write(fd, &iov, 1);
+}
+
+ssize_t __wrap_writev(int fd, const struct iovec *iov, int iovcnt) {
+  return fd_write(fd, iov, iovcnt);
+}
+
+// printf() and friends write through WASI rather than write().
+__wasi_errno_t __wrap___wasi_fd_write(__wasi_fd_t fd,
+                                      const __wasi_ciovec_t *iovs,
+                                      size_t iovs_len,
+                                      __wasi_size_t *nwritten) {
+  ssize_t ret = fd_write(fd, (const struct iovec *)iovs, iovs_len);
+  if (ret < 0) {
+    return errno;
+  }
+  *nwritten = ret;
+  return __WASI_ERRNO_SUCCESS;
+}
+
+static off_t fd_seek(int fd, off_t offset, int whence) {
+  off_t result;
+  int ret = js_lseek(fd, offset, whence, &result);
+  return ret < 0 ? syscall_ret(ret) : result;
+}
+
+off_t __wrap_lseek(int fd, off_t offset, int whence) {
+  return fd_seek(fd, offset, whence);
+}
+
+// stdio seeks through WASI rather than lseek().
+__wasi_errno_t __wrap___wasi_fd_seek(__wasi_fd_t fd, __wasi_filedelta_t offset,
+                                     __wasi_whence_t whence,
+                                     __wasi_filesize_t *newoffset) {
+  off_t ret = fd_seek(fd, offset, whence);
+  if (ret < 0) {
+    return errno;
+  }
+  *newoffset = ret;
+  return __WASI_ERRNO_SUCCESS;
+}
+
+static struct timespec to_timespec(double ms) {
+  struct timespec ts = {.tv_sec = ms / 1e3};
+  ts.tv_nsec = (ms - ts.tv_sec * 1e3) * 1e6;
+  return ts;
+}
+
+static void to_stat(const struct os_stat *os, struct stat *st) {
+  memset(st, 0, sizeof(*st));
+  st->st_dev = os->dev;
+  st->st_ino = os->ino;
+  st->st_mode = os->mode;
+  st->st_nlink = os->nlink;
+  st->st_size = os->size;
+  st->st_rdev = os->rdev;
+  st->st_blksize = os->blksize;
+  st->st_blocks = (os->size + 511) / 512;
+  st->st_atim = to_timespec(os->atime);
+  st->st_mtim = to_timespec(os->mtime);
+  st->st_ctim = to_timespec(os->ctime);
+}
+
+// fstat() ends up here.
+int __wrap___syscall_fstat64(int fd, struct stat *st) {
+  struct os_stat os;
+  int ret = js_fstat(fd, &os);
+  if (ret == 0) {
+    to_stat(&os, st);
+  }
+  return raw_ret(ret);
+}
+
+int __wrap_isatty(int fd) {
+  int ret = js_isatty(fd);
+  if (ret <= 0) {
+    errno = ret < 0 ? from_linux(-ret) : ENOTTY;
+    return 0;
+  }
+  return 1;
+}
+
+// readdir() ends up here.
+int __wrap___syscall_getdents64(int fd, void *dirp, size_t count) {
+  return raw_ret(js_getdents(fd, dirp, count));
+}
+
+int __wrap___syscall_ftruncate64(int fd, off_t length) {
+  return raw_ret(js_ftruncate(fd, length));
+}
+
+int __wrap_pipe2(int fds[2], int flags) {
+  return syscall_ret(js_pipe(fds, flags));
+}
+
+int __wrap_pipe(int fds[2]) { return __wrap_pipe2(fds, 0); }
+
+int __wrap_dup(int fd) { return syscall_ret(js_fcntl(fd, F_DUPFD, 0)); }
+
+int __wrap_dup3(int oldfd, int newfd, int flags) {
+  return syscall_ret(js_dup3(oldfd, newfd, flags));
+}
+
+int __wrap_dup2(int oldfd, int newfd) {
+  if (oldfd == newfd) {
+    // Nothing to do, but oldfd still has to be valid.
+    int ret = js_fcntl(oldfd, F_GETFD, 0);
+    return syscall_ret(ret < 0 ? ret : newfd);
+  }
+  return __wrap_dup3(oldfd, newfd, 0);
+}
+
+int __wrap_fcntl(int fd, int cmd, ...) {
+  va_list ap;
+  va_start(ap, cmd);
+  int arg = va_arg(ap, int);
+  va_end(ap);
+
+  switch (cmd) {
+  case F_DUPFD:
+  case F_DUPFD_CLOEXEC:
+  case F_GETFD:
+  case F_SETFD:
+  case F_GETFL:
+  case F_SETFL:
+  case F_GETPIPE_SZ:
+  case F_SETPIPE_SZ:
+    return syscall_ret(js_fcntl(fd, cmd, arg));
+  }
+
+  // Other commands (e.g. locks) are not supported.
+  errno = EINVAL;
+  return -1;
+}
+
+int __wrap_close(int fd) { return syscall_ret(js_close(fd)); }
+
+// fclose() closes through WASI rather than close().
+__wasi_errno_t __wrap___wasi_fd_close(__wasi_fd_t fd) {
+  return __wrap_close(fd) < 0 ? errno : __WASI_ERRNO_SUCCESS;
+}
+
+// Filesystem:
+//
+// Paths are resolved by the process manager, which keeps track of the working
+// directory of each process.
+
+static int stat_at(int dirfd, const char *path, struct stat *st, int flags) {
+  struct os_stat os;
+  int ret = js_stat(dirfd, path, &os, flags);
+  if (ret == 0) {
+    to_stat(&os, st);
+  }
+  return raw_ret(ret);
+}
+
+int __wrap___syscall_stat64(const char *path, struct stat *st) {
+  return stat_at(AT_FDCWD, path, st, 0);
+}
+
+int __wrap___syscall_lstat64(const char *path, struct stat *st) {
+  return stat_at(AT_FDCWD, path, st, AT_SYMLINK_NOFOLLOW);
+}
+
+int __wrap___syscall_newfstatat(int dirfd, const char *path, struct stat *st,
+                                int flags) {
+  if ((flags & AT_EMPTY_PATH) && !*path) {
+    return __wrap___syscall_fstat64(dirfd, st);
+  }
+  return stat_at(dirfd, path, st, flags);
+}
+
+int __wrap___syscall_readlinkat(int dirfd, const char *path, char *buf,
+                                size_t bufsize) {
+  return raw_ret(js_readlink(dirfd, path, buf, bufsize));
+}
+
+int __wrap___syscall_unlinkat(int dirfd, const char *path, int flags) {
+  return raw_ret(js_unlink(dirfd, path, flags));
+}
+
+int __wrap___syscall_rmdir(const char *path) {
+  return __wrap___syscall_unlinkat(AT_FDCWD, path, AT_REMOVEDIR);
+}
+
+int __wrap___syscall_mkdirat(int dirfd, const char *path, mode_t mode) {
+  return raw_ret(js_mkdir(dirfd, path, mode));
+}
+
+int __wrap___syscall_symlinkat(const char *target, int dirfd,
+                               const char *path) {
+  return raw_ret(js_symlink(target, dirfd, path));
+}
+
+int __wrap___syscall_renameat(int olddirfd, const char *oldpath, int newdirfd,
+                              const char *newpath) {
+  return raw_ret(js_rename(olddirfd, oldpath, newdirfd, newpath));
+}
+
+int __wrap___syscall_chdir(const char *path) {
+  return raw_ret(js_chdir(AT_FDCWD, path));
+}
+
+int __wrap___syscall_fchdir(int fd) { return raw_ret(js_chdir(fd, NULL)); }
+
+int __wrap___syscall_getcwd(char *buf, size_t size) {
+  return raw_ret(js_getcwd(buf, size));
+}
+
+int __wrap___syscall_faccessat(int dirfd, const char *path, int mode,
+                               int flags) {
+  return raw_ret(js_access(dirfd, path, mode));
+}
+
+int __wrap___syscall_chmod(const char *path, mode_t mode) {
+  return raw_ret(js_chmod(AT_FDCWD, path, mode));
+}
+
+int __wrap___syscall_fchmod(int fd, mode_t mode) {
+  return raw_ret(js_chmod(fd, NULL, mode));
+}
+
+// Converts a time passed to utimensat() to milliseconds, or NaN to leave it
+// unchanged.
+static double utime_ms(const struct timespec *times, int i) {
+  struct timespec now;
+  if (!times || times[i].tv_nsec == UTIME_NOW) {
+    clock_gettime(CLOCK_REALTIME, &now);
+    return now.tv_sec * 1e3 + now.tv_nsec / 1e6;
+  }
+  if (times[i].tv_nsec == UTIME_OMIT) {
+    return NAN;
+  }
+  return times[i].tv_sec * 1e3 + times[i].tv_nsec / 1e6;
+}
+
+// utimes() and futimens() end up here, the latter without a path.
+int __wrap___syscall_utimensat(int dirfd, const char *path,
+                               const struct timespec times[2], int flags) {
+  return raw_ret(
+      js_utimens(dirfd, path, utime_ms(times, 0), utime_ms(times, 1)));
+}
+
+static int to_statfs(int ret, const struct os_statfs *os, struct statfs *buf) {
+  if (ret == 0) {
+    memset(buf, 0, sizeof(*buf));
+    buf->f_type = os->type;
+    buf->f_bsize = os->bsize;
+    buf->f_frsize = os->bsize;
+    buf->f_blocks = os->blocks;
+    buf->f_bfree = os->bfree;
+    buf->f_bavail = os->bfree;
+    buf->f_files = os->files;
+    buf->f_ffree = os->ffree;
+    buf->f_namelen = os->namelen;
+    buf->f_flags = os->flags;
+  }
+  return raw_ret(ret);
+}
+
+// statfs() and statvfs() end up here.
+int __wrap___syscall_statfs64(const char *path, size_t size,
+                              struct statfs *buf) {
+  struct os_statfs os;
+  return to_statfs(js_statfs(AT_FDCWD, path, &os), &os, buf);
+}
+
+int __wrap___syscall_fstatfs64(int fd, size_t size, struct statfs *buf) {
+  struct os_statfs os;
+  return to_statfs(js_statfs(fd, NULL, &os), &os, buf);
+}
+
+// Filesystem-specific data is a string of comma-separated options, as passed
+// by mount(8).
+int __wrap_mount(const char *source, const char *target, const char *fstype,
+                 unsigned long flags, const void *data) {
+  return syscall_ret(js_mount(source, target, fstype, flags, data));
+}
+
+int __wrap_umount2(const char *target, int flags) {
+  return syscall_ret(js_umount(target, flags));
+}
+
+int __wrap_umount(const char *target) { return __wrap_umount2(target, 0); }
+
+// Multiplexing:
+
+int __wrap_poll(struct pollfd *fds, nfds_t nfds, int timeout) {
+  int ret;
+  do {
+    ret = js_poll(fds, nfds, timeout);
+  } while (restart(ret, false));
+  return syscall_ret(ret);
+}
+
+// Implemented on top of poll().
+int __wrap_select(int nfds, fd_set *restrict readfds, fd_set *restrict writefds,
+                  fd_set *restrict exceptfds, struct timeval *restrict timeout) {
+  if (nfds < 0 || nfds > FD_SETSIZE ||
+      (timeout && (timeout->tv_sec < 0 || timeout->tv_usec < 0))) {
+    errno = EINVAL;
+    return -1;
+  }
+  double ms = timeout ? timeout->tv_sec * 1000.0 + timeout->tv_usec / 1000.0
+                      : -1;
+
+  struct pollfd fds[nfds > 0 ? nfds : 1];
+  nfds_t count = 0;
+  for (int fd = 0; fd < nfds; fd++) {
+    short events = 0;
+    if (readfds && FD_ISSET(fd, readfds)) {
+      events |= POLLIN;
+    }
+    if (writefds && FD_ISSET(fd, writefds)) {
+      events |= POLLOUT;
+    }
+    if (exceptfds && FD_ISSET(fd, exceptfds)) {
+      events |= POLLPRI;
+    }
+    if (events) {
+      fds[count++] = (struct pollfd){.fd = fd, .events = events};
+    }
+  }
+
+  int ret;
+  do {
+    ret = js_poll(fds, count, ms);
+  } while (restart(ret, false));
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  for (nfds_t i = 0; i < count; i++) {
+    if (fds[i].revents & POLLNVAL) {
+      errno = EBADF;
+      return -1;
+    }
+  }
+
+  fd_set *sets[] = {readfds, writefds, exceptfds};
+  for (int i = 0; i < 3; i++) {
+    if (sets[i]) {
+      FD_ZERO(sets[i]);
+    }
+  }
+  int ready = 0;
+  for (nfds_t i = 0; i < count; i++) {
+    short events = fds[i].events, revents = fds[i].revents;
+    if (events & POLLIN && revents & (POLLIN | POLLHUP | POLLERR)) {
+      FD_SET(fds[i].fd, readfds);
+      ready++;
+    }
+    if (events & POLLOUT && revents & (POLLOUT | POLLERR)) {
+      FD_SET(fds[i].fd, writefds);
+      ready++;
+    }
+    if (events & POLLPRI && revents & POLLPRI) {
+      FD_SET(fds[i].fd, exceptfds);
+      ready++;
+    }
+  }
+  return ready;
+}
+
+int __wrap_epoll_create1(int flags) {
+  return syscall_ret(js_epoll_create(flags));
+}
+
+int __wrap_epoll_create(int size) {
+  if (size <= 0) {
+    errno = EINVAL;
+    return -1;
+  }
+  return __wrap_epoll_create1(0);
+}
+
+int __wrap_epoll_ctl(int epfd, int op, int fd, struct epoll_event *event) {
+  return syscall_ret(js_epoll_ctl(epfd, op, fd, event));
+}
+
+int __wrap_epoll_wait(int epfd, struct epoll_event *events, int maxevents,
+                      int timeout) {
+  int ret;
+  do {
+    ret = js_epoll_wait(epfd, events, maxevents, timeout);
+  } while (restart(ret, false));
+  return syscall_ret(ret);
+}
+
+int __wrap_nanosleep(const struct timespec *req, struct timespec *rem) {
+  if (req->tv_sec < 0 || req->tv_nsec < 0 || req->tv_nsec >= 1000000000) {
+    errno = EINVAL;
+    return -1;
+  }
+  double remaining = req->tv_sec * 1e3 + req->tv_nsec / 1e6;
+  int ret;
+  do {
+    // Once restarted, only the remaining time is slept.
+    ret = js_nanosleep(remaining, &remaining);
+  } while (restart(ret, false));
+  if (ret < 0) {
+    if (rem) {
+      rem->tv_sec = remaining / 1e3;
+      rem->tv_nsec = (remaining - rem->tv_sec * 1e3) * 1e6;
+    }
+    return syscall_ret(ret);
+  }
+  return 0;
+}
+
+// Signals:
+//
+// Handlers run inside the module, so the process manager only keeps track of
+// the dispositions, masks and pending signals. Pending signals are delivered
+// whenever a wrapped syscall returns.
+
+static uint64_t os_sigset(const sigset_t *set) {
+  uint64_t mask = 0;
+  for (int sig = 1; sig <= 64; sig++) {
+    if (sigismember(set, sig) == 1) {
+      mask |= 1ull << (sig - 1);
+    }
+  }
+  return mask;
+}
+
+static void to_sigset(uint64_t mask, sigset_t *set) {
+  sigemptyset(set);
+  for (int sig = 1; sig <= 64; sig++) {
+    if (mask & (1ull << (sig - 1))) {
+      sigaddset(set, sig);
+    }
+  }
+}
+
+// Outcome of handle_signals(), as flags.
+#define HANDLED 1   // Handlers ran.
+#define NORESTART 2 // Some of them were installed without SA_RESTART.
+
+// Runs the handlers of pending signals, or stops running this image if a
+// signal terminated the process.
+static int handle_signals(void) {
+  struct os_sigaction act;
+  int sig, handled = 0;
+  while ((sig = js_signal_deliver(&act)) > 0) {
+    handled |= act.flags & SA_RESTART ? HANDLED : HANDLED | NORESTART;
+    if (act.flags & SA_SIGINFO) {
+      siginfo_t info = {.si_signo = sig};
+      ((void (*)(int, siginfo_t *, void *))(uintptr_t)act.handler)(sig, &info, NULL);
+    } else {
+      ((void (*)(int))(uintptr_t)act.handler)(sig);
+    }
+    js_sigreturn();
+  }
+  if (sig < 0) {
+    if (vfork_depth) {
+      // Only the child was terminated, resume the parent.
+      vfork_resume();
+    }
+    // The process is already gone, only the image is left to stop.
+    __real__Exit(128 - sig);
+  }
+  return handled;
+}
+
+// Handles pending signals, then tells whether the syscall that returned ret
+// should be called again, see signal(7).
+//
+// A syscall interrupted by a signal is restarted if no handler ran, e.g. when
+// the process was merely stopped and continued. Otherwise, only restartable
+// ones are, provided all the handlers were installed with SA_RESTART; the
+// others, like poll() or nanosleep(), fail with EINTR.
+static bool restart(int ret, bool restartable) {
+  int handled = handle_signals();
+  if (ret >= 0 || from_linux(-ret) != EINTR) {
+    return false;
+  }
+  if (!(handled & HANDLED)) {
+    return true;
+  }
+  return restartable && !(handled & NORESTART);
+}
+
+int __wrap_kill(pid_t pid, int sig) {
+  int ret = js_kill(pid, sig);
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  // The signal might have been sent to ourselves.
+  handle_signals();
+  return 0;
+}
+
+int __wrap_raise(int sig) { return __wrap_kill(js_getpid(), sig); }
+
+int __wrap_sigaction(int sig, const struct sigaction *restrict act,
+                     struct sigaction *restrict oldact) {
+  struct os_sigaction os_act, os_oldact;
+  if (act) {
+    os_act.handler = (uintptr_t)act->sa_handler;
+    os_act.flags = act->sa_flags;
+    os_act.mask = os_sigset(&act->sa_mask);
+  }
+  int ret = js_sigaction(sig, act ? &os_act : NULL, oldact ? &os_oldact : NULL);
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  if (oldact) {
+    memset(oldact, 0, sizeof(*oldact));
+    oldact->sa_handler = (void (*)(int))(uintptr_t)os_oldact.handler;
+    oldact->sa_flags = os_oldact.flags;
+    to_sigset(os_oldact.mask, &oldact->sa_mask);
+  }
+  return 0;
+}
+
+void (*__wrap_signal(int sig, void (*func)(int)))(int) {
+  struct sigaction act = {.sa_handler = func, .sa_flags = SA_RESTART}, oldact;
+  if (__wrap_sigaction(sig, &act, &oldact) < 0) {
+    return SIG_ERR;
+  }
+  return oldact.sa_handler;
+}
+
+int __wrap_sigprocmask(int how, const sigset_t *restrict set,
+                       sigset_t *restrict oldset) {
+  uint64_t os_set = set ? os_sigset(set) : 0, os_oldset;
+  int ret = js_sigprocmask(how, set ? &os_set : NULL, &os_oldset);
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  if (oldset) {
+    to_sigset(os_oldset, oldset);
+  }
+  // Unblocking might have made pending signals deliverable.
+  handle_signals();
+  return 0;
+}
+
+int __wrap_sigpending(sigset_t *set) {
+  uint64_t os_set;
+  int ret = js_sigpending(&os_set);
+  if (ret < 0) {
+    return syscall_ret(ret);
+  }
+  to_sigset(os_set, set);
+  return 0;
+}
+
+int __wrap_sigsuspend(const sigset_t *mask) {
+  uint64_t os_mask = os_sigset(mask), os_oldmask;
+  js_sigsuspend(&os_mask, &os_oldmask);
+  // Handlers run with the temporary mask, then the old one is restored.
+  handle_signals();
+  js_sigprocmask(SIG_SETMASK, &os_oldmask, NULL);
+  handle_signals();
+  errno = EINTR;
+  return -1;
+}
+
+int __wrap_pause(void) {
+  uint64_t os_mask;
+  js_sigprocmask(SIG_BLOCK, NULL, &os_mask);
+  sigset_t mask;
+  to_sigset(os_mask, &mask);
+  return __wrap_sigsuspend(&mask);
+}
+
+// Job control:
+//
+// There is a single terminal, so the fd only needs to refer to a terminal.
+
+pid_t __wrap_tcgetpgrp(int fd) {
+  if (!isatty(fd)) {
+    return -1; // errno set by isatty()
+  }
+  return syscall_ret(js_tcgetpgrp());
+}
+
+int __wrap_tcsetpgrp(int fd, pid_t pgrp) {
+  if (!isatty(fd)) {
+    return -1; // errno set by isatty()
+  }
+  if (pgrp < 0) {
+    errno = EINVAL;
+    return -1;
+  }
+  int ret;
+  // Background processes get SIGTTOU, and try again once continued.
+  do {
+    ret = js_tcsetpgrp(pgrp);
+  } while (restart(ret, true));
+  return syscall_ret(ret);
 }